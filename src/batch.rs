@@ -0,0 +1,436 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use futures::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+
+use crate::client::AihordeClient;
+use crate::errors::AihordeError;
+use crate::images::OutputFormat;
+use crate::models::{GenerationInputStable, ModelGenerationInputStable, RequestStatusStable};
+use crate::queue::JobQueue;
+
+/// Name of the manifest in the output directory of a [`Batch`].
+pub const MANIFEST_FILE: &str = "manifest.jsonl";
+
+/// A generated image, as recorded in the manifest of a [`Batch`].
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Identifies the input, so it is skipped when the batch is run again.
+    pub key: String,
+
+    /// The position of the input in the batch.
+    pub index: usize,
+
+    pub prompt: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_name: Option<String>,
+
+    /// The kudos of the request, split evenly between its images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kudos: Option<f64>,
+
+    /// The saved image, relative to the output directory.
+    pub path: PathBuf,
+}
+
+/// An input of a [`Batch`] which could not be generated.
+#[derive(Debug)]
+pub struct BatchFailure {
+    pub index: usize,
+    pub input: GenerationInputStable,
+    pub error: AihordeError,
+}
+
+/// What a run of a [`Batch`] did.
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    /// Inputs which were already in the manifest.
+    pub skipped: usize,
+
+    /// Inputs which were generated and saved in this run.
+    pub completed: usize,
+
+    /// The images saved in this run.
+    pub entries: Vec<ManifestEntry>,
+
+    /// Inputs which failed. They are generated again when the batch is run again.
+    pub failed: Vec<BatchFailure>,
+}
+
+/// Generates a list of inputs into a directory, along with a JSON-lines manifest of the saved images.
+///
+/// Inputs are identified by their content, so running the same batch again only generates
+/// the inputs which are not in the manifest yet.
+#[derive(Debug, Clone)]
+pub struct Batch {
+    client: AihordeClient,
+    queue: JobQueue,
+    output_dir: PathBuf,
+    format: OutputFormat,
+}
+
+impl Batch {
+    /// ### Create a new batch
+    /// #### Arguments
+    /// * `client` - The client to submit the generations with.
+    /// * `output_dir` - The directory to save the images and the manifest in.
+    pub fn new(client: AihordeClient, output_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            queue: JobQueue::new(client.clone()),
+            client,
+            output_dir: output_dir.into(),
+            format: OutputFormat::default(),
+        }
+    }
+
+    /// ### Keep at most `concurrency` requests in flight
+    /// By default, the concurrency is detected with `find_user`.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.queue = self.queue.with_concurrency(concurrency);
+        self
+    }
+
+    /// ### Change how often running requests are checked
+    pub fn with_poll_interval(
+        mut self,
+        poll_interval: Duration,
+        max_poll_interval: Duration,
+    ) -> Self {
+        self.queue = self
+            .queue
+            .with_poll_interval(poll_interval, max_poll_interval);
+        self
+    }
+
+    /// ### Change how many times a faulted request is submitted
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.queue = self.queue.with_max_attempts(max_attempts);
+        self
+    }
+
+    /// ### Change the format the images are saved in
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The manifest of this batch.
+    pub fn manifest_path(&self) -> PathBuf {
+        self.output_dir.join(MANIFEST_FILE)
+    }
+
+    /// ### The images recorded in the manifest
+    pub async fn manifest(&self) -> Result<Vec<ManifestEntry>, AihordeError> {
+        let content = match tokio::fs::read_to_string(self.manifest_path()).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                // A crash may leave a truncated last line behind
+                Err(e) => warn!("Skipping line {} of the manifest: {e}", number + 1),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// ### Generate the inputs which are not in the manifest yet
+    /// Every image is saved and recorded in the manifest as soon as its request is done,
+    /// so an interrupted run loses at most the running requests.
+    pub async fn run(
+        &self,
+        inputs: Vec<GenerationInputStable>,
+    ) -> Result<BatchReport, AihordeError> {
+        tokio::fs::create_dir_all(&self.output_dir).await?;
+        let done: HashSet<String> = self
+            .manifest()
+            .await?
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        let keys = input_keys(&inputs)?;
+
+        let mut report = BatchReport::default();
+        let mut pending = Vec::new();
+        let mut pending_inputs = Vec::new();
+        for (index, (input, key)) in inputs.into_iter().zip(keys).enumerate() {
+            if done.contains(&key) {
+                report.skipped += 1;
+            } else {
+                pending.push((index, key));
+                pending_inputs.push(input);
+            }
+        }
+        info!(
+            "Generating {} inputs, {} already done",
+            pending.len(),
+            report.skipped
+        );
+        if pending.is_empty() {
+            return Ok(report);
+        }
+
+        let mut manifest = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.manifest_path())
+            .await?;
+        let mut jobs = self.queue.clone().run(pending_inputs);
+        while let Some(job) = jobs.next().await {
+            let (index, key) = pending[job.index].clone();
+            let saved = match job.result {
+                Ok(status) => self.save(index, &key, &job.input, &status).await,
+                Err(e) => Err(e),
+            };
+            match saved {
+                Ok(entries) => {
+                    for entry in &entries {
+                        let mut line = serde_json::to_string(entry)?;
+                        line.push('\n');
+                        manifest.write_all(line.as_bytes()).await?;
+                    }
+                    manifest.flush().await?;
+                    report.completed += 1;
+                    report.entries.extend(entries);
+                }
+                Err(error) => {
+                    warn!("Input {index} failed: {error}");
+                    report.failed.push(BatchFailure {
+                        index,
+                        input: job.input,
+                        error,
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    async fn save(
+        &self,
+        index: usize,
+        key: &str,
+        input: &GenerationInputStable,
+        status: &RequestStatusStable,
+    ) -> Result<Vec<ManifestEntry>, AihordeError> {
+        let images = self.client.download_generations(status).await?;
+        let kudos = status
+            .kudos
+            .filter(|_| !images.is_empty())
+            .map(|kudos| kudos as f64 / images.len() as f64);
+        let mut entries = Vec::new();
+        for (number, image) in images.into_iter().enumerate() {
+            let path = PathBuf::from(format!(
+                "{index:05}_{key}_{number}.{}",
+                self.format.extension()
+            ));
+            let image = image.with_parameters(input);
+            image
+                .save_as(self.output_dir.join(&path), self.format)
+                .await?;
+            entries.push(ManifestEntry {
+                key: key.to_string(),
+                index,
+                prompt: input.prompt.clone(),
+                seed: image.generation.seed.clone(),
+                model: image.generation.model.clone(),
+                worker_id: image.generation.worker_id.clone(),
+                worker_name: image.generation.worker_name.clone(),
+                kudos,
+                path,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Content-based keys of the inputs, stable across runs and processes.
+/// Identical inputs are told apart by how often they occurred before.
+pub(crate) fn input_keys(inputs: &[GenerationInputStable]) -> Result<Vec<String>, AihordeError> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    inputs
+        .iter()
+        .map(|input| {
+            // Sort the keys of objects, since those of the `special` map come in random order
+            let mut value = serde_json::to_value(input)?;
+            value.sort_all_objects();
+            let json = serde_json::to_vec(&value)?;
+            let digest = Sha256::digest(&json);
+            let hash: String = digest[..16]
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            let occurrence = seen.entry(hash.clone()).or_default();
+            *occurrence += 1;
+            Ok(match *occurrence {
+                1 => hash,
+                occurrence => format!("{hash}-{occurrence}"),
+            })
+        })
+        .collect()
+}
+
+/// ### Read batch inputs from a file
+/// Files ending in `.csv` are parsed with [`parse_csv`], anything else with [`parse_jsonl`].
+pub async fn read_inputs(
+    path: impl AsRef<Path>,
+) -> Result<Vec<GenerationInputStable>, AihordeError> {
+    let path = path.as_ref();
+    let content = tokio::fs::read_to_string(path).await?;
+    let is_csv = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        parse_csv(&content)
+    } else {
+        parse_jsonl(&content)
+    }
+}
+
+/// ### Parse one `GenerationInputStable` per line
+/// Empty lines are ignored.
+pub fn parse_jsonl(content: &str) -> Result<Vec<GenerationInputStable>, AihordeError> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(number, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| AihordeError::InvalidInput(format!("Line {}: {e}", number + 1)))
+        })
+        .collect()
+}
+
+/// ### Parse a CSV file with a header row
+/// The `prompt` column is required. The other supported columns are `negative_prompt`, `models`
+/// (separated by `|`), `steps`, `n`, `width`, `height`, `cfg_scale`, `seed`, `sampler_name`,
+/// `karras`, `clip_skip` and `nsfw`. Empty cells are left unset.
+pub fn parse_csv(content: &str) -> Result<Vec<GenerationInputStable>, AihordeError> {
+    let mut rows = csv_rows(content)?.into_iter();
+    let Some(header) = rows.next() else {
+        return Ok(Vec::new());
+    };
+    let header: Vec<String> = header
+        .iter()
+        .map(|column| column.trim().to_ascii_lowercase())
+        .collect();
+    if !header.iter().any(|column| column == "prompt") {
+        return Err(AihordeError::InvalidInput(
+            "The CSV header has no prompt column".to_string(),
+        ));
+    }
+
+    rows.enumerate()
+        .filter(|(_, row)| row.iter().any(|cell| !cell.trim().is_empty()))
+        .map(|(number, row)| {
+            let mut input = GenerationInputStable::default();
+            let mut params = ModelGenerationInputStable::default();
+            let mut negative = None;
+            for (column, cell) in header.iter().zip(&row) {
+                let cell = cell.trim();
+                if cell.is_empty() {
+                    continue;
+                }
+                let invalid = || {
+                    AihordeError::InvalidInput(format!(
+                        "Row {}: invalid {column} `{cell}`",
+                        number + 2
+                    ))
+                };
+                match column.as_str() {
+                    "prompt" => input.prompt = cell.to_string(),
+                    "negative_prompt" => negative = Some(cell.to_string()),
+                    "models" | "model" => {
+                        input.models = Some(cell.split('|').map(|m| m.trim().to_string()).collect())
+                    }
+                    "nsfw" => input.nsfw = Some(cell.parse().map_err(|_| invalid())?),
+                    "steps" => params.steps = Some(cell.parse().map_err(|_| invalid())?),
+                    "n" => params.n = Some(cell.parse().map_err(|_| invalid())?),
+                    "width" => params.width = Some(cell.parse().map_err(|_| invalid())?),
+                    "height" => params.height = Some(cell.parse().map_err(|_| invalid())?),
+                    "cfg_scale" => params.cfg_scale = Some(cell.parse().map_err(|_| invalid())?),
+                    "clip_skip" => params.clip_skip = Some(cell.parse().map_err(|_| invalid())?),
+                    "karras" => params.karras = Some(cell.parse().map_err(|_| invalid())?),
+                    "seed" => params.seed = Some(cell.to_string()),
+                    "sampler_name" => {
+                        params.sampler_name = Some(
+                            serde_json::from_value(serde_json::Value::String(cell.to_string()))
+                                .map_err(|_| invalid())?,
+                        )
+                    }
+                    _ => {
+                        return Err(AihordeError::InvalidInput(format!(
+                            "Unknown CSV column {column}"
+                        )));
+                    }
+                }
+            }
+            if let Some(negative) = negative {
+                input.prompt = format!("{} ### {negative}", input.prompt);
+            }
+            if params != ModelGenerationInputStable::default() {
+                input.params = Some(params);
+            }
+            Ok(input)
+        })
+        .collect()
+}
+
+/// Split CSV content into rows of cells, honoring quoted cells with commas, quotes and line breaks.
+fn csv_rows(content: &str) -> Result<Vec<Vec<String>>, AihordeError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => cell.push(c),
+            (false, '"') if cell.is_empty() => quoted = true,
+            (false, ',') => row.push(std::mem::take(&mut cell)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            (false, c) => cell.push(c),
+        }
+    }
+    if quoted {
+        return Err(AihordeError::InvalidInput(
+            "Unterminated quoted CSV cell".to_string(),
+        ));
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    Ok(rows)
+}
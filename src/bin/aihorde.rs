@@ -0,0 +1,468 @@
+//! Command-line access to the AI Horde.
+//!
+//! The API key is taken from `--api-key`, the `AIHORDE_API_KEY` environment variable or the `api_key`
+//! of the config file, in that order. The config file is a JSON object read from `$AIHORDE_CONFIG`,
+//! or `aihorde/config.json` in the user's config directory.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use aihorde_rs::batch::{self, Batch};
+use aihorde_rs::consts::PKG_VERSION;
+use aihorde_rs::{
+    AihordeClient, AihordeError, GenerationInputStable, KudosTransferInput,
+    ModelGenerationInputStable, ModelState, ModelType, OutputFormat, RequestStatusCheck,
+    RequestStatusStable, SamplerName,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use url::Url;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "aihorde",
+    version,
+    about = "Generate images and manage your account on the AI Horde"
+)]
+struct Cli {
+    /// API key of your account. Anonymous when not set anywhere.
+    #[arg(long, env = "AIHORDE_API_KEY", hide_env_values = true, global = true)]
+    api_key: Option<String>,
+
+    /// Base URL of the horde API.
+    #[arg(long, env = "AIHORDE_BASE_URL", global = true)]
+    base_url: Option<Url>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Generate images and save them
+    Generate(GenerateArgs),
+    /// Generate every input of a JSON-lines or CSV file, skipping the ones already in the manifest
+    Batch {
+        /// One `GenerationInputStable` per line, or a CSV file with a `prompt` column.
+        file: PathBuf,
+
+        /// Directory to save the images and the manifest in.
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+
+        /// How many requests to keep in flight. Defaults to the concurrency of your account.
+        #[arg(long)]
+        concurrency: Option<usize>,
+
+        #[arg(long, value_enum, default_value_t = ImageFormat::Webp)]
+        format: ImageFormat,
+    },
+    /// Show the progress of a request
+    Status {
+        /// The UUID of the request.
+        id: String,
+    },
+    /// Cancel a request, saving the images finished so far
+    Cancel {
+        /// The UUID of the request.
+        id: String,
+
+        /// Directory to save the finished images in. They are discarded when not set.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Show the details of your account
+    User,
+    /// List the models served by the horde
+    Models {
+        #[arg(long = "type", value_enum, default_value_t = ModelKind::Image)]
+        model_type: ModelKind,
+
+        /// Only list models served by at least this many workers.
+        #[arg(long)]
+        min_count: Option<u64>,
+
+        /// Only list models served by at most this many workers.
+        #[arg(long)]
+        max_count: Option<u64>,
+
+        /// Include custom models.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Manage kudos
+    Kudos {
+        #[command(subcommand)]
+        command: KudosCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum KudosCommand {
+    /// Transfer kudos to another user
+    Transfer {
+        /// The receiving user, as `username#id`.
+        username: String,
+
+        amount: f64,
+    },
+}
+
+#[derive(Debug, Args)]
+struct GenerateArgs {
+    prompt: String,
+
+    /// Model to use. Repeat to allow several models.
+    #[arg(short, long = "model")]
+    models: Vec<String>,
+
+    #[arg(long)]
+    steps: Option<u16>,
+
+    /// How many images to generate.
+    #[arg(short, long)]
+    n: Option<u8>,
+
+    #[arg(long)]
+    width: Option<u16>,
+
+    #[arg(long)]
+    height: Option<u16>,
+
+    #[arg(long)]
+    cfg_scale: Option<f32>,
+
+    #[arg(long)]
+    seed: Option<String>,
+
+    /// Sampler name as used by the horde, e.g. `k_euler_a`.
+    #[arg(long, value_parser = parse_sampler)]
+    sampler: Option<SamplerName>,
+
+    /// Allow NSFW results.
+    #[arg(long)]
+    nsfw: bool,
+
+    /// Directory to save the images in.
+    #[arg(short, long, default_value = ".")]
+    output: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = ImageFormat::Webp)]
+    format: ImageFormat,
+
+    /// Seconds between progress checks.
+    #[arg(long, default_value_t = 2)]
+    poll_interval: u64,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ModelKind {
+    Image,
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ImageFormat {
+    Webp,
+    Png,
+    Jpeg,
+}
+
+impl From<ImageFormat> for OutputFormat {
+    fn from(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Webp => OutputFormat::WebP,
+            ImageFormat::Png => OutputFormat::Png,
+            ImageFormat::Jpeg => OutputFormat::Jpeg,
+        }
+    }
+}
+
+fn parse_sampler(name: &str) -> Result<SamplerName, String> {
+    match serde_json::from_value(serde_json::Value::String(name.to_string())) {
+        Ok(SamplerName::Unknown) | Err(_) => Err(format!("unknown sampler `{name}`")),
+        Ok(sampler) => Ok(sampler),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    api_key: Option<String>,
+    base_url: Option<Url>,
+}
+
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("AIHORDE_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config_dir.join("aihorde").join("config.json"))
+}
+
+fn load_config() -> Result<Config, AihordeError> {
+    let Some(path) = config_path() else {
+        return Ok(Config::default());
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), AihordeError> {
+    let config = load_config()?;
+    let client = AihordeClient::new(
+        cli.api_key.or(config.api_key),
+        cli.base_url.or(config.base_url),
+        Some(format!(
+            "aihorde-cli:{PKG_VERSION}:https://github.com/lapismyt/aihorde-rs"
+        )),
+    );
+    match cli.command {
+        Command::Generate(args) => generate(&client, args).await,
+        Command::Batch {
+            file,
+            output,
+            concurrency,
+            format,
+        } => {
+            let inputs = batch::read_inputs(&file).await?;
+            let mut batch = Batch::new(client, &output).with_format(format.into());
+            if let Some(concurrency) = concurrency {
+                batch = batch.with_concurrency(concurrency);
+            }
+            let report = batch.run(inputs).await?;
+            for failure in &report.failed {
+                eprintln!("input {} failed: {}", failure.index + 1, failure.error);
+            }
+            println!(
+                "{} generated, {} already done, {} failed, manifest in {}",
+                report.completed,
+                report.skipped,
+                report.failed.len(),
+                batch.manifest_path().display()
+            );
+            if report.failed.is_empty() {
+                Ok(())
+            } else {
+                Err(AihordeError::Other(format!(
+                    "{} inputs failed, run the batch again to retry them",
+                    report.failed.len()
+                )))
+            }
+        }
+        Command::Status { id } => {
+            let check = client.generation_check(id).await?;
+            println!("{}", describe(&check));
+            Ok(())
+        }
+        Command::Cancel { id, output } => {
+            let status = client.generation_cancel(id.clone()).await?;
+            let finished = status.generations.as_ref().map_or(0, Vec::len);
+            println!("Cancelled {id}, {finished} images were finished");
+            if let Some(output) = output {
+                save(&client, &id, &status, None, &output, OutputFormat::WebP).await?;
+            }
+            Ok(())
+        }
+        Command::User => {
+            let user = client.find_user().await?;
+            println!("{}", user.username.as_deref().unwrap_or("<unknown>"));
+            println!("  kudos:       {}", user.kudos.unwrap_or_default());
+            println!("  concurrency: {}", user.concurrency.unwrap_or_default());
+            if let Some(active) = user
+                .active_generations
+                .as_ref()
+                .and_then(|active| active.image.as_ref())
+            {
+                println!("  active:      {}", active.len());
+            }
+            Ok(())
+        }
+        Command::Models {
+            model_type,
+            min_count,
+            max_count,
+            all,
+        } => {
+            let model_type = match model_type {
+                ModelKind::Image => ModelType::Image,
+                ModelKind::Text => ModelType::Text,
+            };
+            let state = if all {
+                ModelState::All
+            } else {
+                ModelState::Known
+            };
+            let mut models = client
+                .get_active_models(Some(model_type), min_count, max_count, Some(state))
+                .await?;
+            models.sort_by(|a, b| b.count.cmp(&a.count).then(a.name.cmp(&b.name)));
+            println!(
+                "{:<40} {:>7} {:>7} {:>6}",
+                "MODEL", "WORKERS", "QUEUED", "ETA"
+            );
+            for model in models {
+                println!(
+                    "{:<40} {:>7} {:>7.0} {:>5}s",
+                    model.name.unwrap_or_default(),
+                    model.count.unwrap_or_default(),
+                    model.queued.unwrap_or_default(),
+                    model.eta.unwrap_or_default()
+                );
+            }
+            Ok(())
+        }
+        Command::Kudos {
+            command: KudosCommand::Transfer { username, amount },
+        } => {
+            let transferred = client
+                .transfer_kudos(KudosTransferInput {
+                    username: username.clone(),
+                    amount,
+                })
+                .await?;
+            println!(
+                "Transferred {} kudos to {username}",
+                transferred.transferred.unwrap_or(amount)
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn generate(client: &AihordeClient, args: GenerateArgs) -> Result<(), AihordeError> {
+    let input = GenerationInputStable {
+        prompt: args.prompt,
+        params: Some(ModelGenerationInputStable {
+            steps: args.steps,
+            n: args.n,
+            width: args.width,
+            height: args.height,
+            cfg_scale: args.cfg_scale,
+            seed: args.seed,
+            sampler_name: args.sampler,
+            ..Default::default()
+        }),
+        nsfw: Some(args.nsfw),
+        models: (!args.models.is_empty()).then_some(args.models),
+        ..Default::default()
+    };
+    let request = client.generate_async(input.clone()).await?;
+    let id = request.id.ok_or_else(|| {
+        AihordeError::UnexpectedResponse("Request was accepted without an ID".to_string())
+    })?;
+    eprintln!(
+        "Submitted {id} for {} kudos, cancel it with `aihorde cancel {id}`",
+        request.kudos.unwrap_or_default()
+    );
+
+    let total = args.n.unwrap_or(1);
+    let mut previous = 0;
+    loop {
+        let check = client.generation_check(id.clone()).await?;
+        let bar = progress_bar(&check, total);
+        // Pad with spaces to erase the rest of a longer previous line
+        eprint!("\r{bar:<previous$}");
+        previous = bar.len();
+        let _ = std::io::stderr().flush();
+        if check.faulted == Some(true) {
+            eprintln!();
+            return Err(AihordeError::RequestFaulted { id });
+        }
+        if check.done == Some(true) {
+            eprintln!();
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(args.poll_interval.max(1))).await;
+    }
+
+    let status = client.generation_status(id.clone()).await?;
+    save(
+        client,
+        &id,
+        &status,
+        Some(&input),
+        &args.output,
+        args.format.into(),
+    )
+    .await
+}
+
+async fn save(
+    client: &AihordeClient,
+    id: &str,
+    status: &RequestStatusStable,
+    input: Option<&GenerationInputStable>,
+    output: &Path,
+    format: OutputFormat,
+) -> Result<(), AihordeError> {
+    tokio::fs::create_dir_all(output).await?;
+    for (index, image) in client
+        .download_generations(status)
+        .await?
+        .into_iter()
+        .enumerate()
+    {
+        let image = match input {
+            Some(input) => image.with_parameters(input),
+            None => image,
+        };
+        let path = output.join(format!("{id}_{index}.{}", format.extension()));
+        image.save_as(&path, format).await?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+const BAR_WIDTH: usize = 30;
+
+fn progress_bar(check: &RequestStatusCheck, total: u8) -> String {
+    let total = total.max(1);
+    let finished = check.finished.unwrap_or_default().min(total);
+    let filled = BAR_WIDTH * finished as usize / total as usize;
+    format!(
+        "[{}{}] {finished}/{total} | {}",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        describe(check)
+    )
+}
+
+fn describe(check: &RequestStatusCheck) -> String {
+    let state = if check.faulted == Some(true) {
+        "faulted"
+    } else if check.done == Some(true) {
+        "done"
+    } else if check.is_possible == Some(false) {
+        "no worker can fulfil it"
+    } else {
+        "running"
+    };
+    format!(
+        "{state}, {} finished, {} processing, {} waiting, queue position {}, ~{}s left",
+        check.finished.unwrap_or_default(),
+        check.processing.unwrap_or_default(),
+        check.waiting.unwrap_or_default(),
+        check.queue_position.unwrap_or_default(),
+        check.wait_time.unwrap_or_default()
+    )
+}
@@ -0,0 +1,314 @@
+//! A synchronous facade over [`crate::AihordeClient`], for programs without an async runtime.
+//!
+//! Every call runs the async client on an internal single-threaded runtime, so the blocking client
+//! must not be used from within an async context. Use `tokio::task::spawn_blocking` there.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::runtime::{Builder, Runtime};
+use url::Url;
+
+use crate::cache::{CacheConfig, ResponseCache};
+use crate::client;
+use crate::enums::{ModelState, ModelType, UserSort};
+use crate::errors::AihordeError;
+use crate::images::DownloadedImage;
+use crate::middleware::Middleware;
+use crate::models::{
+    ActiveModel, GenerationInputStable, GenerationPayloadKobold, GenerationPayloadStable,
+    GenerationSubmitted, InterrogationPopInput, InterrogationPopPayload, InterrogationSubmitInput,
+    KudosTransferInput, KudosTransferred, PopInputKobold, PopInputStable, RequestAsync,
+    RequestStatusCheck, RequestStatusStable, SubmitInputKobold, SubmitInputStable, UserDetails,
+};
+use crate::users::UsersStream;
+
+/// The blocking counterpart of [`crate::AihordeClient`], with the same methods.
+#[derive(Debug, Clone)]
+pub struct AihordeClient {
+    inner: client::AihordeClient,
+    runtime: Arc<Runtime>,
+}
+
+impl AihordeClient {
+    /// ### Create a new blocking AihordeClient instance
+    /// #### Arguments
+    /// * `api_key` - The API Key corresponding to a registered user.
+    /// * `base_url` - The base URL of the AI Horde to connect to.
+    /// * `client_agent` - The client name and version.
+    pub fn new(
+        api_key: Option<String>,
+        base_url: Option<Url>,
+        client_agent: Option<String>,
+    ) -> Result<Self, AihordeError> {
+        Self::from_async(client::AihordeClient::new(api_key, base_url, client_agent))
+    }
+
+    /// ### Wrap an async client
+    pub fn from_async(inner: client::AihordeClient) -> Result<Self, AihordeError> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        Ok(Self {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// The async client this client wraps.
+    pub fn inner(&self) -> &client::AihordeClient {
+        &self.inner
+    }
+
+    fn block_on<T>(&self, future: impl Future<Output = T>) -> T {
+        self.runtime.block_on(future)
+    }
+
+    /// ### Create a client using another API key
+    /// The runtime and HTTP connection pool are shared with this client.
+    pub fn with_api_key(&self, api_key: impl Into<String>) -> Self {
+        Self {
+            inner: self.inner.with_api_key(api_key),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    /// The API key this client authenticates with.
+    pub fn api_key(&self) -> &str {
+        self.inner.api_key()
+    }
+
+    /// ### Cache the responses of slow-changing endpoints
+    /// See [`client::AihordeClient::with_cache`].
+    pub fn with_cache(self, config: CacheConfig) -> Self {
+        Self {
+            inner: self.inner.with_cache(config),
+            runtime: self.runtime,
+        }
+    }
+
+    /// ### Run a middleware around every request
+    /// See [`client::AihordeClient::with_middleware`].
+    pub fn with_middleware(self, middleware: impl Middleware + 'static) -> Self {
+        Self {
+            inner: self.inner.with_middleware(middleware),
+            runtime: self.runtime,
+        }
+    }
+
+    /// ### Create a client that bypasses the cache
+    pub fn without_cache(&self) -> Self {
+        Self {
+            inner: self.inner.without_cache(),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    /// The response cache of this client, if it has one.
+    pub fn cache(&self) -> Option<&ResponseCache> {
+        self.inner.cache()
+    }
+
+    /// ### Lookup user based on their API key
+    pub fn find_user(&self) -> Result<UserDetails, AihordeError> {
+        self.block_on(self.inner.find_user())
+    }
+
+    /// ### Get user details by ID
+    pub fn get_user(&self, user_id: String) -> Result<UserDetails, AihordeError> {
+        self.block_on(self.inner.get_user(user_id))
+    }
+
+    /// ### Get a list of users
+    pub fn get_users(
+        &self,
+        page: u32,
+        sort: Option<String>,
+    ) -> Result<Vec<UserDetails>, AihordeError> {
+        self.block_on(self.inner.get_users(page, sort))
+    }
+
+    /// ### Get a list of users
+    pub fn get_users_sorted(
+        &self,
+        page: u32,
+        sort: UserSort,
+    ) -> Result<Vec<UserDetails>, AihordeError> {
+        self.block_on(self.inner.get_users_sorted(page, sort))
+    }
+
+    /// ### Iterate over the users of all pages
+    /// See [`UsersIter`] for concurrency and stop conditions.
+    pub fn users_stream(&self, sort: UserSort) -> UsersIter {
+        UsersIter {
+            stream: self.inner.users_stream(sort),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    /// ### Initiate an Asynchronous request to generate images
+    pub fn generate_async(
+        &self,
+        generation_input: GenerationInputStable,
+    ) -> Result<RequestAsync, AihordeError> {
+        self.block_on(self.inner.generate_async(generation_input))
+    }
+
+    /// ### Retrieve the status of an Asynchronous generation request without images
+    pub fn generation_check(&self, request_id: String) -> Result<RequestStatusCheck, AihordeError> {
+        self.block_on(self.inner.generation_check(request_id))
+    }
+
+    /// ### Submit an image generation and wait until it is done
+    pub fn generate_and_wait(
+        &self,
+        generation_input: GenerationInputStable,
+        poll_interval: Duration,
+    ) -> Result<RequestStatusStable, AihordeError> {
+        self.block_on(
+            self.inner
+                .generate_and_wait(generation_input, poll_interval),
+        )
+    }
+
+    /// ### Retrieve the full status of an Asynchronous generation request
+    pub fn generation_status(
+        &self,
+        request_id: String,
+    ) -> Result<RequestStatusStable, AihordeError> {
+        self.block_on(self.inner.generation_status(request_id))
+    }
+
+    /// ### Cancel an unfinished request
+    pub fn generation_cancel(
+        &self,
+        request_id: String,
+    ) -> Result<RequestStatusStable, AihordeError> {
+        self.block_on(self.inner.generation_cancel(request_id))
+    }
+
+    /// ### Transfer kudos to another user
+    pub fn transfer_kudos(
+        &self,
+        transfer_input: KudosTransferInput,
+    ) -> Result<KudosTransferred, AihordeError> {
+        self.block_on(self.inner.transfer_kudos(transfer_input))
+    }
+
+    /// ### Returns a list of models active currently in this horde
+    pub fn get_active_models(
+        &self,
+        model_type: Option<ModelType>,
+        min_count: Option<u64>,
+        max_count: Option<u64>,
+        model_state: Option<ModelState>,
+    ) -> Result<Vec<ActiveModel>, AihordeError> {
+        self.block_on(
+            self.inner
+                .get_active_models(model_type, min_count, max_count, model_state),
+        )
+    }
+
+    /// ### Check if there are generation requests queued for fulfillment
+    pub fn generate_pop(
+        &self,
+        pop_input: PopInputStable,
+    ) -> Result<GenerationPayloadStable, AihordeError> {
+        self.block_on(self.inner.generate_pop(pop_input))
+    }
+
+    /// ### Submit a generated image
+    pub fn generate_submit(
+        &self,
+        submit_input: SubmitInputStable,
+    ) -> Result<GenerationSubmitted, AihordeError> {
+        self.block_on(self.inner.generate_submit(submit_input))
+    }
+
+    /// ### Check if there are text generation requests queued for fulfillment
+    pub fn generate_text_pop(
+        &self,
+        pop_input: PopInputKobold,
+    ) -> Result<GenerationPayloadKobold, AihordeError> {
+        self.block_on(self.inner.generate_text_pop(pop_input))
+    }
+
+    /// ### Submit generated text
+    pub fn generate_text_submit(
+        &self,
+        submit_input: SubmitInputKobold,
+    ) -> Result<GenerationSubmitted, AihordeError> {
+        self.block_on(self.inner.generate_text_submit(submit_input))
+    }
+
+    /// ### Check if there are interrogation forms queued for fulfillment
+    pub fn interrogate_pop(
+        &self,
+        pop_input: InterrogationPopInput,
+    ) -> Result<InterrogationPopPayload, AihordeError> {
+        self.block_on(self.inner.interrogate_pop(pop_input))
+    }
+
+    /// ### Submit the results of an interrogation form
+    pub fn interrogate_submit(
+        &self,
+        submit_input: InterrogationSubmitInput,
+    ) -> Result<GenerationSubmitted, AihordeError> {
+        self.block_on(self.inner.interrogate_submit(submit_input))
+    }
+
+    /// ### Download all images of a generation request
+    pub fn download_generations(
+        &self,
+        status: &RequestStatusStable,
+    ) -> Result<Vec<DownloadedImage>, AihordeError> {
+        self.block_on(self.inner.download_generations(status))
+    }
+
+    /// ### Upload a generated .webp image to an r2 upload link
+    pub fn upload_r2(&self, upload_url: &str, image: Vec<u8>) -> Result<(), AihordeError> {
+        self.block_on(self.inner.upload_r2(upload_url, image))
+    }
+}
+
+/// The blocking counterpart of [`UsersStream`], as returned by [`AihordeClient::users_stream`].
+///
+/// The iterator ends after the last page, after the first error, or when a stop condition is met.
+#[derive(Debug)]
+pub struct UsersIter {
+    stream: UsersStream,
+    runtime: Arc<Runtime>,
+}
+
+impl UsersIter {
+    /// ### Fetch several pages at once
+    /// See [`UsersStream::with_concurrency`].
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.stream = self.stream.with_concurrency(concurrency);
+        self
+    }
+
+    /// ### Stop after a number of pages
+    pub fn with_max_pages(mut self, max_pages: u32) -> Self {
+        self.stream = self.stream.with_max_pages(max_pages);
+        self
+    }
+
+    /// ### Stop at the first user matching a condition
+    /// See [`UsersStream::with_stop_when`].
+    pub fn with_stop_when(
+        mut self,
+        stop_when: impl Fn(&UserDetails) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.stream = self.stream.with_stop_when(stop_when);
+        self
+    }
+}
+
+impl Iterator for UsersIter {
+    type Item = Result<UserDetails, AihordeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.stream.next())
+    }
+}
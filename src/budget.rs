@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::client::AihordeClient;
+use crate::errors::AihordeError;
+use crate::models::{GenerationInputStable, RequestAsync, RequestStatusStable};
+
+/// The most kudos a caller may spend.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Budget {
+    /// Per UTC day.
+    pub daily: Option<f64>,
+    /// Per UTC calendar month.
+    pub monthly: Option<f64>,
+}
+
+impl Budget {
+    pub fn with_daily(mut self, kudos: f64) -> Self {
+        self.daily = Some(kudos);
+        self
+    }
+
+    pub fn with_monthly(mut self, kudos: f64) -> Self {
+        self.monthly = Some(kudos);
+        self
+    }
+}
+
+/// How the cost of a request is estimated before it is submitted.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CostEstimate {
+    /// Ask the horde with a `dry_run` request, which is exact but takes a request.
+    #[default]
+    DryRun,
+    /// Compute it locally with [`estimate_kudos`].
+    Local,
+}
+
+/// ### Approximate the kudos cost of a generation
+/// This is the base formula of the horde, from resolution, steps and number of images.
+/// Post-processing, ControlNet and other extras make the real cost higher.
+/// #### Arguments
+/// * `input` - The generation to estimate.
+pub fn estimate_kudos(input: &GenerationInputStable) -> f64 {
+    let params = input.params.clone().unwrap_or_default();
+    let width = params.width.unwrap_or(512) as f64;
+    let height = params.height.unwrap_or(512) as f64;
+    let steps = params.steps.unwrap_or(30) as f64;
+    let n = params.n.unwrap_or(1) as f64;
+    let minimum = 64.0 * 64.0;
+    let resolution = ((width * height - minimum).max(0.0) / (1024.0 * 1024.0 - minimum)).powf(1.75);
+    let per_image = 0.1232 * steps + resolution * 0.1232 * steps * 8.75;
+    (per_image * 100.0).round() / 100.0 * n
+}
+
+/// A request recorded in the ledger of a [`BudgetGuard`].
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub request_id: String,
+    pub caller: String,
+    /// The requested models, or those which generated the images once finished, separated by `|`.
+    pub model: String,
+    pub submitted_at: DateTime<Utc>,
+
+    /// The estimated cost, checked against the budget.
+    pub estimate: f64,
+
+    /// The kudos the horde charged upfront, from `RequestAsync.kudos`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kudos: Option<f64>,
+
+    /// The kudos of the finished request, from `RequestStatusStable.kudos`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_kudos: Option<f64>,
+}
+
+impl LedgerEntry {
+    /// The best known cost of the request.
+    pub fn spent(&self) -> f64 {
+        self.final_kudos.or(self.kudos).unwrap_or(self.estimate)
+    }
+
+    /// The UTC day the request was submitted on.
+    pub fn day(&self) -> NaiveDate {
+        self.submitted_at.date_naive()
+    }
+}
+
+/// Kudos spent by a caller on a model in a day.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct SpendReport {
+    pub caller: String,
+    pub model: String,
+    pub day: NaiveDate,
+    pub requests: usize,
+    pub kudos: f64,
+}
+
+struct LedgerFile {
+    file: File,
+    entries: HashMap<String, LedgerEntry>,
+}
+
+/// Refuses generations over the kudos budget of their caller, and records what every caller spent.
+///
+/// The ledger is a JSON-lines file, where the last line of a request wins when it is opened.
+/// Budgets only hold against the callers' spending through this guard.
+pub struct BudgetGuard {
+    client: AihordeClient,
+    path: PathBuf,
+    estimate: CostEstimate,
+    default_budget: Budget,
+    budgets: HashMap<String, Budget>,
+    inner: Mutex<LedgerFile>,
+}
+
+impl std::fmt::Debug for BudgetGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BudgetGuard")
+            .field("path", &self.path)
+            .field("estimate", &self.estimate)
+            .field("default_budget", &self.default_budget)
+            .field("budgets", &self.budgets)
+            .finish()
+    }
+}
+
+impl BudgetGuard {
+    /// ### Open a ledger, creating it if needed
+    /// Callers have no budget until one is set.
+    /// #### Arguments
+    /// * `client` - The client to submit generations with.
+    /// * `path` - The JSON-lines file to record spending in.
+    pub async fn open(client: AihordeClient, path: impl AsRef<Path>) -> Result<Self, AihordeError> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = HashMap::new();
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => {
+                for (number, line) in content.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str::<LedgerEntry>(line) {
+                        Ok(entry) => {
+                            entries.insert(entry.request_id.clone(), entry);
+                        }
+                        // A crash may leave a truncated last line behind
+                        Err(e) => warn!("Skipping line {} of {}: {e}", number + 1, path.display()),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        debug!(
+            "Opened ledger {} with {} requests",
+            path.display(),
+            entries.len()
+        );
+        Ok(Self {
+            client,
+            path,
+            estimate: CostEstimate::default(),
+            default_budget: Budget::default(),
+            budgets: HashMap::new(),
+            inner: Mutex::new(LedgerFile { file, entries }),
+        })
+    }
+
+    /// ### Set the budget of a caller
+    pub fn with_budget(mut self, caller: impl Into<String>, budget: Budget) -> Self {
+        self.budgets.insert(caller.into(), budget);
+        self
+    }
+
+    /// ### Set the budget of callers without their own
+    pub fn with_default_budget(mut self, budget: Budget) -> Self {
+        self.default_budget = budget;
+        self
+    }
+
+    /// ### Change how costs are estimated
+    pub fn with_estimate(mut self, estimate: CostEstimate) -> Self {
+        self.estimate = estimate;
+        self
+    }
+
+    /// The file the ledger is recorded in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The budget of a caller.
+    pub fn budget(&self, caller: &str) -> Budget {
+        self.budgets
+            .get(caller)
+            .copied()
+            .unwrap_or(self.default_budget)
+    }
+
+    async fn write(&self, inner: &mut LedgerFile, entry: LedgerEntry) -> Result<(), AihordeError> {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        inner.file.write_all(line.as_bytes()).await?;
+        inner.file.flush().await?;
+        inner.entries.insert(entry.request_id.clone(), entry);
+        Ok(())
+    }
+
+    /// ### Estimate the kudos cost of a generation
+    pub async fn estimate(&self, input: &GenerationInputStable) -> Result<f64, AihordeError> {
+        match self.estimate {
+            CostEstimate::Local => Ok(estimate_kudos(input)),
+            CostEstimate::DryRun => {
+                let dry_run = GenerationInputStable {
+                    dry_run: Some(true),
+                    ..input.clone()
+                };
+                let request = self.client.generate_async(dry_run).await?;
+                request.kudos.ok_or_else(|| {
+                    AihordeError::UnexpectedResponse("Dry run returned no kudos".to_string())
+                })
+            }
+        }
+    }
+
+    /// ### The kudos a caller spent on the day and in the month of `at`
+    pub async fn spent(&self, caller: &str, at: DateTime<Utc>) -> (f64, f64) {
+        spent(&self.inner.lock().await.entries, caller, at)
+    }
+
+    /// ### Submit an image generation if the caller can afford it, and record it
+    /// Submissions are serialized, so concurrent requests of a caller cannot overspend together.
+    /// Requests with `dry_run` are passed through without being checked or recorded.
+    /// #### Arguments
+    /// * `caller` - Who the generation is charged to.
+    /// * `generation_input` - The generation to submit.
+    pub async fn generate_async(
+        &self,
+        caller: &str,
+        generation_input: GenerationInputStable,
+    ) -> Result<RequestAsync, AihordeError> {
+        if generation_input.dry_run == Some(true) {
+            return self.client.generate_async(generation_input).await;
+        }
+        let estimate = self.estimate(&generation_input).await?;
+        let mut inner = self.inner.lock().await;
+        let (daily, monthly) = spent(&inner.entries, caller, Utc::now());
+        let budget = self.budget(caller);
+        for (period, spent, limit) in [
+            ("daily", daily, budget.daily),
+            ("monthly", monthly, budget.monthly),
+        ] {
+            if let Some(limit) = limit
+                && spent + estimate > limit
+            {
+                return Err(AihordeError::BudgetExceeded {
+                    caller: caller.to_string(),
+                    period: period.to_string(),
+                    estimate,
+                    spent,
+                    limit,
+                });
+            }
+        }
+        let request = self.client.generate_async(generation_input.clone()).await?;
+        if let Some(id) = &request.id {
+            let model = generation_input
+                .models
+                .as_ref()
+                .filter(|models| !models.is_empty())
+                .map_or_else(|| "any".to_string(), |models| models.join("|"));
+            let entry = LedgerEntry {
+                request_id: id.clone(),
+                caller: caller.to_string(),
+                model,
+                submitted_at: Utc::now(),
+                estimate,
+                kudos: request.kudos,
+                final_kudos: None,
+            };
+            self.write(&mut inner, entry).await?;
+        }
+        Ok(request)
+    }
+
+    /// ### Record the final cost of a finished request
+    /// The models which generated its images replace the requested ones. Unknown requests are ignored.
+    pub async fn record_status(
+        &self,
+        request_id: &str,
+        status: &RequestStatusStable,
+    ) -> Result<(), AihordeError> {
+        let mut inner = self.inner.lock().await;
+        let Some(mut entry) = inner.entries.get(request_id).cloned() else {
+            return Ok(());
+        };
+        if let Some(kudos) = status.kudos {
+            entry.final_kudos = Some(kudos as f64);
+        }
+        let models: BTreeSet<&str> = status
+            .generations
+            .iter()
+            .flatten()
+            .filter_map(|generation| generation.model.as_deref())
+            .collect();
+        if !models.is_empty() {
+            entry.model = models.into_iter().collect::<Vec<_>>().join("|");
+        }
+        self.write(&mut inner, entry).await
+    }
+
+    /// ### Retrieve the full status of a request and record its final cost
+    pub async fn generation_status(
+        &self,
+        request_id: String,
+    ) -> Result<RequestStatusStable, AihordeError> {
+        let status = self.client.generation_status(request_id.clone()).await?;
+        if status.done == Some(true) {
+            self.record_status(&request_id, &status).await?;
+        }
+        Ok(status)
+    }
+
+    /// ### Every recorded request, oldest first
+    pub async fn entries(&self) -> Vec<LedgerEntry> {
+        let mut entries: Vec<LedgerEntry> =
+            self.inner.lock().await.entries.values().cloned().collect();
+        entries.sort_by_key(|entry| entry.submitted_at);
+        entries
+    }
+
+    /// ### The spending per caller, model and day
+    pub async fn report(&self) -> Vec<SpendReport> {
+        let mut rows: BTreeMap<(String, String, NaiveDate), SpendReport> = BTreeMap::new();
+        for entry in self.inner.lock().await.entries.values() {
+            let row = rows
+                .entry((entry.caller.clone(), entry.model.clone(), entry.day()))
+                .or_insert_with(|| SpendReport {
+                    caller: entry.caller.clone(),
+                    model: entry.model.clone(),
+                    day: entry.day(),
+                    requests: 0,
+                    kudos: 0.0,
+                });
+            row.requests += 1;
+            row.kudos += entry.spent();
+        }
+        rows.into_values().collect()
+    }
+
+    /// ### Write the report as CSV
+    /// The columns are `caller`, `model`, `day`, `requests` and `kudos`.
+    pub async fn export_csv(&self, path: impl AsRef<Path>) -> Result<(), AihordeError> {
+        let mut content = String::from("caller,model,day,requests,kudos\n");
+        for row in self.report().await {
+            content.push_str(&format!(
+                "{},{},{},{},{}\n",
+                csv_field(&row.caller),
+                csv_field(&row.model),
+                row.day,
+                row.requests,
+                row.kudos
+            ));
+        }
+        tokio::fs::write(path, content).await?;
+        Ok(())
+    }
+}
+
+fn spent(entries: &HashMap<String, LedgerEntry>, caller: &str, at: DateTime<Utc>) -> (f64, f64) {
+    let day = at.date_naive();
+    let mut daily = 0.0;
+    let mut monthly = 0.0;
+    for entry in entries.values().filter(|entry| entry.caller == caller) {
+        let entry_day = entry.day();
+        if entry_day.year() == day.year() && entry_day.month() == day.month() {
+            monthly += entry.spent();
+            if entry_day == day {
+                daily += entry.spent();
+            }
+        }
+    }
+    (daily, monthly)
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
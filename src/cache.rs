@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use url::Url;
+
+/// Endpoints whose responses an [`AihordeClient`](crate::AihordeClient) can cache.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum CachedEndpoint {
+    /// `get_active_models`
+    ActiveModels,
+    /// `get_users_sorted`
+    Users,
+    /// `get_user`
+    User,
+}
+
+/// How long the responses of each endpoint are reused.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CacheConfig {
+    ttls: HashMap<CachedEndpoint, Duration>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            ttls: HashMap::from([
+                (CachedEndpoint::ActiveModels, Duration::from_secs(60)),
+                (CachedEndpoint::Users, Duration::from_secs(300)),
+                (CachedEndpoint::User, Duration::from_secs(60)),
+            ]),
+        }
+    }
+}
+
+impl CacheConfig {
+    /// ### Change how long the responses of an endpoint are reused
+    /// With a TTL of zero, every call is revalidated with the server, which is only cheaper when it sends ETags.
+    pub fn with_ttl(mut self, endpoint: CachedEndpoint, ttl: Duration) -> Self {
+        self.ttls.insert(endpoint, ttl);
+        self
+    }
+
+    /// ### Never cache the responses of an endpoint
+    pub fn without(mut self, endpoint: CachedEndpoint) -> Self {
+        self.ttls.remove(&endpoint);
+        self
+    }
+
+    /// How long the responses of an endpoint are reused, or `None` if they are not cached.
+    pub fn ttl(&self, endpoint: CachedEndpoint) -> Option<Duration> {
+        self.ttls.get(&endpoint).copied()
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    endpoint: CachedEndpoint,
+    body: String,
+    etag: Option<String>,
+    stored: Instant,
+}
+
+/// What the cache knows about a request.
+pub(crate) enum Lookup {
+    /// The cached body can be used as it is.
+    Fresh(String),
+    /// The cached body has to be revalidated, with its ETag if the server sent one.
+    Stale(Option<String>),
+    Missing,
+}
+
+/// Responses of slow-changing endpoints, shared by every clone of a client.
+#[derive(Debug)]
+pub struct ResponseCache {
+    config: CacheConfig,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, CacheEntry>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// How many responses are cached.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// ### Forget the responses of an endpoint
+    pub fn invalidate(&self, endpoint: CachedEndpoint) {
+        self.lock().retain(|_, entry| entry.endpoint != endpoint);
+    }
+
+    /// ### Forget every response
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    pub(crate) fn lookup(&self, endpoint: CachedEndpoint, key: &str) -> Lookup {
+        let Some(ttl) = self.config.ttl(endpoint) else {
+            return Lookup::Missing;
+        };
+        match self.lock().get(key) {
+            Some(entry) if entry.stored.elapsed() < ttl => {
+                debug!("Answering {key} from the cache");
+                Lookup::Fresh(entry.body.clone())
+            }
+            Some(entry) => Lookup::Stale(entry.etag.clone()),
+            None => Lookup::Missing,
+        }
+    }
+
+    /// Mark a cached response as fresh again, after the server answered `304 Not Modified`.
+    pub(crate) fn revalidate(&self, key: &str) -> Option<String> {
+        let mut entries = self.lock();
+        let entry = entries.get_mut(key)?;
+        entry.stored = Instant::now();
+        Some(entry.body.clone())
+    }
+
+    pub(crate) fn store(
+        &self,
+        endpoint: CachedEndpoint,
+        key: String,
+        body: String,
+        etag: Option<String>,
+    ) {
+        self.lock().insert(
+            key,
+            CacheEntry {
+                endpoint,
+                body,
+                etag,
+                stored: Instant::now(),
+            },
+        );
+    }
+}
+
+/// The URL with its query parameters sorted, so the same request always has the same key.
+pub(crate) fn cache_key(url: &Url) -> String {
+    let mut key = url.clone();
+    let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    pairs.sort();
+    key.set_query(None);
+    if !pairs.is_empty() {
+        key.query_pairs_mut().extend_pairs(pairs);
+    }
+    key.to_string()
+}
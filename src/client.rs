@@ -1,20 +1,42 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+#[cfg(feature = "rt-tokio")]
+use std::time::Duration;
 
+use crate::cache::{CacheConfig, CachedEndpoint, Lookup, ResponseCache, cache_key};
 use crate::consts::{DEFAULT_API_KEY, DEFAULT_BASE_URL, PKG_VERSION};
-use crate::enums::{ModelState, ModelType};
+#[cfg(feature = "rt-tokio")]
+use crate::consts::{R2_UPLOAD_ATTEMPTS, R2_UPLOAD_BACKOFF_MS};
+use crate::enums::{ModelState, ModelType, UserSort};
 use crate::errors::AihordeError;
-use crate::models::{ActiveModel, GenerationInputStable, RequestAsync, RequestStatusCheck, RequestStatusStable, UserDetails, ValidationError};
+use crate::images::{DownloadedImage, GeneratedImage};
+use crate::middleware::{Middleware, Middlewares, RequestInfo};
+use crate::models::{
+    ActiveModel, GenerationInputStable, GenerationPayloadKobold, GenerationPayloadStable,
+    GenerationStable, GenerationSubmitted, InterrogationPopInput, InterrogationPopPayload,
+    InterrogationSubmitInput, KudosTransferInput, KudosTransferred, PopInputKobold, PopInputStable,
+    RequestAsync, RequestStatusCheck, RequestStatusStable, SubmitInputKobold, SubmitInputStable,
+    UserDetails, ValidationError,
+};
+use futures::future::try_join_all;
 use log::{debug, warn};
-use reqwest::Client;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+use reqwest::{Client, Request, RequestBuilder, Response, StatusCode};
 use serde::Deserialize;
 use url::Url;
 
+/// A client of the AI Horde API. Clones share their connections, cache and middlewares.
+///
+/// Requests go through async `reqwest`, so they have to run inside a tokio 1.x runtime.
 #[derive(Debug, Clone)]
 pub struct AihordeClient {
     api_key: String,
     base_url: Url,
     client_agent: String,
     client: Client,
+    cache: Option<Arc<ResponseCache>>,
+    middlewares: Middlewares,
 }
 
 impl Default for AihordeClient {
@@ -30,6 +52,8 @@ impl Default for AihordeClient {
                 PKG_VERSION
             ),
             client: Client::new(),
+            cache: None,
+            middlewares: Middlewares::default(),
         }
     }
 }
@@ -60,9 +84,103 @@ impl AihordeClient {
             base_url,
             client_agent,
             client,
+            cache: None,
+            middlewares: Middlewares::default(),
         }
     }
 
+    /// ### Create a client using another API key
+    /// The HTTP connection pool, base URL and client agent are shared with this client.
+    /// #### Arguments
+    /// * `api_key` - The API Key corresponding to a registered user.
+    pub fn with_api_key(&self, api_key: impl Into<String>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            ..self.clone()
+        }
+    }
+
+    /// The API key this client authenticates with.
+    pub fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    /// ### Cache the responses of slow-changing endpoints
+    /// The cache is shared by every clone of the returned client, including clones made with [`AihordeClient::with_api_key`].
+    /// Entries are kept per API key, and revalidated with the server's ETag, if it sent one, once their TTL ran out.
+    /// #### Arguments
+    /// * `config` - How long the responses of each endpoint are reused.
+    pub fn with_cache(mut self, config: CacheConfig) -> Self {
+        self.cache = Some(Arc::new(ResponseCache::new(config)));
+        self
+    }
+
+    /// ### Create a client that bypasses the cache
+    /// Every request of the returned client goes to the server, and does not update the cache either.
+    pub fn without_cache(&self) -> Self {
+        Self {
+            cache: None,
+            ..self.clone()
+        }
+    }
+
+    /// The response cache of this client, if it has one.
+    /// Use it to invalidate cached responses after changing what they describe.
+    pub fn cache(&self) -> Option<&ResponseCache> {
+        self.cache.as_deref()
+    }
+
+    /// ### Run a middleware around every request
+    /// Middlewares run in the order they were added, and are shared by every clone of the returned client.
+    /// #### Arguments
+    /// * `middleware` - The hooks to run, see [`Middleware`].
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Let the middlewares change a request before it is sent.
+    fn prepare(&self, endpoint: &'static str, mut request: Request) -> (RequestInfo, Request) {
+        let info = RequestInfo {
+            endpoint,
+            method: request.method().clone(),
+            url: request.url().clone(),
+            started: Instant::now(),
+        };
+        self.middlewares.before_send(&info, &mut request);
+        (info, request)
+    }
+
+    /// Send a prepared request, telling the middlewares how it went.
+    async fn send(&self, info: &RequestInfo, request: Request) -> Result<Response, AihordeError> {
+        match self.client.execute(request).await {
+            Ok(response) => {
+                self.middlewares.after_response(info, &response);
+                Ok(response)
+            }
+            Err(e) => {
+                let error = e.into();
+                self.middlewares.on_error(info, &error);
+                Err(error)
+            }
+        }
+    }
+
+    /// Send a request through the middlewares and parse its response.
+    async fn execute<T: for<'de> Deserialize<'de>>(
+        &self,
+        endpoint: &'static str,
+        request: RequestBuilder,
+    ) -> Result<T, AihordeError> {
+        let (info, request) = self.prepare(endpoint, request.build()?);
+        let response = self.send(&info, request).await?;
+        let result = AihordeClient::handle_response::<T>(response).await;
+        if let Err(error) = &result {
+            self.middlewares.on_error(&info, error);
+        }
+        result
+    }
+
     async fn handle_response<T: for<'de> Deserialize<'de>>(
         response: reqwest::Response,
     ) -> Result<T, AihordeError> {
@@ -73,13 +191,20 @@ impl AihordeClient {
         let text = response.text().await?;
         // debug!("Response text: {:?}", text);
 
+        AihordeClient::parse_response(status, &text)
+    }
+
+    fn parse_response<T: for<'de> Deserialize<'de>>(
+        status: StatusCode,
+        text: &str,
+    ) -> Result<T, AihordeError> {
         if status.is_success() {
-            let result: T = serde_json::from_str(&text)
+            let result: T = serde_json::from_str(text)
                 .map_err(|e| AihordeError::JsonParseError(e.to_string()))?;
             Ok(result)
         } else {
             // Try to parse as ValidationError first
-            if let Ok(validation_error) = serde_json::from_str::<ValidationError>(&text) {
+            if let Ok(validation_error) = serde_json::from_str::<ValidationError>(text) {
                 Err(AihordeError::ApiError {
                     code: validation_error.rc,
                     message: validation_error.message,
@@ -87,62 +212,143 @@ impl AihordeClient {
             } else {
                 Err(AihordeError::UnexpectedHTTPCode {
                     code: status.as_u16(),
-                    message: text,
+                    message: text.to_string(),
                 })
             }
         }
     }
 
+    /// Send a GET request through the response cache, if this client has one.
+    async fn get_cached<T: for<'de> Deserialize<'de>>(
+        &self,
+        endpoint: &'static str,
+        cached: CachedEndpoint,
+        request: RequestBuilder,
+    ) -> Result<T, AihordeError> {
+        let Some(cache) = &self.cache else {
+            return self.execute(endpoint, request).await;
+        };
+        let mut request = request.build()?;
+        let key = format!("{} {}", self.api_key, cache_key(request.url()));
+        match cache.lookup(cached, &key) {
+            Lookup::Fresh(body) => return AihordeClient::parse_response(StatusCode::OK, &body),
+            Lookup::Stale(Some(etag)) => {
+                if let Ok(value) = etag.parse() {
+                    request.headers_mut().insert(IF_NONE_MATCH, value);
+                }
+            }
+            Lookup::Stale(None) | Lookup::Missing => {}
+        }
+        let (info, request) = self.prepare(endpoint, request);
+        let response = self.send(&info, request).await?;
+        let status = response.status();
+        debug!("Response status: {status}");
+        if status == StatusCode::NOT_MODIFIED
+            && let Some(body) = cache.revalidate(&key)
+        {
+            debug!("Cached response of {cached:?} is still valid");
+            return AihordeClient::parse_response(StatusCode::OK, &body);
+        }
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(String::from);
+        let result = match response.text().await {
+            Ok(text) => AihordeClient::parse_response(status, &text).map(|result| (result, text)),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok((result, text)) => {
+                if cache.config().ttl(cached).is_some() {
+                    cache.store(cached, key, text, etag);
+                }
+                Ok(result)
+            }
+            Err(error) => {
+                self.middlewares.on_error(&info, &error);
+                Err(error)
+            }
+        }
+    }
+
     /// ### Lookup user based on their API key
     /// This can be used to verify a user exists
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/find_user"))
+    )]
     pub async fn find_user(&self) -> Result<UserDetails, AihordeError> {
         let url = format!("{}/find_user", self.base_url);
-        let response = self
+        let request = self
             .client
             .get(url)
             .header("apikey", &self.api_key)
-            .header("Client-Agent", &self.client_agent)
-            .send()
-            .await?;
-        let user = AihordeClient::handle_response::<UserDetails>(response).await?;
+            .header("Client-Agent", &self.client_agent);
+        let user = self.execute::<UserDetails>("/find_user", request).await?;
         Ok(user)
     }
 
     /// ### Details and statistics about a specific user
     /// #### Arguments
     /// * `user_id` - The ID of the user to retrieve.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/users/{id}", user_id = %user_id))
+    )]
     pub async fn get_user(&self, user_id: String) -> Result<UserDetails, AihordeError> {
         let url = format!("{}/users/{}", self.base_url, user_id);
-        let response = self
+        let request = self
             .client
             .get(url)
             .header("apikey", &self.api_key)
-            .header("Client-Agent", &self.client_agent)
-            .send()
+            .header("Client-Agent", &self.client_agent);
+        let user = self
+            .get_cached::<UserDetails>("/users/{id}", CachedEndpoint::User, request)
             .await?;
-        let user = AihordeClient::handle_response::<UserDetails>(response).await?;
         Ok(user)
     }
 
     /// ### A List with the details and statistic of all registered users
     /// #### Arguments
-    /// * `page` - Which page of results to return. Each page has 25 users.
-    /// * `sort` - How to sort the returned list.
+    /// * `page` - Which page of results to return, starting at 1. Each page has 25 users.
+    /// * `sort` - How to sort the returned list, `"kudos"` or `"age"`, by kudos if `None`.
     pub async fn get_users(
         &self,
         page: u32,
         sort: Option<String>,
     ) -> Result<Vec<UserDetails>, AihordeError> {
-        let sort = sort.unwrap_or("kudos".to_string());
+        let sort = sort.unwrap_or_else(|| UserSort::default().to_string());
+        self.users_page(page, &sort).await
+    }
+
+    /// ### A List with the details and statistic of all registered users
+    /// See [`AihordeClient::users_stream`] to go through all pages.
+    /// #### Arguments
+    /// * `page` - Which page of results to return, starting at 1. Each page has 25 users.
+    /// * `sort` - How to sort the returned list.
+    pub async fn get_users_sorted(
+        &self,
+        page: u32,
+        sort: UserSort,
+    ) -> Result<Vec<UserDetails>, AihordeError> {
+        self.users_page(page, &sort.to_string()).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "get_users", skip_all, fields(endpoint = "/users", page = page, sort = sort))
+    )]
+    async fn users_page(&self, page: u32, sort: &str) -> Result<Vec<UserDetails>, AihordeError> {
         let url = format!("{}/users?page={}&sort={}", self.base_url, page, sort);
-        let response = self
+        let request = self
             .client
             .get(url)
             .header("apikey", &self.api_key)
-            .header("Client-Agent", &self.client_agent)
-            .send()
+            .header("Client-Agent", &self.client_agent);
+        let users = self
+            .get_cached::<Vec<UserDetails>>("/users", CachedEndpoint::Users, request)
             .await?;
-        let users = AihordeClient::handle_response::<Vec<UserDetails>>(response).await?;
         Ok(users)
     }
 
@@ -151,20 +357,35 @@ impl AihordeClient {
     /// This endpoint will always be accepted, even if there are no workers available currently to fulfill this request.
     /// Perhaps some will appear in the next 10 minutes.
     /// Asynchronous requests live for 10 minutes before being considered stale and being deleted.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/generate/async", model = ?generation_input.models, request_id = tracing::field::Empty, kudos = tracing::field::Empty))
+    )]
     pub async fn generate_async(
         &self,
         generation_input: GenerationInputStable,
     ) -> Result<RequestAsync, AihordeError> {
         let url = format!("{}/generate/async", self.base_url);
-        let response = self
+        let request = self
             .client
             .post(url)
             .header("apikey", &self.api_key)
             .header("Client-Agent", &self.client_agent)
-            .json(&generation_input)
-            .send()
+            .json(&generation_input);
+        let request = self
+            .execute::<RequestAsync>("/generate/async", request)
             .await?;
-        let request = AihordeClient::handle_response::<RequestAsync>(response).await?;
+        if let Some(id) = &request.id {
+            record("request_id", id.as_str());
+        }
+        if let Some(kudos) = request.kudos {
+            record("kudos", kudos);
+            // Dry runs only estimate the cost
+            #[cfg(feature = "metrics")]
+            if generation_input.dry_run != Some(true) {
+                crate::metrics::record_kudos(kudos);
+            }
+        }
         Ok(request)
     }
 
@@ -172,50 +393,178 @@ impl AihordeClient {
     /// Use this request to check the status of a currently running asynchronous request without consuming bandwidth.
     /// #### Arguments
     /// * `request_id` - The UUID of the request to check.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/generate/check/{id}", request_id = %request_id, wait_time = tracing::field::Empty, done = tracing::field::Empty))
+    )]
     pub async fn generation_check(
         &self,
         request_id: String,
     ) -> Result<RequestStatusCheck, AihordeError> {
         let url = format!("{}/generate/check/{}", self.base_url, request_id);
-        let response = self
+        let request = self
             .client
             .get(url)
             .header("apikey", &self.api_key)
-            .header("Client-Agent", &self.client_agent)
-            .send()
+            .header("Client-Agent", &self.client_agent);
+        let request = self
+            .execute::<RequestStatusCheck>("/generate/check/{id}", request)
             .await?;
-        let request = AihordeClient::handle_response::<RequestStatusCheck>(response).await?;
+        if let Some(wait_time) = request.wait_time {
+            record("wait_time", wait_time as u64);
+            #[cfg(feature = "metrics")]
+            crate::metrics::record_queue_wait(wait_time);
+        }
+        if let Some(done) = request.done {
+            record("done", done);
+        }
         Ok(request)
     }
 
+    /// ### Submit an image generation and wait until it is done
+    /// The request is checked with the lightweight check endpoint, and its full status is only retrieved once done.
+    /// #### Arguments
+    /// * `generation_input` - The generation to submit.
+    /// * `poll_interval` - How long to wait between checks.
+    #[cfg(feature = "rt-tokio")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(model = ?generation_input.models, request_id = tracing::field::Empty, kudos = tracing::field::Empty, polls = tracing::field::Empty))
+    )]
+    pub async fn generate_and_wait(
+        &self,
+        generation_input: GenerationInputStable,
+        poll_interval: Duration,
+    ) -> Result<RequestStatusStable, AihordeError> {
+        #[cfg(feature = "metrics")]
+        let submitted = Instant::now();
+        let request = self.generate_async(generation_input).await?;
+        let id = request.id.ok_or_else(|| {
+            AihordeError::UnexpectedResponse("Request was accepted without an ID".to_string())
+        })?;
+        record("request_id", id.as_str());
+        let mut polls: u64 = 0;
+        loop {
+            tokio::time::sleep(poll_interval).await;
+            polls += 1;
+            record("polls", polls);
+            let check = self.generation_check(id.clone()).await?;
+            if check.faulted == Some(true) {
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_generation(submitted.elapsed(), "faulted");
+                return Err(AihordeError::RequestFaulted { id });
+            }
+            if check.done == Some(true) {
+                #[cfg(feature = "metrics")]
+                crate::metrics::record_generation(submitted.elapsed(), "done");
+                let status = self.generation_status(id).await?;
+                if let Some(kudos) = status.kudos {
+                    record("kudos", kudos as f64);
+                }
+                return Ok(status);
+            }
+            debug!(
+                "Request {id} not done yet, {} seconds to wait",
+                check.wait_time.unwrap_or_default()
+            );
+        }
+    }
+
     /// ### Retrieve the full status of an Asynchronous generation request
     /// This request will include all already generated images in download URL or base64 encoded .webp files.
     /// As such, you are requested to not retrieve this endpoint often. Instead use the /check/ endpoint first.
     /// This endpoint is limited to 10 request per minute.
     /// #### Arguments
     /// * `request_id` - The UUID of the request to check.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/generate/status/{id}", request_id = %request_id, kudos = tracing::field::Empty))
+    )]
     pub async fn generation_status(
         &self,
         request_id: String,
     ) -> Result<RequestStatusStable, AihordeError> {
         let url = format!("{}/generate/status/{}", self.base_url, request_id);
-        let response = self
+        let request = self
             .client
             .get(url)
             .header("apikey", &self.api_key)
-            .header("Client-Agent", &self.client_agent)
-            .send()
+            .header("Client-Agent", &self.client_agent);
+        let request = self
+            .execute::<RequestStatusStable>("/generate/status/{id}", request)
+            .await?;
+        if let Some(kudos) = request.kudos {
+            record("kudos", kudos as f64);
+        }
+        Ok(request)
+    }
+
+    /// ### Cancel an unfinished request
+    /// This request will include all already generated images in base64 encoded .webp files.
+    /// #### Arguments
+    /// * `request_id` - The UUID of the request to cancel.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/generate/status/{id}", request_id = %request_id))
+    )]
+    pub async fn generation_cancel(
+        &self,
+        request_id: String,
+    ) -> Result<RequestStatusStable, AihordeError> {
+        let url = format!("{}/generate/status/{}", self.base_url, request_id);
+        let request = self
+            .client
+            .delete(url)
+            .header("apikey", &self.api_key)
+            .header("Client-Agent", &self.client_agent);
+        let request = self
+            .execute::<RequestStatusStable>("/generate/status/{id}", request)
             .await?;
-        let request = AihordeClient::handle_response::<RequestStatusStable>(response).await?;
+        if let Some(kudos) = request.kudos {
+            record("kudos", kudos as f64);
+        }
         Ok(request)
     }
 
+    /// ### Transfer kudos to another user
+    /// #### Arguments
+    /// * `transfer_input` - The receiving user and the amount of kudos.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/kudos/transfer", recipient = %transfer_input.username, kudos = transfer_input.amount))
+    )]
+    pub async fn transfer_kudos(
+        &self,
+        transfer_input: KudosTransferInput,
+    ) -> Result<KudosTransferred, AihordeError> {
+        let url = format!("{}/kudos/transfer", self.base_url);
+        let request = self
+            .client
+            .post(url)
+            .header("apikey", &self.api_key)
+            .header("Client-Agent", &self.client_agent)
+            .json(&transfer_input);
+        let transferred = self
+            .execute::<KudosTransferred>("/kudos/transfer", request)
+            .await?;
+        // Both users' kudos changed
+        if let Some(cache) = &self.cache {
+            cache.invalidate(CachedEndpoint::User);
+            cache.invalidate(CachedEndpoint::Users);
+        }
+        Ok(transferred)
+    }
+
     /// ### Returns a list of models active currently in this horde
     /// #### Arguments
     /// * `model_type` - Filter the models by type (image or text).
     /// * `min_count` - Filter only models that have at least this amount of threads serving.
     /// * `max_count` - Filter the models that have at most this amount of threads serving.
     /// * `model_state` - If 'known', only show stats for known models in the model reference. If 'custom' only show stats for custom models. If 'all' shows stats for all models.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/status/models"))
+    )]
     pub async fn get_active_models(
         &self,
         model_type: Option<ModelType>,
@@ -237,15 +586,287 @@ impl AihordeClient {
         if let Some(model_state) = model_state {
             query.insert("state", serde_json::to_string(&model_state).unwrap());
         }
-        let response = self
+        let request = self
             .client
             .get(url)
             .query(&query)
             .header("apikey", &self.api_key)
-            .header("Client-Agent", &self.client_agent)
-            .send()
+            .header("Client-Agent", &self.client_agent);
+        let models = self
+            .get_cached::<Vec<ActiveModel>>("/status/models", CachedEndpoint::ActiveModels, request)
             .await?;
-        let models = AihordeClient::handle_response::<Vec<ActiveModel>>(response).await?;
         Ok(models)
     }
+
+    /// ### Check if there are generation requests queued for fulfillment
+    /// This endpoint is used by registered workers only.
+    /// When no job is available, the returned payload has no `id` and `skipped` explains why.
+    /// #### Arguments
+    /// * `pop_input` - The name and capabilities of the worker.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/generate/pop", worker = %pop_input.name, request_id = tracing::field::Empty, model = tracing::field::Empty))
+    )]
+    pub async fn generate_pop(
+        &self,
+        pop_input: PopInputStable,
+    ) -> Result<GenerationPayloadStable, AihordeError> {
+        let url = format!("{}/generate/pop", self.base_url);
+        let request = self
+            .client
+            .post(url)
+            .header("apikey", &self.api_key)
+            .header("Client-Agent", &self.client_agent)
+            .json(&pop_input);
+        let payload = self
+            .execute::<GenerationPayloadStable>("/generate/pop", request)
+            .await?;
+        if let Some(id) = &payload.id {
+            record("request_id", id.as_str());
+        }
+        if let Some(model) = &payload.model {
+            record("model", model.as_str());
+        }
+        Ok(payload)
+    }
+
+    /// ### Submit a generated image
+    /// This endpoint is used by registered workers only.
+    /// When the image was uploaded to the `r2_upload` link of the job, submit `"R2"` as the generation.
+    /// #### Arguments
+    /// * `submit_input` - The UUID of the job and its result.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/generate/submit", request_id = %submit_input.id, kudos = tracing::field::Empty))
+    )]
+    pub async fn generate_submit(
+        &self,
+        submit_input: SubmitInputStable,
+    ) -> Result<GenerationSubmitted, AihordeError> {
+        let url = format!("{}/generate/submit", self.base_url);
+        let request = self
+            .client
+            .post(url)
+            .header("apikey", &self.api_key)
+            .header("Client-Agent", &self.client_agent)
+            .json(&submit_input);
+        let submitted = self
+            .execute::<GenerationSubmitted>("/generate/submit", request)
+            .await?;
+        if let Some(reward) = submitted.reward {
+            record("kudos", reward);
+        }
+        Ok(submitted)
+    }
+
+    /// ### Check if there are text generation requests queued for fulfillment
+    /// This endpoint is used by registered workers only.
+    /// When no job is available, the returned payload has no `id` and `skipped` explains why.
+    /// #### Arguments
+    /// * `pop_input` - The name and capabilities of the worker.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/generate/text/pop", worker = %pop_input.name, request_id = tracing::field::Empty, model = tracing::field::Empty))
+    )]
+    pub async fn generate_text_pop(
+        &self,
+        pop_input: PopInputKobold,
+    ) -> Result<GenerationPayloadKobold, AihordeError> {
+        let url = format!("{}/generate/text/pop", self.base_url);
+        let request = self
+            .client
+            .post(url)
+            .header("apikey", &self.api_key)
+            .header("Client-Agent", &self.client_agent)
+            .json(&pop_input);
+        let payload = self
+            .execute::<GenerationPayloadKobold>("/generate/text/pop", request)
+            .await?;
+        if let Some(id) = &payload.id {
+            record("request_id", id.as_str());
+        }
+        if let Some(model) = &payload.model {
+            record("model", model.as_str());
+        }
+        Ok(payload)
+    }
+
+    /// ### Submit generated text
+    /// This endpoint is used by registered workers only.
+    /// #### Arguments
+    /// * `submit_input` - The UUID of the job and its result.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/generate/text/submit", request_id = %submit_input.id, kudos = tracing::field::Empty))
+    )]
+    pub async fn generate_text_submit(
+        &self,
+        submit_input: SubmitInputKobold,
+    ) -> Result<GenerationSubmitted, AihordeError> {
+        let url = format!("{}/generate/text/submit", self.base_url);
+        let request = self
+            .client
+            .post(url)
+            .header("apikey", &self.api_key)
+            .header("Client-Agent", &self.client_agent)
+            .json(&submit_input);
+        let submitted = self
+            .execute::<GenerationSubmitted>("/generate/text/submit", request)
+            .await?;
+        if let Some(reward) = submitted.reward {
+            record("kudos", reward);
+        }
+        Ok(submitted)
+    }
+
+    /// ### Check if there are interrogation forms queued for fulfillment
+    /// This endpoint is used by registered alchemist workers only.
+    /// #### Arguments
+    /// * `pop_input` - The name and supported forms of the worker.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/interrogate/pop", worker = %pop_input.name))
+    )]
+    pub async fn interrogate_pop(
+        &self,
+        pop_input: InterrogationPopInput,
+    ) -> Result<InterrogationPopPayload, AihordeError> {
+        let url = format!("{}/interrogate/pop", self.base_url);
+        let request = self
+            .client
+            .post(url)
+            .header("apikey", &self.api_key)
+            .header("Client-Agent", &self.client_agent)
+            .json(&pop_input);
+        let payload = self
+            .execute::<InterrogationPopPayload>("/interrogate/pop", request)
+            .await?;
+        Ok(payload)
+    }
+
+    /// ### Submit the results of an interrogation form
+    /// This endpoint is used by registered alchemist workers only.
+    /// #### Arguments
+    /// * `submit_input` - The UUID of the form and its result.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "/interrogate/submit", request_id = %submit_input.id, kudos = tracing::field::Empty))
+    )]
+    pub async fn interrogate_submit(
+        &self,
+        submit_input: InterrogationSubmitInput,
+    ) -> Result<GenerationSubmitted, AihordeError> {
+        let url = format!("{}/interrogate/submit", self.base_url);
+        let request = self
+            .client
+            .post(url)
+            .header("apikey", &self.api_key)
+            .header("Client-Agent", &self.client_agent)
+            .json(&submit_input);
+        let submitted = self
+            .execute::<GenerationSubmitted>("/interrogate/submit", request)
+            .await?;
+        if let Some(reward) = submitted.reward {
+            record("kudos", reward);
+        }
+        Ok(submitted)
+    }
+
+    /// ### Wrap a finished generation into a [`GeneratedImage`]
+    /// The returned image knows whether it is Base64-encoded or has to be downloaded from r2.
+    pub fn generated_image(
+        &self,
+        generation: GenerationStable,
+    ) -> Result<GeneratedImage, AihordeError> {
+        GeneratedImage::new(self.client.clone(), generation)
+    }
+
+    /// ### Download all images of a generation request concurrently
+    /// Images are returned in the same order as `status.generations`.
+    /// #### Arguments
+    /// * `status` - The full status of a request, as returned by `generation_status`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(images = status.generations.as_ref().map_or(0, Vec::len)))
+    )]
+    pub async fn download_generations(
+        &self,
+        status: &RequestStatusStable,
+    ) -> Result<Vec<DownloadedImage>, AihordeError> {
+        let images = status
+            .generations
+            .iter()
+            .flatten()
+            .map(|generation| self.generated_image(generation.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        try_join_all(images.iter().map(GeneratedImage::download)).await
+    }
+
+    /// ### Upload a generated .webp image to an r2 upload link
+    /// After a successful upload, submit `"R2"` as the generation of the job.
+    /// Connection errors, timeouts, `429 Too Many Requests` and server errors are retried with an exponential backoff,
+    /// up to `R2_UPLOAD_ATTEMPTS` attempts in total.
+    /// #### Arguments
+    /// * `upload_url` - The presigned `r2_upload` link of a job.
+    /// * `image` - The .webp file to upload.
+    #[cfg(feature = "rt-tokio")]
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(endpoint = "r2_upload", bytes = image.len()))
+    )]
+    pub async fn upload_r2(&self, upload_url: &str, image: Vec<u8>) -> Result<(), AihordeError> {
+        let mut attempt = 1;
+        loop {
+            let request = self
+                .client
+                .put(upload_url)
+                .header(reqwest::header::CONTENT_TYPE, "image/webp")
+                .body(image.clone());
+            let (info, request) = self.prepare("r2_upload", request.build()?);
+            let result = self.client.execute(request).await;
+            if let Ok(response) = &result {
+                self.middlewares.after_response(&info, response);
+            }
+            let (error, retryable) = match result {
+                Ok(response) if response.status().is_success() => {
+                    debug!("Uploaded {} bytes to r2", image.len());
+                    return Ok(());
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let error = AihordeError::UnexpectedHTTPCode {
+                        code: status.as_u16(),
+                        message: response.text().await.unwrap_or_default(),
+                    };
+                    let retryable = status.is_server_error()
+                        || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+                    (error, retryable)
+                }
+                Err(e) => {
+                    let retryable = e.is_connect() || e.is_timeout();
+                    (e.into(), retryable)
+                }
+            };
+            self.middlewares.on_error(&info, &error);
+            if !retryable {
+                return Err(error);
+            }
+            if attempt >= R2_UPLOAD_ATTEMPTS {
+                return Err(error);
+            }
+            warn!("r2 upload attempt {attempt} failed, retrying: {error}");
+            let backoff = R2_UPLOAD_BACKOFF_MS * 2u64.pow(attempt - 1);
+            tokio::time::sleep(Duration::from_millis(backoff)).await;
+            attempt += 1;
+        }
+    }
 }
+
+/// Record a field of the current span.
+#[cfg(feature = "tracing")]
+fn record(field: &'static str, value: impl tracing::Value) {
+    tracing::Span::current().record(field, value);
+}
+
+#[cfg(not(feature = "tracing"))]
+fn record<T>(_field: &'static str, _value: T) {}
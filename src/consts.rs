@@ -2,3 +2,11 @@ pub const PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const DEFAULT_API_KEY: &str = "0000000000";
 pub const DEFAULT_BASE_URL: &str = "https://aihorde.net/api/v2";
 pub const DEFAULT_CLIENT_AGENT: &str = "aihorde-rs:{}:https://github.com/lapismyt/aihorde-rs";
+pub const R2_UPLOAD_ATTEMPTS: u32 = 3;
+pub const R2_UPLOAD_BACKOFF_MS: u64 = 250;
+/// The longest a `JobQueue` waits before checking a request again after failed checks.
+pub const CHECK_BACKOFF_MAX_SECS: u64 = 60;
+/// How long asynchronous requests live on the horde before they are deleted.
+pub const REQUEST_TTL_SECS: i64 = 600;
+/// How many users a page of `get_users_sorted` holds.
+pub const USERS_PER_PAGE: usize = 25;
@@ -239,6 +239,22 @@ pub enum GenerationState {
     #[default]
     Ok,
     Censored,
+    Faulted,
+    Csam,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestState {
+    #[default]
+    Waiting,
+    Processing,
+    Done,
+    Partial,
+    Faulted,
+    Cancelled,
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -309,3 +325,14 @@ pub enum ModelState {
     Custom,
     All,
 }
+
+/// How `get_users_sorted` sorts users.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UserSort {
+    /// Most kudos first.
+    #[default]
+    Kudos,
+    /// Oldest accounts first.
+    Age,
+}
@@ -1,4 +1,5 @@
 use crate::enums::RequestErrorCode;
+use crate::outcome::GenerationWarning;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -66,10 +67,46 @@ pub enum AihordeError {
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 
+    /// Error occurred during base64 decoding
+    #[error("Base64 decoding error: {0}")]
+    Base64Error(#[from] base64::DecodeError),
+
+    /// Error occurred during image decoding/encoding
+    #[cfg(feature = "image")]
+    #[error("Image error: {0}")]
+    ImageError(#[from] image::ImageError),
+
     /// Unable to parse JSON response
     #[error("Unable to parse JSON response: {0}")]
     JsonParseError(String),
 
+    /// A generation completed with warnings while analyzed in strict mode
+    #[error("Generation {} completed with warnings: {}", id.as_deref().unwrap_or("<unknown>"), warnings.iter().map(ToString::to_string).collect::<Vec<_>>().join("; "))]
+    GenerationWarnings {
+        id: Option<String>,
+        warnings: Vec<GenerationWarning>,
+    },
+
+    /// A generation request faulted on the horde
+    #[error("Request {id} faulted")]
+    RequestFaulted { id: String },
+
+    /// Every API key of a key pool is disabled, out of kudos or at its concurrency limit
+    #[error("No usable API key left in the pool")]
+    NoApiKeyAvailable,
+
+    /// A request would exceed the kudos budget of its caller
+    #[error(
+        "Request of {caller} would cost about {estimate} kudos, over its {period} budget ({spent} of {limit} spent)"
+    )]
+    BudgetExceeded {
+        caller: String,
+        period: String,
+        estimate: f64,
+        spent: f64,
+        limit: f64,
+    },
+
     /// Other errors
     #[error("Other error: {0}")]
     Other(String),
@@ -0,0 +1,281 @@
+#[cfg(feature = "image")]
+use std::io::Cursor;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+#[cfg(feature = "image")]
+use image::{DynamicImage, ImageFormat};
+use log::debug;
+use reqwest::Client;
+use url::Url;
+
+use crate::errors::AihordeError;
+use crate::metadata::{GenerationParameters, embed_parameters};
+use crate::models::{GenerationInputStable, GenerationStable};
+
+/// Format of a generated image, and used when saving or converting it.
+///
+/// Converting between formats needs the `image` feature.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OutputFormat {
+    /// Keep the original .webp file as returned by the horde.
+    #[default]
+    WebP,
+    Png,
+    Jpeg,
+}
+
+impl OutputFormat {
+    /// ### Guess the output format from a file extension
+    /// Returns `None` if the extension is missing or not supported.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "webp" => Some(OutputFormat::WebP),
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            _ => None,
+        }
+    }
+
+    /// ### Detect the format of an image file from its signature
+    /// Returns `None` if the data is not a WebP, PNG or JPEG file.
+    pub fn guess(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+            Some(OutputFormat::WebP)
+        } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(OutputFormat::Png)
+        } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(OutputFormat::Jpeg)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "image")]
+    fn image_format(&self) -> ImageFormat {
+        match self {
+            OutputFormat::WebP => ImageFormat::WebP,
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+        }
+    }
+
+    /// The file extension conventionally used for this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::WebP => "webp",
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// Where the image data of a generation lives.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ImageSource {
+    /// The image is embedded in the response as a Base64-encoded .webp file.
+    Base64(String),
+    /// The image has to be downloaded from a cloudflare r2 URL.
+    R2(Url),
+}
+
+impl ImageSource {
+    /// ### Interpret the `img` field of a generation
+    /// The horde sends an r2 download URL when the request was made with `r2` enabled,
+    /// and a Base64-encoded .webp file otherwise.
+    pub fn parse(img: &str) -> Result<Self, AihordeError> {
+        if img.starts_with("http://") || img.starts_with("https://") {
+            Ok(ImageSource::R2(Url::parse(img)?))
+        } else {
+            Ok(ImageSource::Base64(img.to_string()))
+        }
+    }
+}
+
+/// A generated image which has not been fetched yet.
+#[derive(Debug, Clone)]
+pub struct GeneratedImage {
+    generation: GenerationStable,
+    source: ImageSource,
+    client: Client,
+}
+
+impl GeneratedImage {
+    pub(crate) fn new(client: Client, generation: GenerationStable) -> Result<Self, AihordeError> {
+        let img = generation.img.as_deref().ok_or_else(|| {
+            AihordeError::UnexpectedResponse(format!(
+                "Generation {} has no image",
+                generation.id.as_deref().unwrap_or("<unknown>")
+            ))
+        })?;
+        let source = ImageSource::parse(img)?;
+        Ok(Self {
+            generation,
+            source,
+            client,
+        })
+    }
+
+    /// The generation this image belongs to.
+    pub fn generation(&self) -> &GenerationStable {
+        &self.generation
+    }
+
+    /// Where the image data lives.
+    pub fn source(&self) -> &ImageSource {
+        &self.source
+    }
+
+    /// ### Fetch and verify the image
+    /// The data is checked to be a WebP, PNG or JPEG file, and fully decoded with the `image` feature.
+    /// Decodes Base64 images in place and downloads r2 images using the shared HTTP client.
+    pub async fn download(&self) -> Result<DownloadedImage, AihordeError> {
+        let bytes = match &self.source {
+            ImageSource::Base64(data) => STANDARD.decode(data)?,
+            ImageSource::R2(url) => {
+                debug!("Downloading image from {url}");
+                let response = self.client.get(url.clone()).send().await?;
+                let status = response.status();
+                if !status.is_success() {
+                    return Err(AihordeError::UnexpectedHTTPCode {
+                        code: status.as_u16(),
+                        message: response.text().await.unwrap_or_default(),
+                    });
+                }
+                response.bytes().await?.to_vec()
+            }
+        };
+        DownloadedImage::new(self.generation.clone(), bytes)
+    }
+
+    /// ### Fetch the raw .webp bytes of the image
+    pub async fn bytes(&self) -> Result<Vec<u8>, AihordeError> {
+        Ok(self.download().await?.bytes)
+    }
+
+    /// ### Fetch the image and save it to `path`
+    /// The format is guessed from the file extension, falling back to .webp.
+    #[cfg(feature = "rt-tokio")]
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), AihordeError> {
+        self.download().await?.save(path).await
+    }
+
+    /// ### Fetch the image and save it to `path` in the given format
+    #[cfg(feature = "rt-tokio")]
+    pub async fn save_as(
+        &self,
+        path: impl AsRef<Path>,
+        format: OutputFormat,
+    ) -> Result<(), AihordeError> {
+        self.download().await?.save_as(path, format).await
+    }
+}
+
+/// A generated image whose data has been fetched and verified.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DownloadedImage {
+    /// The generation this image belongs to.
+    pub generation: GenerationStable,
+
+    /// The image file exactly as returned by the horde.
+    pub bytes: Vec<u8>,
+
+    /// The detected format of `bytes`.
+    pub format: OutputFormat,
+
+    /// Parameters to embed into the image when encoding or saving it.
+    pub parameters: Option<GenerationParameters>,
+}
+
+impl DownloadedImage {
+    fn new(generation: GenerationStable, bytes: Vec<u8>) -> Result<Self, AihordeError> {
+        let format = OutputFormat::guess(&bytes).ok_or_else(|| {
+            AihordeError::UnexpectedResponse(format!(
+                "Generation {} is not a WebP, PNG or JPEG image",
+                generation.id.as_deref().unwrap_or("<unknown>")
+            ))
+        })?;
+        // Make sure the data is an actual image before handing it out
+        #[cfg(feature = "image")]
+        image::load_from_memory_with_format(&bytes, format.image_format())?;
+        Ok(Self {
+            generation,
+            bytes,
+            format,
+            parameters: None,
+        })
+    }
+
+    /// ### Embed the generation parameters when encoding or saving this image
+    /// #### Arguments
+    /// * `input` - The input the request was submitted with.
+    pub fn with_parameters(mut self, input: &GenerationInputStable) -> Self {
+        self.parameters = Some(GenerationParameters::new(input, &self.generation));
+        self
+    }
+
+    /// ### Decode the image into pixels
+    #[cfg(feature = "image")]
+    pub fn decode(&self) -> Result<DynamicImage, AihordeError> {
+        Ok(image::load_from_memory_with_format(
+            &self.bytes,
+            self.format.image_format(),
+        )?)
+    }
+
+    #[cfg(feature = "image")]
+    fn convert(&self, format: OutputFormat) -> Result<Vec<u8>, AihordeError> {
+        let mut image = self.decode()?;
+        if format == OutputFormat::Jpeg {
+            image = DynamicImage::ImageRgb8(image.to_rgb8());
+        }
+        let mut buffer = Cursor::new(Vec::new());
+        image.write_to(&mut buffer, format.image_format())?;
+        Ok(buffer.into_inner())
+    }
+
+    #[cfg(not(feature = "image"))]
+    fn convert(&self, format: OutputFormat) -> Result<Vec<u8>, AihordeError> {
+        Err(AihordeError::InvalidInput(format!(
+            "Converting {:?} images to {format:?} needs the `image` feature",
+            self.format
+        )))
+    }
+
+    /// ### Encode the image in the given format
+    /// Converting to another format needs the `image` feature, and converting to JPEG drops the alpha channel.
+    /// If parameters were attached with `with_parameters`, they are embedded into the result.
+    pub fn encode(&self, format: OutputFormat) -> Result<Vec<u8>, AihordeError> {
+        let data = if format == self.format {
+            self.bytes.clone()
+        } else {
+            self.convert(format)?
+        };
+        match &self.parameters {
+            Some(parameters) => embed_parameters(&data, parameters),
+            None => Ok(data),
+        }
+    }
+
+    /// ### Save the image to `path`
+    /// The format is guessed from the file extension, falling back to .webp.
+    #[cfg(feature = "rt-tokio")]
+    pub async fn save(&self, path: impl AsRef<Path>) -> Result<(), AihordeError> {
+        let format = OutputFormat::from_path(&path).unwrap_or_default();
+        self.save_as(path, format).await
+    }
+
+    /// ### Save the image to `path` in the given format
+    #[cfg(feature = "rt-tokio")]
+    pub async fn save_as(
+        &self,
+        path: impl AsRef<Path>,
+        format: OutputFormat,
+    ) -> Result<(), AihordeError> {
+        let data = self.encode(format)?;
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+}
@@ -1,8 +1,10 @@
+#![allow(clippy::derivable_impls)]
+
 use std::fmt;
 
 use crate::enums::{
     ControlType, InjectTi, InterrogationType, MetadataType, MetadataValue, ModelState, ModelType,
-    PostProcessing, RequestErrorCode, SamplerName, SourceProcessing, StyleType, Workflow,
+    PostProcessing, RequestErrorCode, SamplerName, SourceProcessing, StyleType, UserSort, Workflow,
 };
 
 impl Default for SamplerName {
@@ -225,3 +227,51 @@ impl fmt::Display for RequestErrorCode {
         }
     }
 }
+
+impl fmt::Display for UserSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UserSort::Kudos => write!(f, "kudos"),
+            UserSort::Age => write!(f, "age"),
+        }
+    }
+}
+
+impl fmt::Display for MetadataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataType::Lora => write!(f, "lora"),
+            MetadataType::Ti => write!(f, "ti"),
+            MetadataType::Censorship => write!(f, "censorship"),
+            MetadataType::SourceImage => write!(f, "source_image"),
+            MetadataType::SourceMask => write!(f, "source_mask"),
+            MetadataType::ExtraSourceImages => write!(f, "extra_source_images"),
+            MetadataType::BatchIndex => write!(f, "batch_index"),
+            MetadataType::Information => write!(f, "information"),
+        }
+    }
+}
+
+impl fmt::Display for MetadataValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetadataValue::DownloadFailed => write!(f, "download_failed"),
+            MetadataValue::ParseFailed => write!(f, "parse_failed"),
+            MetadataValue::BaselineMismatch => write!(f, "baseline_mismatch"),
+            MetadataValue::Csam => write!(f, "csam"),
+            MetadataValue::Nsfw => write!(f, "nsfw"),
+            MetadataValue::SeeRef => write!(f, "see_ref"),
+        }
+    }
+}
+
+impl InterrogationType {
+    /// ### Whether this form produces an image
+    /// Image-producing forms are uploaded to the `r2_upload` link of the form instead of being sent inline.
+    pub fn produces_image(&self) -> bool {
+        !matches!(
+            self,
+            InterrogationType::Caption | InterrogationType::Interrogation | InterrogationType::Nsfw
+        )
+    }
+}
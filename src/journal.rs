@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::client::AihordeClient;
+use crate::consts::REQUEST_TTL_SECS;
+use crate::enums::RequestErrorCode;
+use crate::errors::AihordeError;
+use crate::models::{GenerationInputStable, RequestAsync, RequestStatusStable};
+
+/// What is known about a journaled request.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalState {
+    /// Submitted, and not known to be finished.
+    #[default]
+    Pending,
+    Done,
+    Faulted,
+    /// The request was deleted by the horde before its result was retrieved.
+    Expired,
+}
+
+/// A request recorded in a [`Journal`].
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub request_id: String,
+    pub input: GenerationInputStable,
+    pub submitted_at: DateTime<Utc>,
+
+    /// The kudos spent on the request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kudos: Option<f64>,
+
+    pub state: JournalState,
+}
+
+impl JournalEntry {
+    /// True when the horde has deleted the request by now.
+    pub fn is_expired(&self) -> bool {
+        Utc::now() - self.submitted_at >= chrono::Duration::seconds(REQUEST_TTL_SECS)
+    }
+}
+
+/// A journaled request which was polled again after a restart.
+#[derive(Debug)]
+pub struct ResumedRequest {
+    pub entry: JournalEntry,
+
+    /// The full status of the request, or `RequestExpired` if it was deleted in the meantime.
+    pub result: Result<RequestStatusStable, AihordeError>,
+}
+
+struct JournalFile {
+    file: File,
+    entries: HashMap<String, JournalEntry>,
+}
+
+/// Records submitted requests in a JSON-lines file, so they can be resumed after a restart.
+///
+/// Every change appends the entry to the file, and the last line of a request wins when the journal is opened.
+/// Use [`Journal::compact`] to drop the finished requests from the file.
+pub struct Journal {
+    path: PathBuf,
+    inner: Mutex<JournalFile>,
+}
+
+impl std::fmt::Debug for Journal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Journal").field("path", &self.path).finish()
+    }
+}
+
+impl Journal {
+    /// ### Open a journal, creating it if needed
+    /// #### Arguments
+    /// * `path` - The JSON-lines file to record requests in.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, AihordeError> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = HashMap::new();
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) => {
+                for (number, line) in content.lines().enumerate() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str::<JournalEntry>(line) {
+                        Ok(entry) => {
+                            entries.insert(entry.request_id.clone(), entry);
+                        }
+                        // A crash may leave a truncated last line behind
+                        Err(e) => warn!("Skipping line {} of {}: {e}", number + 1, path.display()),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        debug!(
+            "Opened journal {} with {} requests",
+            path.display(),
+            entries.len()
+        );
+        Ok(Self {
+            path,
+            inner: Mutex::new(JournalFile { file, entries }),
+        })
+    }
+
+    /// The file this journal is recorded in.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn write(&self, entry: JournalEntry) -> Result<(), AihordeError> {
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut inner = self.inner.lock().await;
+        inner.file.write_all(line.as_bytes()).await?;
+        inner.file.flush().await?;
+        inner.entries.insert(entry.request_id.clone(), entry);
+        Ok(())
+    }
+
+    /// ### Record a submitted request
+    pub async fn record_submitted(
+        &self,
+        request_id: &str,
+        input: &GenerationInputStable,
+        kudos: Option<f64>,
+    ) -> Result<(), AihordeError> {
+        self.write(JournalEntry {
+            request_id: request_id.to_string(),
+            input: input.clone(),
+            submitted_at: Utc::now(),
+            kudos,
+            state: JournalState::Pending,
+        })
+        .await
+    }
+
+    /// ### Record that a request is finished
+    /// Unknown requests are ignored.
+    pub async fn record_state(
+        &self,
+        request_id: &str,
+        state: JournalState,
+    ) -> Result<(), AihordeError> {
+        let entry = self.inner.lock().await.entries.get(request_id).cloned();
+        match entry {
+            Some(entry) if entry.state != state => {
+                self.write(JournalEntry { state, ..entry }).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// ### Every journaled request
+    pub async fn entries(&self) -> Vec<JournalEntry> {
+        let mut entries: Vec<JournalEntry> =
+            self.inner.lock().await.entries.values().cloned().collect();
+        entries.sort_by_key(|entry| entry.submitted_at);
+        entries
+    }
+
+    /// ### The requests which are not known to be finished
+    pub async fn pending(&self) -> Vec<JournalEntry> {
+        let mut entries = self.entries().await;
+        entries.retain(|entry| entry.state == JournalState::Pending);
+        entries
+    }
+
+    /// ### Submit an image generation and record it
+    pub async fn generate_async(
+        &self,
+        client: &AihordeClient,
+        generation_input: GenerationInputStable,
+    ) -> Result<RequestAsync, AihordeError> {
+        let request = client.generate_async(generation_input.clone()).await?;
+        if let Some(id) = &request.id {
+            self.record_submitted(id, &generation_input, request.kudos)
+                .await?;
+        }
+        Ok(request)
+    }
+
+    /// ### Resume polling the pending requests
+    /// Requests older than the lifetime of requests on the horde, or which the horde does not know anymore,
+    /// are marked as expired with a `RequestExpired` error. The others are polled until they finish.
+    /// #### Arguments
+    /// * `client` - The client to poll the requests with.
+    /// * `poll_interval` - How long to wait between checks.
+    pub async fn resume(
+        &self,
+        client: &AihordeClient,
+        poll_interval: Duration,
+    ) -> Vec<ResumedRequest> {
+        let pending = self.pending().await;
+        info!("Resuming {} journaled requests", pending.len());
+        join_all(pending.into_iter().map(|entry| async move {
+            let result = if entry.is_expired() {
+                Err(expired(&entry.request_id))
+            } else {
+                wait(client, &entry.request_id, poll_interval).await
+            };
+            let state = match &result {
+                Ok(_) => JournalState::Done,
+                Err(AihordeError::ApiError {
+                    code: RequestErrorCode::RequestExpired,
+                    ..
+                }) => JournalState::Expired,
+                Err(AihordeError::RequestFaulted { .. }) => JournalState::Faulted,
+                // Leave it pending, it can still be resumed later
+                Err(_) => JournalState::Pending,
+            };
+            if let Err(e) = self.record_state(&entry.request_id, state).await {
+                warn!("Failed to journal request {}: {e}", entry.request_id);
+            }
+            ResumedRequest { entry, result }
+        }))
+        .await
+    }
+
+    /// ### Rewrite the file with the pending requests only
+    pub async fn compact(&self) -> Result<(), AihordeError> {
+        let mut inner = self.inner.lock().await;
+        inner
+            .entries
+            .retain(|_, entry| entry.state == JournalState::Pending);
+        let mut content = String::new();
+        for entry in inner.entries.values() {
+            content.push_str(&serde_json::to_string(entry)?);
+            content.push('\n');
+        }
+        let temporary = self.path.with_extension("tmp");
+        tokio::fs::write(&temporary, content).await?;
+        tokio::fs::rename(&temporary, &self.path).await?;
+        inner.file = OpenOptions::new().append(true).open(&self.path).await?;
+        Ok(())
+    }
+}
+
+fn expired(request_id: &str) -> AihordeError {
+    AihordeError::ApiError {
+        code: RequestErrorCode::RequestExpired,
+        message: Some(format!(
+            "Request {request_id} expired before it was retrieved"
+        )),
+    }
+}
+
+async fn wait(
+    client: &AihordeClient,
+    request_id: &str,
+    poll_interval: Duration,
+) -> Result<RequestStatusStable, AihordeError> {
+    loop {
+        let check = match client.generation_check(request_id.to_string()).await {
+            Ok(check) => check,
+            Err(AihordeError::ApiError {
+                code: RequestErrorCode::RequestNotFound,
+                ..
+            }) => return Err(expired(request_id)),
+            Err(e) => return Err(e),
+        };
+        if check.faulted == Some(true) {
+            return Err(AihordeError::RequestFaulted {
+                id: request_id.to_string(),
+            });
+        }
+        if check.done == Some(true) {
+            return client.generation_status(request_id.to_string()).await;
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
@@ -0,0 +1,298 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use crate::client::AihordeClient;
+use crate::enums::RequestErrorCode;
+use crate::errors::AihordeError;
+use crate::models::{GenerationInputStable, RequestAsync, UserDetails};
+
+/// How a [`KeyPool`] picks the key for the next call.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum KeySelection {
+    /// The key with the highest kudos balance.
+    #[default]
+    MostKudos,
+    /// The key with the fewest active generations, then the most kudos.
+    FewestActive,
+}
+
+/// Why a key of a [`KeyPool`] is not used.
+#[derive(Debug, PartialEq, Clone)]
+pub enum KeyState {
+    Usable,
+    /// Skipped until the next refresh, e.g. after `KudosUpfront` or `TooManyPrompts`.
+    Exhausted(RequestErrorCode),
+    /// Never used again, e.g. after `InvalidAPIKey` or `SharedKeyExpired`.
+    Disabled(RequestErrorCode),
+}
+
+/// The last known state of a key of a [`KeyPool`].
+#[derive(Debug, Clone)]
+pub struct PooledKey {
+    pub api_key: String,
+
+    /// The user owning the key, as of the last refresh.
+    pub details: Option<UserDetails>,
+
+    pub state: KeyState,
+
+    /// Image generations submitted through the pool since the last refresh.
+    pub submitted: u64,
+
+    refreshed: Option<Instant>,
+}
+
+impl PooledKey {
+    fn new(api_key: String) -> Self {
+        Self {
+            api_key,
+            details: None,
+            state: KeyState::Usable,
+            submitted: 0,
+            refreshed: None,
+        }
+    }
+
+    /// The kudos balance as of the last refresh.
+    pub fn kudos(&self) -> f64 {
+        self.details
+            .as_ref()
+            .and_then(|details| details.kudos)
+            .unwrap_or_default()
+    }
+
+    /// The active image generations, including the ones submitted since the last refresh.
+    pub fn active_generations(&self) -> u64 {
+        let active = self
+            .details
+            .as_ref()
+            .and_then(|details| details.active_generations.as_ref())
+            .and_then(|active| active.image.as_ref())
+            .map_or(0, |image| image.len() as u64);
+        active + self.submitted
+    }
+
+    fn has_capacity(&self) -> bool {
+        let concurrency = self
+            .details
+            .as_ref()
+            .and_then(|details| details.concurrency);
+        concurrency.is_none_or(|concurrency| self.active_generations() < concurrency)
+    }
+}
+
+/// Balances calls across several API keys with separate kudos balances and concurrency limits.
+///
+/// Balances are refreshed with `find_user` when they are older than the refresh interval.
+/// Keys rejected with `InvalidAPIKey` or `SharedKeyExpired` are disabled, keys without enough kudos
+/// or at their concurrency limit are skipped until the next refresh, and the call is retried with the next key.
+#[derive(Debug, Clone)]
+pub struct KeyPool {
+    client: AihordeClient,
+    keys: Arc<Mutex<Vec<PooledKey>>>,
+    selection: KeySelection,
+    refresh_interval: Duration,
+}
+
+impl KeyPool {
+    /// ### Create a new key pool
+    /// #### Arguments
+    /// * `client` - The client to derive the clients of every key from.
+    /// * `api_keys` - The API keys, either user keys or shared keys.
+    pub fn new(client: AihordeClient, api_keys: impl IntoIterator<Item = String>) -> Self {
+        let keys = api_keys.into_iter().map(PooledKey::new).collect();
+        Self {
+            client,
+            keys: Arc::new(Mutex::new(keys)),
+            selection: KeySelection::default(),
+            refresh_interval: Duration::from_secs(60),
+        }
+    }
+
+    /// ### Change how the key for the next call is picked
+    pub fn with_selection(mut self, selection: KeySelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// ### Change how often balances are refreshed
+    pub fn with_refresh_interval(mut self, refresh_interval: Duration) -> Self {
+        self.refresh_interval = refresh_interval;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<PooledKey>> {
+        self.keys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// ### The last known state of every key
+    pub fn keys(&self) -> Vec<PooledKey> {
+        self.lock().clone()
+    }
+
+    /// ### Refresh the balances of every key now
+    /// Keys which are rejected by the horde are disabled.
+    pub async fn refresh(&self) -> Result<(), AihordeError> {
+        let api_keys: Vec<String> = self
+            .lock()
+            .iter()
+            .filter(|key| !matches!(key.state, KeyState::Disabled(_)))
+            .map(|key| key.api_key.clone())
+            .collect();
+        for api_key in api_keys {
+            self.refresh_key(&api_key).await?;
+        }
+        Ok(())
+    }
+
+    async fn refresh_key(&self, api_key: &str) -> Result<(), AihordeError> {
+        let result = self.client.with_api_key(api_key).find_user().await;
+        let mut keys = self.lock();
+        let Some(key) = keys.iter_mut().find(|key| key.api_key == api_key) else {
+            return Ok(());
+        };
+        match result {
+            Ok(details) => {
+                debug!(
+                    "Refreshed key of {}: {} kudos",
+                    details.username.as_deref().unwrap_or("<unknown>"),
+                    details.kudos.unwrap_or_default()
+                );
+                key.details = Some(details);
+                key.state = KeyState::Usable;
+                key.submitted = 0;
+                key.refreshed = Some(Instant::now());
+                Ok(())
+            }
+            Err(AihordeError::ApiError { code, .. }) if is_disabling(&code) => {
+                warn!("Disabling API key of the pool: {code}");
+                key.state = KeyState::Disabled(code);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// ### Pick the key for the next call
+    /// Stale balances are refreshed first. A key whose refresh fails keeps its last known balance.
+    pub async fn client(&self) -> Result<AihordeClient, AihordeError> {
+        self.pick(&[]).await
+    }
+
+    async fn pick(&self, exclude: &[String]) -> Result<AihordeClient, AihordeError> {
+        let stale: Vec<String> = self
+            .lock()
+            .iter()
+            .filter(|key| {
+                !matches!(key.state, KeyState::Disabled(_))
+                    && key
+                        .refreshed
+                        .is_none_or(|refreshed| refreshed.elapsed() >= self.refresh_interval)
+            })
+            .map(|key| key.api_key.clone())
+            .collect();
+        for api_key in stale {
+            if let Err(e) = self.refresh_key(&api_key).await {
+                warn!("Failed to refresh an API key of the pool: {e}");
+            }
+        }
+
+        let keys = self.lock();
+        let usable = keys
+            .iter()
+            .filter(|key| key.state == KeyState::Usable && key.has_capacity())
+            .filter(|key| !exclude.contains(&key.api_key));
+        let best = match self.selection {
+            KeySelection::MostKudos => usable.max_by(|a, b| a.kudos().total_cmp(&b.kudos())),
+            KeySelection::FewestActive => usable.min_by(|a, b| {
+                a.active_generations()
+                    .cmp(&b.active_generations())
+                    .then(b.kudos().total_cmp(&a.kudos()))
+            }),
+        };
+        best.map(|key| self.client.with_api_key(&key.api_key))
+            .ok_or(AihordeError::NoApiKeyAvailable)
+    }
+
+    /// ### Run `call` with the best key, failing over to the next one
+    /// The call is retried with another key when the horde rejects the key, it lacks the kudos
+    /// or it is at its concurrency limit. Other errors are returned as they are.
+    pub async fn call<T, F, Fut>(&self, call: F) -> Result<T, AihordeError>
+    where
+        F: Fn(AihordeClient) -> Fut,
+        Fut: Future<Output = Result<T, AihordeError>>,
+    {
+        let mut tried = Vec::new();
+        loop {
+            let client = self.pick(&tried).await?;
+            let api_key = client.api_key().to_string();
+            tried.push(api_key.clone());
+            match call(client).await {
+                Err(AihordeError::ApiError { code, message }) if is_failover(&code) => {
+                    warn!(
+                        "API key of the pool rejected ({code}): {}",
+                        message.as_deref().unwrap_or("No message")
+                    );
+                    let mut keys = self.lock();
+                    if let Some(key) = keys.iter_mut().find(|key| key.api_key == api_key) {
+                        key.state = if is_disabling(&code) {
+                            KeyState::Disabled(code)
+                        } else {
+                            KeyState::Exhausted(code)
+                        };
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// ### Submit an image generation with the best key
+    /// Counts towards the active generations of the key until the next refresh.
+    pub async fn generate_async(
+        &self,
+        generation_input: GenerationInputStable,
+    ) -> Result<RequestAsync, AihordeError> {
+        let dry_run = generation_input.dry_run == Some(true);
+        let (api_key, request) = self
+            .call(|client| {
+                let generation_input = generation_input.clone();
+                async move {
+                    let request = client.generate_async(generation_input).await?;
+                    Ok((client.api_key().to_string(), request))
+                }
+            })
+            .await?;
+        if !dry_run && let Some(key) = self.lock().iter_mut().find(|key| key.api_key == api_key) {
+            key.submitted += 1;
+            if let (Some(details), Some(kudos)) = (key.details.as_mut(), request.kudos) {
+                details.kudos = Some(details.kudos.unwrap_or_default() - kudos);
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Errors after which a key is never used again.
+fn is_disabling(code: &RequestErrorCode) -> bool {
+    matches!(
+        code,
+        RequestErrorCode::InvalidAPIKey | RequestErrorCode::SharedKeyExpired
+    )
+}
+
+/// Errors after which the call is retried with another key.
+fn is_failover(code: &RequestErrorCode) -> bool {
+    is_disabling(code)
+        || matches!(
+            code,
+            RequestErrorCode::KudosUpfront
+                | RequestErrorCode::SharedKeyInsufficientKudos
+                | RequestErrorCode::TooManyPrompts
+        )
+}
@@ -1,14 +1,80 @@
+//! Rust library for using [AI Horde](https://aihorde.net) with ease.
+//!
+//! # Runtime
+//! Nothing in the core client sleeps or spawns tasks. The helpers which do, `generate_and_wait`,
+//! the r2 upload retries, saving images, job queues, batches, sweeps and workers, need the
+//! `rt-tokio` feature, and the crate only depends on tokio directly with it.
+//!
+//! Running [`AihordeClient`] without tokio is out of scope: it sends its requests with async
+//! `reqwest`, whose connections need a tokio 1.x reactor. Under another executor, such as async-std
+//! or smol, poll its futures through a compatibility layer like the `async-compat` crate.
+//!
+//! # Features
+//! * `rt-tokio` - Helpers which sleep or spawn tasks: polling, r2 upload retries, saving images,
+//!   job queues, batches, sweeps and workers.
+//! * `image` - Converting images between WebP, PNG and JPEG, decoding them and drawing contact sheets.
+//! * `blocking` - A synchronous client running its own tokio runtime.
+//! * `webhook`, `mock`, `journal`, `budget`, `cli`, `tracing` and `metrics`, see their modules.
+
+#[cfg(feature = "rt-tokio")]
+pub mod batch;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "budget")]
+pub mod budget;
+pub mod cache;
 pub mod client;
 pub mod consts;
 pub mod enums;
 pub mod errors;
+pub mod images;
 pub mod impls;
+#[cfg(feature = "journal")]
+pub mod journal;
+pub mod key_pool;
+pub mod metadata;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod middleware;
+#[cfg(feature = "mock")]
+pub mod mock;
 pub mod models;
+pub mod outcome;
+#[cfg(feature = "rt-tokio")]
+pub mod queue;
+pub mod seed;
+#[cfg(feature = "rt-tokio")]
+pub mod sweep;
+pub mod users;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+#[cfg(feature = "rt-tokio")]
+pub mod worker;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "mock"))]
 mod tests;
 
 pub use models::*;
 pub use enums::*;
+#[cfg(feature = "rt-tokio")]
+pub use batch::{Batch, BatchReport};
+#[cfg(feature = "budget")]
+pub use budget::{Budget, BudgetGuard, CostEstimate};
+pub use cache::{CacheConfig, CachedEndpoint};
 pub use client::AihordeClient;
-pub use errors::AihordeError;
\ No newline at end of file
+pub use errors::AihordeError;
+pub use key_pool::{KeyPool, KeySelection};
+pub use images::{DownloadedImage, GeneratedImage, ImageSource, OutputFormat};
+pub use metadata::GenerationParameters;
+pub use middleware::{LoggingMiddleware, Middleware, TimingMiddleware};
+pub use outcome::{GenerationOutcome, GenerationWarning};
+pub use seed::Seed;
+pub use users::UsersStream;
+#[cfg(feature = "rt-tokio")]
+pub use queue::{FinishedJob, JobQueue};
+#[cfg(feature = "rt-tokio")]
+pub use sweep::{Sweep, SweepAxis, SweepResult};
+#[cfg(feature = "rt-tokio")]
+pub use worker::{
+    HordeWorker, HordeWorkerBackend, WorkerConfig, WorkerHandle, WorkerOutput, WorkerStats,
+};
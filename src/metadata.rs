@@ -0,0 +1,848 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::enums::SamplerName;
+use crate::errors::AihordeError;
+use crate::images::OutputFormat;
+use crate::models::{
+    GenerationInputStable, GenerationMetadataStable, GenerationStable, ModelGenerationInputStable,
+    ModelPayloadLorasStable, ModelPayloadTextualInversionsStable,
+};
+
+/// Keyword of the PNG text chunk used by A1111 and most other UIs.
+const PARAMETERS_KEYWORD: &str = "parameters";
+const EXIF_USER_COMMENT_TAG: u16 = 0x9286;
+const EXIF_IFD_POINTER_TAG: u16 = 0x8769;
+const EXIF_UNICODE_PREFIX: &[u8] = b"UNICODE\0";
+const EXIF_ASCII_PREFIX: &[u8] = b"ASCII\0\0\0";
+const JPEG_EXIF_PREFIX: &[u8] = b"Exif\0\0";
+const JPEG_XMP_PREFIX: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_VALUE_START: &str = "<rdf:li xml:lang=\"x-default\">";
+const XMP_VALUE_END: &str = "</rdf:li>";
+const KNOWN_KEYS: &[&str] = &[
+    "Steps",
+    "Sampler",
+    "Schedule type",
+    "CFG scale",
+    "Seed",
+    "Size",
+    "Model",
+    "Denoising strength",
+    "Clip skip",
+    "Worker",
+    "Worker ID",
+    "Loras",
+    "TIs",
+    "Generation metadata",
+];
+
+/// The parameters which produced a generated image.
+///
+/// Serializes to and parses from the A1111 "parameters" text format, so images saved
+/// by this crate can be inspected by common tools and fed back to the horde.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct GenerationParameters {
+    pub prompt: String,
+    pub negative_prompt: Option<String>,
+    pub steps: Option<u16>,
+    pub sampler_name: Option<SamplerName>,
+    pub karras: Option<bool>,
+    pub cfg_scale: Option<f32>,
+    pub seed: Option<String>,
+    pub width: Option<u16>,
+    pub height: Option<u16>,
+    pub model: Option<String>,
+    pub denoising_strength: Option<f32>,
+    pub clip_skip: Option<u8>,
+    pub worker_id: Option<String>,
+    pub worker_name: Option<String>,
+    pub loras: Option<Vec<ModelPayloadLorasStable>>,
+    pub tis: Option<Vec<ModelPayloadTextualInversionsStable>>,
+    pub gen_metadata: Option<Vec<GenerationMetadataStable>>,
+}
+
+impl GenerationParameters {
+    /// ### Collect the parameters of a finished generation
+    /// #### Arguments
+    /// * `input` - The input the request was submitted with.
+    /// * `generation` - The generation as returned by `generation_status`.
+    pub fn new(input: &GenerationInputStable, generation: &GenerationStable) -> Self {
+        let (prompt, negative_prompt) = match input.prompt.split_once("###") {
+            Some((prompt, negative)) => {
+                (prompt.trim().to_string(), Some(negative.trim().to_string()))
+            }
+            None => (input.prompt.clone(), None),
+        };
+        let params = input.params.clone().unwrap_or_default();
+        Self {
+            prompt,
+            negative_prompt,
+            steps: params.steps,
+            sampler_name: params.sampler_name,
+            karras: params.karras,
+            cfg_scale: params.cfg_scale,
+            seed: generation.seed.clone().or(params.seed),
+            width: params.width,
+            height: params.height,
+            model: generation.model.clone().or_else(|| {
+                input
+                    .models
+                    .as_ref()
+                    .and_then(|models| models.first().cloned())
+            }),
+            denoising_strength: params.denoising_strength,
+            clip_skip: params.clip_skip,
+            worker_id: generation.worker_id.clone(),
+            worker_name: generation.worker_name.clone(),
+            loras: params.loras,
+            tis: params.tis,
+            gen_metadata: generation.gen_metadata.clone(),
+        }
+    }
+
+    /// ### Rebuild a generation input reproducing this image
+    /// The worker is not pinned, as it may no longer be online.
+    pub fn to_generation_input(&self) -> GenerationInputStable {
+        let prompt = match &self.negative_prompt {
+            Some(negative) => format!("{} ### {}", self.prompt, negative),
+            None => self.prompt.clone(),
+        };
+        GenerationInputStable {
+            prompt,
+            params: Some(ModelGenerationInputStable {
+                sampler_name: self.sampler_name.clone(),
+                cfg_scale: self.cfg_scale,
+                denoising_strength: self.denoising_strength,
+                height: self.height,
+                width: self.width,
+                karras: self.karras,
+                clip_skip: self.clip_skip,
+                loras: self.loras.clone(),
+                tis: self.tis.clone(),
+                seed: self.seed.clone(),
+                steps: self.steps,
+                n: Some(1),
+                ..Default::default()
+            }),
+            models: self.model.clone().map(|model| vec![model]),
+            ..Default::default()
+        }
+    }
+}
+
+fn sampler_to_a1111(sampler: &SamplerName) -> Option<&'static str> {
+    match sampler {
+        SamplerName::KDpmAdaptive => Some("DPM adaptive"),
+        SamplerName::KEulerA => Some("Euler a"),
+        SamplerName::KDpmpp2M => Some("DPM++ 2M"),
+        SamplerName::Ddim => Some("DDIM"),
+        SamplerName::KDpm2 => Some("DPM2"),
+        SamplerName::KEuler => Some("Euler"),
+        SamplerName::KDpmpp2SA => Some("DPM++ 2S a"),
+        SamplerName::KLms => Some("LMS"),
+        SamplerName::KDpmppSde => Some("DPM++ SDE"),
+        SamplerName::KDpm2A => Some("DPM2 a"),
+        SamplerName::KDpmFast => Some("DPM fast"),
+        SamplerName::KHeun => Some("Heun"),
+        SamplerName::Unknown => None,
+    }
+}
+
+fn sampler_from_a1111(name: &str) -> SamplerName {
+    match name {
+        "DPM adaptive" => SamplerName::KDpmAdaptive,
+        "Euler a" => SamplerName::KEulerA,
+        "DPM++ 2M" => SamplerName::KDpmpp2M,
+        "DDIM" => SamplerName::Ddim,
+        "DPM2" => SamplerName::KDpm2,
+        "Euler" => SamplerName::KEuler,
+        "DPM++ 2S a" => SamplerName::KDpmpp2SA,
+        "LMS" => SamplerName::KLms,
+        "DPM++ SDE" => SamplerName::KDpmppSde,
+        "DPM2 a" => SamplerName::KDpm2A,
+        "DPM fast" => SamplerName::KDpmFast,
+        "Heun" => SamplerName::KHeun,
+        _ => SamplerName::Unknown,
+    }
+}
+
+/// Quote a value the way A1111 does when it would break the `key: value, ...` syntax.
+fn quote(value: &str) -> String {
+    if value.contains([',', ':', '"', '\n']) {
+        serde_json::to_string(value).unwrap_or_default()
+    } else {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for GenerationParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.prompt)?;
+        if let Some(negative) = &self.negative_prompt {
+            writeln!(f, "Negative prompt: {negative}")?;
+        }
+
+        let mut fields: Vec<(&str, String)> = Vec::new();
+        if let Some(steps) = self.steps {
+            fields.push(("Steps", steps.to_string()));
+        }
+        if let Some(name) = self.sampler_name.as_ref().and_then(sampler_to_a1111) {
+            fields.push(("Sampler", name.to_string()));
+        }
+        if self.karras == Some(true) {
+            fields.push(("Schedule type", "Karras".to_string()));
+        }
+        if let Some(cfg_scale) = self.cfg_scale {
+            fields.push(("CFG scale", cfg_scale.to_string()));
+        }
+        if let Some(seed) = &self.seed {
+            fields.push(("Seed", seed.clone()));
+        }
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            fields.push(("Size", format!("{width}x{height}")));
+        }
+        if let Some(model) = &self.model {
+            fields.push(("Model", model.clone()));
+        }
+        if let Some(denoising_strength) = self.denoising_strength {
+            fields.push(("Denoising strength", denoising_strength.to_string()));
+        }
+        if let Some(clip_skip) = self.clip_skip {
+            fields.push(("Clip skip", clip_skip.to_string()));
+        }
+        if let Some(worker_name) = &self.worker_name {
+            fields.push(("Worker", worker_name.clone()));
+        }
+        if let Some(worker_id) = &self.worker_id {
+            fields.push(("Worker ID", worker_id.clone()));
+        }
+        if let Some(loras) = &self.loras {
+            fields.push((
+                "Loras",
+                serde_json::to_string(loras).map_err(|_| fmt::Error)?,
+            ));
+        }
+        if let Some(tis) = &self.tis {
+            fields.push(("TIs", serde_json::to_string(tis).map_err(|_| fmt::Error)?));
+        }
+        if let Some(gen_metadata) = &self.gen_metadata {
+            fields.push((
+                "Generation metadata",
+                serde_json::to_string(gen_metadata).map_err(|_| fmt::Error)?,
+            ));
+        }
+
+        let fields = fields
+            .iter()
+            .map(|(key, value)| format!("{key}: {}", quote(value)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{fields}")
+    }
+}
+
+/// Split an A1111 parameters line into key/value pairs.
+fn parse_fields(line: &str) -> Vec<(String, String)> {
+    let mut fields = Vec::new();
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let Some((key, value)) = rest.split_once(':') else {
+            break;
+        };
+        let key = key.trim();
+        if key.is_empty() || key.contains(',') {
+            break;
+        }
+        let value = value.trim_start();
+        let (value, remaining) = if value.starts_with('"') {
+            let mut escaped = false;
+            let mut end = None;
+            for (index, character) in value.char_indices().skip(1) {
+                match character {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => {
+                        end = Some(index);
+                        break;
+                    }
+                    _ => escaped = false,
+                }
+            }
+            let Some(end) = end else {
+                break;
+            };
+            let quoted = &value[..=end];
+            let unquoted = serde_json::from_str::<String>(quoted).unwrap_or_default();
+            (unquoted, &value[end + 1..])
+        } else {
+            match value.split_once(',') {
+                Some((value, remaining)) => (value.trim().to_string(), remaining),
+                None => (value.trim().to_string(), ""),
+            }
+        };
+        fields.push((key.to_string(), value));
+        rest = remaining.trim_start().trim_start_matches(',').trim_start();
+    }
+    fields
+}
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, AihordeError> {
+    value
+        .parse()
+        .map_err(|_| AihordeError::InvalidInput(format!("Invalid {key}: {value}")))
+}
+
+impl FromStr for GenerationParameters {
+    type Err = AihordeError;
+
+    /// Parse A1111 "parameters" text.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut lines: Vec<&str> = text.trim_end().lines().collect();
+        let fields = lines
+            .last()
+            .map(|last| parse_fields(last))
+            .unwrap_or_default();
+        // Like A1111, accept any line with at least three fields, but also shorter ones
+        // written by this crate, as long as they can't be part of the prompt
+        let is_fields = fields.len() >= 3
+            || (lines.len() > 1
+                && !fields.is_empty()
+                && fields
+                    .iter()
+                    .all(|(key, _)| KNOWN_KEYS.contains(&key.as_str())));
+        let fields = if is_fields {
+            lines.pop();
+            fields
+        } else {
+            Vec::new()
+        };
+
+        let mut parameters = GenerationParameters::default();
+        let negative_start = lines
+            .iter()
+            .position(|line| line.starts_with("Negative prompt:"));
+        match negative_start {
+            Some(index) => {
+                parameters.prompt = lines[..index].join("\n");
+                let negative = lines[index..].join("\n");
+                parameters.negative_prompt = Some(
+                    negative
+                        .trim_start_matches("Negative prompt:")
+                        .trim()
+                        .to_string(),
+                );
+            }
+            None => parameters.prompt = lines.join("\n"),
+        }
+
+        for (key, value) in fields {
+            match key.as_str() {
+                "Steps" => parameters.steps = Some(parse_number(&key, &value)?),
+                "Sampler" => {
+                    // Older A1111 versions append the scheduler to the sampler name
+                    let name = match value.strip_suffix(" Karras") {
+                        Some(name) => {
+                            parameters.karras = Some(true);
+                            name
+                        }
+                        None => value.as_str(),
+                    };
+                    parameters.sampler_name = Some(sampler_from_a1111(name));
+                }
+                "Schedule type" => parameters.karras = Some(value == "Karras"),
+                "CFG scale" => parameters.cfg_scale = Some(parse_number(&key, &value)?),
+                "Seed" => parameters.seed = Some(value),
+                "Size" => {
+                    let (width, height) = value.split_once('x').ok_or_else(|| {
+                        AihordeError::InvalidInput(format!("Invalid Size: {value}"))
+                    })?;
+                    parameters.width = Some(parse_number(&key, width)?);
+                    parameters.height = Some(parse_number(&key, height)?);
+                }
+                "Model" => parameters.model = Some(value),
+                "Denoising strength" => {
+                    parameters.denoising_strength = Some(parse_number(&key, &value)?)
+                }
+                "Clip skip" => parameters.clip_skip = Some(parse_number(&key, &value)?),
+                "Worker" => parameters.worker_name = Some(value),
+                "Worker ID" => parameters.worker_id = Some(value),
+                "Loras" => parameters.loras = Some(serde_json::from_str(&value)?),
+                "TIs" => parameters.tis = Some(serde_json::from_str(&value)?),
+                "Generation metadata" => {
+                    parameters.gen_metadata = Some(serde_json::from_str(&value)?)
+                }
+                _ => {}
+            }
+        }
+        Ok(parameters)
+    }
+}
+
+/// ### Embed generation parameters into an image file
+/// PNG images get a `parameters` text chunk, WebP and JPEG images get an EXIF user comment
+/// and an XMP packet.
+pub fn embed_parameters(
+    bytes: &[u8],
+    parameters: &GenerationParameters,
+) -> Result<Vec<u8>, AihordeError> {
+    let text = parameters.to_string();
+    match OutputFormat::guess(bytes) {
+        Some(OutputFormat::Png) => png_embed(bytes, &text),
+        Some(OutputFormat::WebP) => webp_embed(bytes, &text),
+        Some(OutputFormat::Jpeg) => jpeg_embed(bytes, &text),
+        None => Err(unsupported()),
+    }
+}
+
+/// ### Read generation parameters embedded into an image file
+/// Returns `None` if the image carries no parameters.
+pub fn read_parameters(bytes: &[u8]) -> Result<Option<GenerationParameters>, AihordeError> {
+    let text = match OutputFormat::guess(bytes) {
+        Some(OutputFormat::Png) => png_read(bytes)?,
+        Some(OutputFormat::WebP) => webp_read(bytes)?,
+        Some(OutputFormat::Jpeg) => jpeg_read(bytes)?,
+        None => return Err(unsupported()),
+    };
+    text.map(|text| text.parse()).transpose()
+}
+
+/// ### Read an image file back into a generation input reproducing it
+/// Returns `None` if the image carries no parameters.
+pub fn read_generation_input(bytes: &[u8]) -> Result<Option<GenerationInputStable>, AihordeError> {
+    Ok(read_parameters(bytes)?.map(|parameters| parameters.to_generation_input()))
+}
+
+fn unsupported() -> AihordeError {
+    AihordeError::InvalidInput("Not a WebP, PNG or JPEG image".to_string())
+}
+
+fn malformed(format: &str) -> AihordeError {
+    AihordeError::InvalidInput(format!("Malformed {format} file"))
+}
+
+fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(data.len() + 12);
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+    chunk.extend_from_slice(&hasher.finalize().to_be_bytes());
+    chunk
+}
+
+/// A chunk of a PNG or WebP file, or a segment of a JPEG file.
+struct Chunk<'a> {
+    kind: [u8; 4],
+    data: &'a [u8],
+    start: usize,
+    end: usize,
+}
+
+/// Split a PNG file into its chunks.
+fn png_chunks(bytes: &[u8]) -> Result<Vec<Chunk<'_>>, AihordeError> {
+    let mut chunks = Vec::new();
+    let mut offset = 8;
+    while offset + 12 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+        let chunk_type: [u8; 4] = bytes[offset + 4..offset + 8].try_into().unwrap();
+        let end = offset + 12 + length;
+        if end > bytes.len() {
+            return Err(malformed("PNG"));
+        }
+        chunks.push(Chunk {
+            kind: chunk_type,
+            data: &bytes[offset + 8..offset + 8 + length],
+            start: offset,
+            end,
+        });
+        offset = end;
+    }
+    Ok(chunks)
+}
+
+fn png_embed(bytes: &[u8], text: &str) -> Result<Vec<u8>, AihordeError> {
+    let chunks = png_chunks(bytes)?;
+    let is_parameters = |chunk_type: &[u8; 4], data: &[u8]| {
+        (chunk_type == b"tEXt" || chunk_type == b"iTXt")
+            && data.starts_with(PARAMETERS_KEYWORD.as_bytes())
+            && data.get(PARAMETERS_KEYWORD.len()) == Some(&0)
+    };
+
+    // tEXt is Latin-1, so fall back to an uncompressed iTXt chunk for anything else
+    let mut data = PARAMETERS_KEYWORD.as_bytes().to_vec();
+    data.push(0);
+    let chunk = if text.chars().all(|character| (character as u32) < 0x100) {
+        data.extend(text.chars().map(|character| character as u8));
+        png_chunk(b"tEXt", &data)
+    } else {
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+        png_chunk(b"iTXt", &data)
+    };
+
+    let mut output = bytes[..8].to_vec();
+    for Chunk {
+        kind,
+        data,
+        start,
+        end,
+    } in chunks
+    {
+        if is_parameters(&kind, data) {
+            continue;
+        }
+        output.extend_from_slice(&bytes[start..end]);
+        if &kind == b"IHDR" {
+            output.extend_from_slice(&chunk);
+        }
+    }
+    Ok(output)
+}
+
+fn png_read(bytes: &[u8]) -> Result<Option<String>, AihordeError> {
+    for Chunk { kind, data, .. } in png_chunks(bytes)? {
+        let Some(text) = data
+            .strip_prefix(PARAMETERS_KEYWORD.as_bytes())
+            .and_then(|data| data.strip_prefix(&[0]))
+        else {
+            continue;
+        };
+        match &kind {
+            b"tEXt" => return Ok(Some(text.iter().map(|&byte| byte as char).collect())),
+            b"iTXt" => {
+                // compression flag, compression method, language tag, translated keyword
+                let [0, _, rest @ ..] = text else {
+                    continue;
+                };
+                let mut parts = rest.splitn(3, |&byte| byte == 0);
+                let (Some(_), Some(_), Some(text)) = (parts.next(), parts.next(), parts.next())
+                else {
+                    continue;
+                };
+                return Ok(Some(String::from_utf8_lossy(text).into_owned()));
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Build a little-endian TIFF structure holding only an EXIF user comment.
+fn exif_user_comment(text: &str) -> Vec<u8> {
+    let mut comment = EXIF_UNICODE_PREFIX.to_vec();
+    comment.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+
+    let exif_ifd_offset: u32 = 8 + 2 + 12 + 4;
+    let comment_offset: u32 = exif_ifd_offset + 2 + 12 + 4;
+    let mut tiff = b"II\x2a\x00".to_vec();
+    tiff.extend_from_slice(&8u32.to_le_bytes());
+    // IFD0 with a single pointer to the EXIF IFD
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&EXIF_IFD_POINTER_TAG.to_le_bytes());
+    tiff.extend_from_slice(&4u16.to_le_bytes());
+    tiff.extend_from_slice(&1u32.to_le_bytes());
+    tiff.extend_from_slice(&exif_ifd_offset.to_le_bytes());
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    // EXIF IFD with the user comment
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&EXIF_USER_COMMENT_TAG.to_le_bytes());
+    tiff.extend_from_slice(&7u16.to_le_bytes());
+    tiff.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+    tiff.extend_from_slice(&comment_offset.to_le_bytes());
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(&comment);
+    tiff
+}
+
+/// Find the user comment in a TIFF structure, following the EXIF IFD pointer.
+fn exif_read_user_comment(tiff: &[u8]) -> Option<String> {
+    let tiff = tiff.strip_prefix(JPEG_EXIF_PREFIX).unwrap_or(tiff);
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes: [u8; 2] = tiff.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes: [u8; 4] = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let find_entry = |ifd: usize, tag: u16| -> Option<usize> {
+        let count = read_u16(ifd)? as usize;
+        (0..count)
+            .map(|index| ifd + 2 + index * 12)
+            .find(|&entry| read_u16(entry) == Some(tag))
+    };
+
+    let ifd0 = read_u32(4)? as usize;
+    let exif_ifd = read_u32(find_entry(ifd0, EXIF_IFD_POINTER_TAG)? + 8)? as usize;
+    let entry = find_entry(exif_ifd, EXIF_USER_COMMENT_TAG)?;
+    let count = read_u32(entry + 4)? as usize;
+    let data = if count <= 4 {
+        tiff.get(entry + 8..entry + 8 + count)?
+    } else {
+        let offset = read_u32(entry + 8)? as usize;
+        tiff.get(offset..offset + count)?
+    };
+
+    if let Some(text) = data.strip_prefix(EXIF_UNICODE_PREFIX) {
+        let units = text
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+        Some(String::from_utf16_lossy(&units.collect::<Vec<_>>()))
+    } else {
+        let text = data.strip_prefix(EXIF_ASCII_PREFIX).unwrap_or(data);
+        Some(
+            String::from_utf8_lossy(text)
+                .trim_end_matches('\0')
+                .to_string(),
+        )
+    }
+}
+
+fn xmp_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xmp_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#10;", "\n")
+        .replace("&amp;", "&")
+}
+
+fn xmp_packet(text: &str) -> Vec<u8> {
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+         <rdf:Description rdf:about=\"\" xmlns:exif=\"http://ns.adobe.com/exif/1.0/\">\
+         <exif:UserComment><rdf:Alt>{XMP_VALUE_START}{}{XMP_VALUE_END}</rdf:Alt></exif:UserComment>\
+         </rdf:Description></rdf:RDF></x:xmpmeta>\
+         <?xpacket end=\"w\"?>",
+        xmp_escape(text)
+    )
+    .into_bytes()
+}
+
+fn xmp_read_user_comment(xmp: &[u8]) -> Option<String> {
+    let xmp = String::from_utf8_lossy(xmp);
+    let start = xmp.find(XMP_VALUE_START)? + XMP_VALUE_START.len();
+    let end = start + xmp[start..].find(XMP_VALUE_END)?;
+    Some(xmp_unescape(&xmp[start..end]))
+}
+
+/// Split a WebP file into its RIFF chunks.
+fn webp_chunks(bytes: &[u8]) -> Result<Vec<Chunk<'_>>, AihordeError> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        return Err(malformed("WebP"));
+    }
+    let mut chunks = Vec::new();
+    let mut offset = 12;
+    while offset + 8 <= bytes.len() {
+        let fourcc: [u8; 4] = bytes[offset..offset + 4].try_into().unwrap();
+        let length = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        let end = offset + 8 + length;
+        if end > bytes.len() {
+            return Err(malformed("WebP"));
+        }
+        chunks.push(Chunk {
+            kind: fourcc,
+            data: &bytes[offset + 8..end],
+            start: offset,
+            end,
+        });
+        offset = end + length % 2;
+    }
+    Ok(chunks)
+}
+
+fn webp_push_chunk(output: &mut Vec<u8>, fourcc: &[u8; 4], data: &[u8]) {
+    output.extend_from_slice(fourcc);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+/// Build the VP8X header of the extended WebP format for a simple WebP image.
+fn webp_extended_header(fourcc: &[u8; 4], data: &[u8]) -> Result<[u8; 10], AihordeError> {
+    let (width, height, alpha) = match fourcc {
+        b"VP8 " => {
+            if data.len() < 10 || data[3..6] != [0x9d, 0x01, 0x2a] {
+                return Err(malformed("WebP"));
+            }
+            let width = u16::from_le_bytes([data[6], data[7]]) & 0x3fff;
+            let height = u16::from_le_bytes([data[8], data[9]]) & 0x3fff;
+            (width as u32, height as u32, false)
+        }
+        b"VP8L" => {
+            if data.len() < 5 || data[0] != 0x2f {
+                return Err(malformed("WebP"));
+            }
+            let bits = u32::from_le_bytes(data[1..5].try_into().unwrap());
+            let width = (bits & 0x3fff) + 1;
+            let height = ((bits >> 14) & 0x3fff) + 1;
+            (width, height, bits >> 28 & 1 == 1)
+        }
+        _ => return Err(malformed("WebP")),
+    };
+    if width == 0 || height == 0 {
+        return Err(malformed("WebP"));
+    }
+    let mut header = [0u8; 10];
+    if alpha {
+        header[0] |= 0x10;
+    }
+    header[4..7].copy_from_slice(&(width - 1).to_le_bytes()[..3]);
+    header[7..10].copy_from_slice(&(height - 1).to_le_bytes()[..3]);
+    Ok(header)
+}
+
+fn webp_embed(bytes: &[u8], text: &str) -> Result<Vec<u8>, AihordeError> {
+    let chunks = webp_chunks(bytes)?;
+    let first = chunks.first().ok_or_else(|| malformed("WebP"))?;
+    let mut header: [u8; 10] = if &first.kind == b"VP8X" {
+        first
+            .data
+            .get(..10)
+            .and_then(|data| data.try_into().ok())
+            .ok_or_else(|| malformed("WebP"))?
+    } else {
+        webp_extended_header(&first.kind, first.data)?
+    };
+    // EXIF and XMP flags
+    header[0] |= 0x08 | 0x04;
+
+    let mut body = b"WEBP".to_vec();
+    webp_push_chunk(&mut body, b"VP8X", &header);
+    for chunk in &chunks {
+        if matches!(&chunk.kind, b"VP8X" | b"EXIF" | b"XMP ") {
+            continue;
+        }
+        webp_push_chunk(&mut body, &chunk.kind, chunk.data);
+    }
+    webp_push_chunk(&mut body, b"EXIF", &exif_user_comment(text));
+    webp_push_chunk(&mut body, b"XMP ", &xmp_packet(text));
+
+    let mut output = b"RIFF".to_vec();
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend_from_slice(&body);
+    Ok(output)
+}
+
+fn webp_read(bytes: &[u8]) -> Result<Option<String>, AihordeError> {
+    let chunks = webp_chunks(bytes)?;
+    let exif = chunks
+        .iter()
+        .filter(|chunk| &chunk.kind == b"EXIF")
+        .find_map(|chunk| exif_read_user_comment(chunk.data));
+    let xmp = || {
+        chunks
+            .iter()
+            .filter(|chunk| &chunk.kind == b"XMP ")
+            .find_map(|chunk| xmp_read_user_comment(chunk.data))
+    };
+    Ok(exif.or_else(xmp))
+}
+
+/// Split the header of a JPEG file into its segments, stopping at the image data.
+/// The marker is stored in the first byte of the chunk kind.
+fn jpeg_segments(bytes: &[u8]) -> Result<Vec<Chunk<'_>>, AihordeError> {
+    let mut segments = Vec::new();
+    let mut offset = 2;
+    while offset + 4 <= bytes.len() && bytes[offset] == 0xff {
+        let marker = bytes[offset + 1];
+        if marker == 0xda {
+            break;
+        }
+        let length = u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]) as usize;
+        let end = offset + 2 + length;
+        if length < 2 || end > bytes.len() {
+            return Err(malformed("JPEG"));
+        }
+        segments.push(Chunk {
+            kind: [marker, 0, 0, 0],
+            data: &bytes[offset + 4..end],
+            start: offset,
+            end,
+        });
+        offset = end;
+    }
+    Ok(segments)
+}
+
+fn jpeg_push_segment(output: &mut Vec<u8>, prefix: &[u8], data: &[u8]) -> Result<(), AihordeError> {
+    let length = prefix.len() + data.len() + 2;
+    let length = u16::try_from(length)
+        .map_err(|_| AihordeError::InvalidInput("Parameters are too long for JPEG".to_string()))?;
+    output.extend_from_slice(&[0xff, 0xe1]);
+    output.extend_from_slice(&length.to_be_bytes());
+    output.extend_from_slice(prefix);
+    output.extend_from_slice(data);
+    Ok(())
+}
+
+fn jpeg_embed(bytes: &[u8], text: &str) -> Result<Vec<u8>, AihordeError> {
+    let segments = jpeg_segments(bytes)?;
+    let is_metadata = |marker: u8, data: &[u8]| {
+        marker == 0xe1 && (data.starts_with(JPEG_EXIF_PREFIX) || data.starts_with(JPEG_XMP_PREFIX))
+    };
+
+    let mut output = bytes[..2].to_vec();
+    let mut rest = 2;
+    let mut inserted = false;
+    for Chunk {
+        kind: [marker, ..],
+        data,
+        start,
+        end,
+    } in segments
+    {
+        // Keep the JFIF header first, as required by its specification
+        if !inserted && marker != 0xe0 {
+            jpeg_push_segment(&mut output, JPEG_EXIF_PREFIX, &exif_user_comment(text))?;
+            jpeg_push_segment(&mut output, JPEG_XMP_PREFIX, &xmp_packet(text))?;
+            inserted = true;
+        }
+        if !is_metadata(marker, data) {
+            output.extend_from_slice(&bytes[start..end]);
+        }
+        rest = end;
+    }
+    if !inserted {
+        jpeg_push_segment(&mut output, JPEG_EXIF_PREFIX, &exif_user_comment(text))?;
+        jpeg_push_segment(&mut output, JPEG_XMP_PREFIX, &xmp_packet(text))?;
+    }
+    output.extend_from_slice(&bytes[rest..]);
+    Ok(output)
+}
+
+fn jpeg_read(bytes: &[u8]) -> Result<Option<String>, AihordeError> {
+    let segments = jpeg_segments(bytes)?;
+    let app1 = || segments.iter().filter(|segment| segment.kind[0] == 0xe1);
+    let exif = app1()
+        .find_map(|segment| exif_read_user_comment(segment.data.strip_prefix(JPEG_EXIF_PREFIX)?));
+    let xmp = || {
+        app1()
+            .find_map(|segment| xmp_read_user_comment(segment.data.strip_prefix(JPEG_XMP_PREFIX)?))
+    };
+    Ok(exif.or_else(xmp))
+}
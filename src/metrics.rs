@@ -0,0 +1,104 @@
+//! Usage metrics of [`AihordeClient`](crate::AihordeClient), reported through the `metrics` facade.
+//!
+//! Nothing is collected until a recorder is installed, for example `metrics-exporter-prometheus`
+//! to serve them to Prometheus. Call [`describe`] afterwards to register units and descriptions.
+//!
+//! | Metric | Type | Labels |
+//! |---|---|---|
+//! | `aihorde_requests_total` | counter | `endpoint`, `method`, `status` |
+//! | `aihorde_request_duration_seconds` | histogram | `endpoint`, `method` |
+//! | `aihorde_errors_total` | counter | `endpoint`, `code` |
+//! | `aihorde_kudos_spent` | histogram | |
+//! | `aihorde_queue_wait_seconds` | histogram | |
+//! | `aihorde_generation_duration_seconds` | histogram | `outcome` |
+
+#[cfg(feature = "rt-tokio")]
+use std::time::Duration;
+
+use ::metrics::{Unit, counter, describe_counter, describe_histogram, histogram};
+use reqwest::Response;
+
+use crate::errors::AihordeError;
+use crate::middleware::RequestInfo;
+
+pub const REQUESTS: &str = "aihorde_requests_total";
+pub const REQUEST_DURATION: &str = "aihorde_request_duration_seconds";
+pub const ERRORS: &str = "aihorde_errors_total";
+pub const KUDOS_SPENT: &str = "aihorde_kudos_spent";
+pub const QUEUE_WAIT: &str = "aihorde_queue_wait_seconds";
+pub const GENERATION_DURATION: &str = "aihorde_generation_duration_seconds";
+
+/// ### Register the units and descriptions of every metric with the installed recorder
+pub fn describe() {
+    describe_counter!(REQUESTS, "Requests that got a response, by status");
+    describe_histogram!(
+        REQUEST_DURATION,
+        Unit::Seconds,
+        "Time until the response headers arrived"
+    );
+    describe_counter!(
+        ERRORS,
+        "Failed requests, by `RequestErrorCode` or kind of failure"
+    );
+    describe_histogram!(
+        KUDOS_SPENT,
+        "Kudos of every accepted generation request but dry runs, the sum is the total spent"
+    );
+    describe_histogram!(
+        QUEUE_WAIT,
+        Unit::Seconds,
+        "Wait time estimated by the horde on every check of a request"
+    );
+    describe_histogram!(
+        GENERATION_DURATION,
+        Unit::Seconds,
+        "Time from submitting a generation until it was done or faulted"
+    );
+}
+
+/// The `code` label of a failed request.
+pub fn error_code(error: &AihordeError) -> String {
+    match error {
+        AihordeError::ApiError { code, .. } => code.to_string(),
+        AihordeError::UnexpectedHTTPCode { code, .. } => format!("HTTP{code}"),
+        AihordeError::RequestError(e) if e.is_timeout() => "Timeout".to_string(),
+        AihordeError::RequestError(_) => "Connection".to_string(),
+        AihordeError::JsonParseError(_) | AihordeError::JsonError(_) => {
+            "InvalidResponse".to_string()
+        }
+        _ => "Other".to_string(),
+    }
+}
+
+pub(crate) fn record_response(info: &RequestInfo, response: &Response) {
+    counter!(
+        REQUESTS,
+        "endpoint" => info.endpoint,
+        "method" => info.method.to_string(),
+        "status" => response.status().as_u16().to_string()
+    )
+    .increment(1);
+    histogram!(
+        REQUEST_DURATION,
+        "endpoint" => info.endpoint,
+        "method" => info.method.to_string()
+    )
+    .record(info.elapsed());
+}
+
+pub(crate) fn record_error(info: &RequestInfo, error: &AihordeError) {
+    counter!(ERRORS, "endpoint" => info.endpoint, "code" => error_code(error)).increment(1);
+}
+
+pub(crate) fn record_kudos(kudos: f64) {
+    histogram!(KUDOS_SPENT).record(kudos);
+}
+
+pub(crate) fn record_queue_wait(wait_time: u16) {
+    histogram!(QUEUE_WAIT).record(wait_time as f64);
+}
+
+#[cfg(feature = "rt-tokio")]
+pub(crate) fn record_generation(elapsed: Duration, outcome: &'static str) {
+    histogram!(GENERATION_DURATION, "outcome" => outcome).record(elapsed);
+}
@@ -0,0 +1,243 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use log::{Level, log};
+use reqwest::header::HeaderMap;
+use reqwest::{Method, Request, Response};
+use url::Url;
+
+use crate::errors::AihordeError;
+
+/// A request sent by an [`AihordeClient`](crate::AihordeClient).
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    /// The path of the API endpoint, with IDs as `{id}`, like `/generate/status/{id}`.
+    pub endpoint: &'static str,
+    pub method: Method,
+    pub url: Url,
+    /// When the request was handed to the middlewares.
+    pub started: Instant,
+}
+
+impl RequestInfo {
+    /// How long ago the request started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+}
+
+/// Hooks an [`AihordeClient`](crate::AihordeClient) runs around every request it sends.
+///
+/// Middlewares run in the order they were added to the client.
+pub trait Middleware: Send + Sync {
+    /// ### Called before a request is sent
+    /// The request can still be changed, for example to add headers.
+    #[allow(unused_variables)]
+    fn before_send(&self, info: &RequestInfo, request: &mut Request) {}
+
+    /// ### Called when the server answered, whatever the status
+    #[allow(unused_variables)]
+    fn after_response(&self, info: &RequestInfo, response: &Response) {}
+
+    /// ### Called when a request failed
+    /// This includes connection errors, error statuses and responses that could not be parsed.
+    #[allow(unused_variables)]
+    fn on_error(&self, info: &RequestInfo, error: &AihordeError) {}
+}
+
+/// The middlewares of a client, shared by its clones.
+#[derive(Clone, Default)]
+pub(crate) struct Middlewares(Vec<Arc<dyn Middleware>>);
+
+impl fmt::Debug for Middlewares {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Middlewares({})", self.0.len())
+    }
+}
+
+impl Middlewares {
+    pub(crate) fn push(&mut self, middleware: Arc<dyn Middleware>) {
+        self.0.push(middleware);
+    }
+
+    pub(crate) fn before_send(&self, info: &RequestInfo, request: &mut Request) {
+        for middleware in &self.0 {
+            middleware.before_send(info, request);
+        }
+    }
+
+    pub(crate) fn after_response(&self, info: &RequestInfo, response: &Response) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_response(info, response);
+        for middleware in &self.0 {
+            middleware.after_response(info, response);
+        }
+    }
+
+    pub(crate) fn on_error(&self, info: &RequestInfo, error: &AihordeError) {
+        #[cfg(feature = "metrics")]
+        crate::metrics::record_error(info, error);
+        for middleware in &self.0 {
+            middleware.on_error(info, error);
+        }
+    }
+}
+
+/// The headers, with the values of secret ones replaced by `***`.
+pub fn redact_headers(headers: &HeaderMap, secret: &HashSet<String>) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if secret.contains(name.as_str()) {
+                "***".to_string()
+            } else {
+                value.to_str().unwrap_or("<binary>").to_string()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Logs every request through the `log` crate, without leaking API keys.
+#[derive(Debug, Clone)]
+pub struct LoggingMiddleware {
+    level: Level,
+    secret_headers: HashSet<String>,
+}
+
+impl Default for LoggingMiddleware {
+    fn default() -> Self {
+        Self {
+            level: Level::Debug,
+            secret_headers: HashSet::from(["apikey".to_string(), "authorization".to_string()]),
+        }
+    }
+}
+
+impl LoggingMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ### Log at another level than `Debug`
+    /// Failed requests are always logged as warnings.
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// ### Redact another header
+    /// `apikey` and `authorization` are always redacted.
+    /// #### Arguments
+    /// * `name` - The name of the header, which is case-insensitive.
+    pub fn with_secret_header(mut self, name: &str) -> Self {
+        self.secret_headers.insert(name.to_ascii_lowercase());
+        self
+    }
+}
+
+impl Middleware for LoggingMiddleware {
+    fn before_send(&self, info: &RequestInfo, request: &mut Request) {
+        log!(
+            self.level,
+            "{} {} {:?}",
+            info.method,
+            info.url,
+            redact_headers(request.headers(), &self.secret_headers)
+        );
+    }
+
+    fn after_response(&self, info: &RequestInfo, response: &Response) {
+        log!(
+            self.level,
+            "{} {} -> {} in {:?}",
+            info.method,
+            info.endpoint,
+            response.status(),
+            info.elapsed()
+        );
+    }
+
+    fn on_error(&self, info: &RequestInfo, error: &AihordeError) {
+        log!(
+            Level::Warn,
+            "{} {} failed after {:?}: {error}",
+            info.method,
+            info.endpoint,
+            info.elapsed()
+        );
+    }
+}
+
+/// Response times of an endpoint.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct EndpointTiming {
+    /// Requests that got a response, whatever the status.
+    pub responses: u64,
+    /// Requests that failed, see [`Middleware::on_error`].
+    pub errors: u64,
+    pub total: Duration,
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl EndpointTiming {
+    /// The mean time until a response, if there was one.
+    pub fn average(&self) -> Option<Duration> {
+        (self.responses > 0).then(|| self.total / self.responses as u32)
+    }
+}
+
+/// Measures how long every endpoint takes to answer.
+/// Clones share their measurements, so keep one to read them after adding it to a client.
+#[derive(Debug, Clone, Default)]
+pub struct TimingMiddleware {
+    timings: Arc<Mutex<BTreeMap<String, EndpointTiming>>>,
+}
+
+impl TimingMiddleware {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, EndpointTiming>> {
+        self.timings
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// ### The measurements so far, by `METHOD /endpoint`
+    pub fn timings(&self) -> BTreeMap<String, EndpointTiming> {
+        self.lock().clone()
+    }
+
+    /// ### Forget all measurements
+    pub fn reset(&self) {
+        self.lock().clear();
+    }
+}
+
+impl Middleware for TimingMiddleware {
+    fn after_response(&self, info: &RequestInfo, _response: &Response) {
+        let elapsed = info.elapsed();
+        let mut timings = self.lock();
+        let timing = timings
+            .entry(format!("{} {}", info.method, info.endpoint))
+            .or_default();
+        if timing.responses == 0 || elapsed < timing.min {
+            timing.min = elapsed;
+        }
+        timing.max = timing.max.max(elapsed);
+        timing.total += elapsed;
+        timing.responses += 1;
+    }
+
+    fn on_error(&self, info: &RequestInfo, _error: &AihordeError) {
+        self.lock()
+            .entry(format!("{} {}", info.method, info.endpoint))
+            .or_default()
+            .errors += 1;
+    }
+}
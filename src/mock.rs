@@ -0,0 +1,1449 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use image::{ImageFormat, Rgb, RgbImage};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use url::Url;
+
+use crate::client::AihordeClient;
+use crate::consts::{DEFAULT_API_KEY, USERS_PER_PAGE};
+use crate::enums::{GenerationState, InterrogationType, ModelState, ModelType, RequestErrorCode};
+use crate::errors::AihordeError;
+use crate::models::{
+    ActiveModel, GenerationInputStable, GenerationPayloadKobold, GenerationPayloadStable,
+    GenerationStable, GenerationSubmitted, InterrogationPopFormPayload, InterrogationPopInput,
+    InterrogationPopPayload, InterrogationSubmitInput, KudosTransferInput, KudosTransferred,
+    ModelPayloadKobold, ModelPayloadStable, NoValidRequestFoundKobold, NoValidRequestFoundStable,
+    PopInputKobold, PopInputStable, RequestAsync, RequestStatusCheck, RequestStatusStable,
+    SubmitInputKobold, SubmitInputStable, UserActiveGenerations, UserDetails, ValidationError,
+};
+
+/// Limits how many requests the mock accepts in a time window.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MockRateLimit {
+    pub max_requests: usize,
+    pub window: Duration,
+}
+
+/// Behaviour of a [`MockHorde`].
+#[derive(Debug, Clone)]
+pub struct MockHordeConfig {
+    /// How many check or status calls it takes for a request to be done.
+    pub checks_until_done: u32,
+
+    /// The queue position reported while a request is waiting.
+    pub queue_position: u16,
+
+    /// The wait time reported for every remaining check, in seconds.
+    pub seconds_per_check: u16,
+
+    /// The kudos every requested image costs.
+    pub kudos_per_image: f64,
+
+    /// How long requests live before they are considered stale and deleted.
+    pub request_ttl: Duration,
+
+    /// Reject calls with `429 Too Many Requests` beyond this limit.
+    pub rate_limit: Option<MockRateLimit>,
+
+    /// Reject unknown API keys with `InvalidAPIKey` instead of treating them as anonymous.
+    pub strict_api_keys: bool,
+
+    /// The models reported by `/status/models`.
+    pub models: Vec<ActiveModel>,
+
+    /// Leave requests to workers popping and submitting jobs,
+    /// instead of finishing them after `checks_until_done` checks.
+    pub external_workers: bool,
+}
+
+impl Default for MockHordeConfig {
+    fn default() -> Self {
+        let model = |name: &str, model_type: ModelType, count: u64| ActiveModel {
+            name: Some(name.to_string()),
+            count: Some(count),
+            performance: Some(count as f64 * 1000.0),
+            queued: Some(0.0),
+            jobs: Some(0.0),
+            eta: Some(0),
+            model_type: Some(model_type),
+        };
+        Self {
+            checks_until_done: 2,
+            queue_position: 3,
+            seconds_per_check: 5,
+            kudos_per_image: 10.0,
+            request_ttl: Duration::from_secs(600),
+            rate_limit: None,
+            strict_api_keys: false,
+            models: vec![
+                model("stable_diffusion", ModelType::Image, 20),
+                model("AlbedoBase XL (SDXL)", ModelType::Image, 12),
+                model("Deliberate", ModelType::Image, 4),
+                model("koboldcpp/LLaMA2-13B-Psyfighter2", ModelType::Text, 8),
+            ],
+            external_workers: false,
+        }
+    }
+}
+
+/// An error the mock answers the next matching call with.
+#[derive(Debug, Clone)]
+struct InjectedError {
+    path: String,
+    status: StatusCode,
+    rc: RequestErrorCode,
+    message: String,
+}
+
+#[derive(Debug, Clone)]
+struct MockRequest {
+    owner: String,
+    input: GenerationInputStable,
+    kudos: f64,
+    checks: u32,
+    faulted: bool,
+    cancelled: bool,
+    created: Instant,
+    /// How many images were handed out to workers.
+    popped: u8,
+    /// The generations submitted by workers, by image index.
+    submitted: BTreeMap<u64, GenerationStable>,
+}
+
+impl MockRequest {
+    fn images(&self) -> u8 {
+        self.input
+            .params
+            .as_ref()
+            .and_then(|params| params.n)
+            .unwrap_or(1)
+    }
+
+    fn is_done(&self, config: &MockHordeConfig) -> bool {
+        if self.faulted || self.cancelled {
+            true
+        } else if config.external_workers {
+            self.submitted.len() >= self.images() as usize
+        } else {
+            self.checks >= config.checks_until_done
+        }
+    }
+}
+
+/// A text job queued with [`MockHorde::queue_text_job`].
+#[derive(Debug, Clone)]
+struct MockTextJob {
+    id: String,
+    model: String,
+    payload: ModelPayloadKobold,
+    popped: bool,
+    submission: Option<SubmitInputKobold>,
+}
+
+/// An interrogation form queued with [`MockHorde::queue_interrogation`].
+#[derive(Debug, Clone)]
+struct MockForm {
+    id: String,
+    form: InterrogationType,
+    source_image: String,
+    popped: bool,
+    submission: Option<InterrogationSubmitInput>,
+}
+
+#[derive(Debug)]
+struct MockState {
+    config: MockHordeConfig,
+    base_url: String,
+    users: Vec<(String, UserDetails)>,
+    requests: HashMap<String, MockRequest>,
+    request_counter: u64,
+    /// Jobs handed out to workers, as `(request id, image index)`.
+    jobs: HashMap<String, (String, u64)>,
+    job_counter: u64,
+    text_jobs: Vec<MockTextJob>,
+    forms: Vec<MockForm>,
+    /// Files uploaded to r2 links, by `{id}/{file}`.
+    uploads: HashMap<String, Vec<u8>>,
+    faults: usize,
+    errors: Vec<InjectedError>,
+    calls: VecDeque<Instant>,
+    call_log: Vec<String>,
+    /// How many `304 Not Modified` responses were sent.
+    not_modified: usize,
+}
+
+impl MockState {
+    fn user(&self, api_key: &str) -> Option<&UserDetails> {
+        self.users
+            .iter()
+            .find(|(key, _)| key == api_key)
+            .map(|(_, user)| user)
+    }
+
+    fn user_mut(&mut self, api_key: &str) -> Option<&mut UserDetails> {
+        self.users
+            .iter_mut()
+            .find(|(key, _)| key == api_key)
+            .map(|(_, user)| user)
+    }
+
+    fn active_requests(&self, api_key: &str) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .requests
+            .iter()
+            .filter(|(_, request)| request.owner == api_key && !request.is_done(&self.config))
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn user_details(&self, api_key: &str, user: &UserDetails) -> UserDetails {
+        UserDetails {
+            active_generations: Some(UserActiveGenerations {
+                image: Some(self.active_requests(api_key)),
+                text: Some(Vec::new()),
+                alchemy: Some(Vec::new()),
+            }),
+            ..user.clone()
+        }
+    }
+
+    fn next_job_id(&mut self) -> String {
+        self.job_counter += 1;
+        format!("00000000-0000-4000-9000-{:012}", self.job_counter)
+    }
+
+    fn expire_requests(&mut self) {
+        let ttl = self.config.request_ttl;
+        self.requests
+            .retain(|_, request| request.created.elapsed() < ttl);
+    }
+}
+
+fn error_response(
+    status: StatusCode,
+    rc: RequestErrorCode,
+    message: impl Into<String>,
+) -> Response {
+    let body = ValidationError {
+        message: Some(message.into()),
+        rc,
+    };
+    (status, Json(body)).into_response()
+}
+
+/// A JSON response with an ETag, or `304 Not Modified` when the client already has it.
+fn with_etag(state: &mut MockState, headers: &HeaderMap, body: &impl Serialize) -> Response {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    let etag = format!("\"{:08x}\"", crc32fast::hash(&body));
+    let cached = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').any(|tag| tag.trim() == etag));
+    if cached {
+        state.not_modified += 1;
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    (
+        [
+            (header::CONTENT_TYPE, "application/json".to_string()),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+fn api_key(headers: &HeaderMap) -> String {
+    headers
+        .get("apikey")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or(DEFAULT_API_KEY)
+        .to_string()
+}
+
+/// Deterministic fake image for a seed.
+fn fake_image(seed: u64) -> Vec<u8> {
+    let color = Rgb([(seed % 256) as u8, (seed / 256 % 256) as u8, 128]);
+    let image = RgbImage::from_pixel(64, 64, color);
+    let mut buffer = Cursor::new(Vec::new());
+    image
+        .write_to(&mut buffer, ImageFormat::WebP)
+        .expect("encoding a webp in memory cannot fail");
+    buffer.into_inner()
+}
+
+/// The seed the horde hands out for an image of a request.
+fn request_seed(request: &MockRequest, index: u64) -> u64 {
+    let params = request.input.params.clone().unwrap_or_default();
+    match params.image_seeds() {
+        Some(seeds) => seeds
+            .get(index as usize)
+            .map_or(1000 + index, |&seed| seed as u64),
+        // Random seeds, but reproducible ones
+        None => 1000 + index,
+    }
+}
+
+type SharedState = Arc<Mutex<MockState>>;
+
+fn lock(state: &SharedState) -> MutexGuard<'_, MockState> {
+    state
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Rate limiting and error injection, applied to every call.
+async fn intercept(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    {
+        let mut state = lock(&state);
+        state
+            .call_log
+            .push(format!("{} {}", request.method(), path));
+        if let Some(limit) = state.config.rate_limit.clone() {
+            let now = Instant::now();
+            while state
+                .calls
+                .front()
+                .is_some_and(|call| now.duration_since(*call) >= limit.window)
+            {
+                state.calls.pop_front();
+            }
+            if state.calls.len() >= limit.max_requests {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(serde_json::json!({"message": "Rate limit exceeded"})),
+                )
+                    .into_response();
+            }
+            state.calls.push_back(now);
+        }
+        if let Some(index) = state
+            .errors
+            .iter()
+            .position(|error| path.starts_with(&error.path))
+        {
+            let error = state.errors.remove(index);
+            return error_response(error.status, error.rc, error.message);
+        }
+    }
+    next.run(request).await
+}
+
+async fn find_user(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    let state = lock(&state);
+    let api_key = api_key(&headers);
+    match state.user(&api_key) {
+        Some(user) => Json(state.user_details(&api_key, user)).into_response(),
+        None if state.config.strict_api_keys => error_response(
+            StatusCode::UNAUTHORIZED,
+            RequestErrorCode::InvalidAPIKey,
+            "No user matching sent API Key. Have you remembered to register at https://aihorde.net/register ?",
+        ),
+        None => {
+            let anonymous = state.user(DEFAULT_API_KEY).cloned().unwrap_or_default();
+            Json(state.user_details(DEFAULT_API_KEY, &anonymous)).into_response()
+        }
+    }
+}
+
+async fn get_user(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Path(user_id): Path<String>,
+) -> Response {
+    let mut state = lock(&state);
+    let user_id = user_id.parse::<u64>().ok();
+    match state
+        .users
+        .iter()
+        .find(|(_, user)| user_id.is_some() && user.id == user_id)
+    {
+        Some((api_key, user)) => {
+            let details = state.user_details(api_key, user);
+            with_etag(&mut state, &headers, &details)
+        }
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            RequestErrorCode::UserNotFound,
+            "User not found",
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UsersQuery {
+    page: Option<usize>,
+    sort: Option<String>,
+}
+
+async fn get_users(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(query): Query<UsersQuery>,
+) -> Response {
+    let mut state = lock(&state);
+    let mut users: Vec<&UserDetails> = state.users.iter().map(|(_, user)| user).collect();
+    match query.sort.as_deref().unwrap_or("kudos") {
+        "kudos" => users.sort_by(|a, b| {
+            b.kudos
+                .unwrap_or_default()
+                .total_cmp(&a.kudos.unwrap_or_default())
+        }),
+        "age" => users.sort_by_key(|user| user.id),
+        sort => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                RequestErrorCode::BadRequest,
+                format!("Unknown sort: {sort}"),
+            );
+        }
+    }
+    // The first page is 1, but the horde also accepts 0 for it
+    let page = query.page.unwrap_or(1).max(1) - 1;
+    let users: Vec<UserDetails> = users
+        .into_iter()
+        .skip(page * USERS_PER_PAGE)
+        .take(USERS_PER_PAGE)
+        .cloned()
+        .collect();
+    with_etag(&mut state, &headers, &users)
+}
+
+async fn generate_async(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(input): Json<GenerationInputStable>,
+) -> Response {
+    let mut state = lock(&state);
+    state.expire_requests();
+    let api_key = api_key(&headers);
+    if input.prompt.trim().is_empty() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            RequestErrorCode::MissingPrompt,
+            "The generation prompt cannot be empty",
+        );
+    }
+    if state.user(&api_key).is_none() && state.config.strict_api_keys {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            RequestErrorCode::InvalidAPIKey,
+            "No user matching sent API Key.",
+        );
+    }
+
+    let images = input
+        .params
+        .as_ref()
+        .and_then(|params| params.n)
+        .unwrap_or(1);
+    let kudos = images as f64 * state.config.kudos_per_image;
+    if input.dry_run == Some(true) {
+        return Json(RequestAsync {
+            kudos: Some(kudos),
+            ..Default::default()
+        })
+        .into_response();
+    }
+
+    let user = state
+        .user(&api_key)
+        .or_else(|| state.user(DEFAULT_API_KEY))
+        .cloned()
+        .unwrap_or_default();
+    let concurrency = user.concurrency.unwrap_or(u64::MAX) as usize;
+    if state.active_requests(&api_key).len() >= concurrency {
+        return error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            RequestErrorCode::TooManyPrompts,
+            format!("Parallel requests ({concurrency}) exceeded user limit"),
+        );
+    }
+    let balance = user.kudos.unwrap_or_default();
+    if api_key != DEFAULT_API_KEY && balance < kudos {
+        return error_response(
+            StatusCode::FORBIDDEN,
+            RequestErrorCode::KudosUpfront,
+            "Not enough kudos for this request",
+        );
+    }
+    if let Some(user) = state.user_mut(&api_key) {
+        user.kudos = Some(balance - kudos);
+    }
+
+    state.request_counter += 1;
+    let id = format!("00000000-0000-4000-8000-{:012}", state.request_counter);
+    let faulted = state.faults > 0;
+    if faulted {
+        state.faults -= 1;
+    }
+    debug!("Mock horde accepted request {id}");
+    state.requests.insert(
+        id.clone(),
+        MockRequest {
+            owner: api_key,
+            input,
+            kudos,
+            checks: 0,
+            faulted,
+            cancelled: false,
+            created: Instant::now(),
+            popped: 0,
+            submitted: BTreeMap::new(),
+        },
+    );
+    (
+        StatusCode::ACCEPTED,
+        Json(RequestAsync {
+            id: Some(id),
+            kudos: Some(kudos),
+            ..Default::default()
+        }),
+    )
+        .into_response()
+}
+
+fn request_not_found(id: &str) -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        RequestErrorCode::RequestNotFound,
+        format!("Request with ID '{id}' not found"),
+    )
+}
+
+fn progress(state: &mut MockState, id: &str) -> Option<RequestStatusCheck> {
+    state.expire_requests();
+    let config = state.config.clone();
+    let request = state.requests.get_mut(id)?;
+    request.checks += 1;
+    let images = request.images();
+    let done = request.is_done(&config);
+    let remaining = if config.external_workers {
+        (images as usize).saturating_sub(request.submitted.len()) as u16
+    } else {
+        config.checks_until_done.saturating_sub(request.checks) as u16
+    };
+    let status = if config.external_workers && !request.faulted {
+        let finished = request.submitted.len() as u8;
+        RequestStatusCheck {
+            finished: Some(finished),
+            processing: Some(request.popped - finished),
+            waiting: Some(images - request.popped),
+            queue_position: Some(if request.popped == 0 {
+                config.queue_position
+            } else {
+                0
+            }),
+            ..Default::default()
+        }
+    } else if done {
+        RequestStatusCheck {
+            finished: Some(if request.faulted { 0 } else { images }),
+            processing: Some(0),
+            waiting: Some(0),
+            ..Default::default()
+        }
+    } else if request.checks == 1 {
+        RequestStatusCheck {
+            finished: Some(0),
+            processing: Some(0),
+            waiting: Some(images),
+            queue_position: Some(config.queue_position),
+            ..Default::default()
+        }
+    } else {
+        RequestStatusCheck {
+            finished: Some(0),
+            processing: Some(images),
+            waiting: Some(0),
+            ..Default::default()
+        }
+    };
+    Some(RequestStatusCheck {
+        restarted: Some(0),
+        done: Some(done && !request.faulted),
+        faulted: Some(request.faulted),
+        wait_time: Some(remaining * config.seconds_per_check),
+        queue_position: status.queue_position.or(Some(0)),
+        kudos: Some(if done { request.kudos as f32 } else { 0.0 }),
+        is_possible: Some(true),
+        ..status
+    })
+}
+
+fn full_status(state: &MockState, id: &str, check: RequestStatusCheck) -> RequestStatusStable {
+    let request = &state.requests[id];
+    if state.config.external_workers {
+        return RequestStatusStable {
+            generations: Some(request.submitted.values().cloned().collect()),
+            ..status_from_check(request, check)
+        };
+    }
+    let finished = check.finished.unwrap_or_default() as u64;
+    let generations = (0..finished)
+        .map(|index| {
+            let seed = request_seed(request, index);
+            let model = request
+                .input
+                .models
+                .as_ref()
+                .and_then(|models| models.first().cloned())
+                .unwrap_or_else(|| "stable_diffusion".to_string());
+            let img = if request.input.r2 == Some(true) {
+                format!("{}/r2/{id}/{index}.webp", state.base_url)
+            } else {
+                STANDARD.encode(fake_image(seed))
+            };
+            GenerationStable {
+                worker_id: Some("00000000-0000-4000-8000-00000000beef".to_string()),
+                worker_name: Some("Mock Worker".to_string()),
+                model: Some(model),
+                state: GenerationState::Ok,
+                img: Some(img),
+                seed: Some(seed.to_string()),
+                id: Some(format!("{id}-{index}")),
+                censored: Some(false),
+                gen_metadata: Some(Vec::new()),
+            }
+        })
+        .collect();
+    RequestStatusStable {
+        generations: Some(generations),
+        ..status_from_check(request, check)
+    }
+}
+
+fn status_from_check(request: &MockRequest, check: RequestStatusCheck) -> RequestStatusStable {
+    RequestStatusStable {
+        finished: check.finished,
+        processing: check.processing,
+        restarted: check.restarted,
+        waiting: check.waiting,
+        done: check.done,
+        faulted: check.faulted,
+        wait_time: check.wait_time,
+        queue_position: check.queue_position,
+        kudos: check.kudos,
+        is_possible: check.is_possible,
+        generations: None,
+        shared: request.input.shared,
+    }
+}
+
+async fn generation_check(State(state): State<SharedState>, Path(id): Path<String>) -> Response {
+    let mut state = lock(&state);
+    match progress(&mut state, &id) {
+        Some(check) => Json(check).into_response(),
+        None => request_not_found(&id),
+    }
+}
+
+async fn generation_status(
+    State(state): State<SharedState>,
+    method: Method,
+    Path(id): Path<String>,
+) -> Response {
+    let mut state = lock(&state);
+    if method == Method::DELETE
+        && let Some(request) = state.requests.get_mut(&id)
+    {
+        request.cancelled = true;
+    }
+    let Some(check) = progress(&mut state, &id) else {
+        return request_not_found(&id);
+    };
+    let status = full_status(&state, &id, check);
+    if method == Method::DELETE {
+        state.requests.remove(&id);
+    }
+    Json(status).into_response()
+}
+
+async fn transfer_kudos(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(input): Json<KudosTransferInput>,
+) -> Response {
+    let mut state = lock(&state);
+    let sender = api_key(&headers);
+    if sender == DEFAULT_API_KEY {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            RequestErrorCode::KudosTransferFromAnon,
+            "You cannot transfer Kudos from Anonymous",
+        );
+    }
+    let Some(balance) = state
+        .user(&sender)
+        .map(|user| user.kudos.unwrap_or_default())
+    else {
+        return error_response(
+            StatusCode::UNAUTHORIZED,
+            RequestErrorCode::InvalidAPIKey,
+            "No user matching sent API Key.",
+        );
+    };
+    let Some(receiver) = state
+        .users
+        .iter()
+        .find(|(_, user)| user.username.as_deref() == Some(input.username.as_str()))
+        .map(|(key, _)| key.clone())
+    else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            RequestErrorCode::UserNotFound,
+            format!("User not found: {}", input.username),
+        );
+    };
+    let rejection = if receiver == DEFAULT_API_KEY {
+        Some((
+            RequestErrorCode::KudosTransferToAnon,
+            "You cannot transfer Kudos to Anonymous",
+        ))
+    } else if receiver == sender {
+        Some((
+            RequestErrorCode::KudosTransferToSelf,
+            "You cannot transfer Kudos to yourself",
+        ))
+    } else if input.amount <= 0.0 {
+        Some((
+            RequestErrorCode::NegativeKudosTransfer,
+            "Amount has to be positive",
+        ))
+    } else if input.amount > balance {
+        Some((RequestErrorCode::KudosTransferNotEnough, "Not enough kudos"))
+    } else {
+        None
+    };
+    if let Some((rc, message)) = rejection {
+        return error_response(StatusCode::BAD_REQUEST, rc, message);
+    }
+    for (api_key, amount) in [(sender, -input.amount), (receiver, input.amount)] {
+        if let Some(user) = state.user_mut(&api_key) {
+            user.kudos = Some(user.kudos.unwrap_or_default() + amount);
+        }
+    }
+    Json(KudosTransferred {
+        transferred: Some(input.amount),
+    })
+    .into_response()
+}
+
+/// Workers have to belong to a registered user.
+fn reject_worker(state: &MockState, api_key: &str) -> Option<Response> {
+    if api_key == DEFAULT_API_KEY {
+        return Some(error_response(
+            StatusCode::FORBIDDEN,
+            RequestErrorCode::AnonForbiddenWorker,
+            "Anonymous is not allowed to run workers",
+        ));
+    }
+    if state.user(api_key).is_none() {
+        return Some(error_response(
+            StatusCode::UNAUTHORIZED,
+            RequestErrorCode::InvalidAPIKey,
+            "No user matching sent API Key.",
+        ));
+    }
+    None
+}
+
+async fn generate_pop(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(pop): Json<PopInputStable>,
+) -> Response {
+    let mut state = lock(&state);
+    state.expire_requests();
+    if let Some(response) = reject_worker(&state, &api_key(&headers)) {
+        return response;
+    }
+
+    let mut skipped = NoValidRequestFoundStable::default();
+    let mut ids: Vec<&String> = state.requests.keys().collect();
+    ids.sort();
+    let mut chosen = None;
+    for id in ids {
+        let request = &state.requests[id];
+        if request.faulted || request.cancelled || request.popped >= request.images() {
+            continue;
+        }
+        let params = request.input.params.clone().unwrap_or_default();
+        let serves_model = match (&request.input.models, &pop.models) {
+            (Some(wanted), Some(served)) if !wanted.is_empty() => {
+                wanted.iter().any(|model| served.contains(model))
+            }
+            _ => true,
+        };
+        let pixels = params.width.unwrap_or(512) as u64 * params.height.unwrap_or(512) as u64;
+        if !serves_model {
+            *skipped.models.get_or_insert(0) += 1;
+        } else if request.input.nsfw == Some(true) && pop.nsfw != Some(true) {
+            *skipped.nsfw.get_or_insert(0) += 1;
+        } else if pop.max_pixels.is_some_and(|max| pixels > max) {
+            *skipped.max_pixels.get_or_insert(0) += 1;
+        } else {
+            chosen = Some(id.clone());
+            break;
+        }
+    }
+    let Some(request_id) = chosen else {
+        return Json(GenerationPayloadStable {
+            skipped: Some(skipped),
+            ..Default::default()
+        })
+        .into_response();
+    };
+
+    let (input, first, amount) = {
+        let request = &state.requests[&request_id];
+        let available = request.images() - request.popped;
+        let amount = pop.amount.unwrap_or(1).clamp(1, available as u32) as u8;
+        (request.input.clone(), request.popped as u64, amount)
+    };
+    let mut ids = Vec::new();
+    let mut r2_uploads = Vec::new();
+    for index in first..first + amount as u64 {
+        let job_id = state.next_job_id();
+        r2_uploads.push(format!("{}/r2/{request_id}/{index}.webp", state.base_url));
+        state
+            .jobs
+            .insert(job_id.clone(), (request_id.clone(), index));
+        ids.push(job_id);
+    }
+    let request = state
+        .requests
+        .get_mut(&request_id)
+        .expect("the chosen request exists");
+    request.popped += amount;
+    let seed = request_seed(request, first);
+    debug!("Mock horde handed out {} job(s) of {request_id}", ids.len());
+
+    let params = input.params.unwrap_or_default();
+    let payload = ModelPayloadStable {
+        sampler_name: params.sampler_name,
+        cfg_scale: params.cfg_scale,
+        denoising_strength: params.denoising_strength,
+        hires_fix_denoising_strength: params.hires_fix_denoising_strength,
+        seed: Some(seed.to_string()),
+        height: params.height,
+        width: params.width,
+        seed_variation: params.seed_variation,
+        post_processing: params.post_processing,
+        karras: params.karras,
+        tiling: params.tiling,
+        hires_fix: params.hires_fix,
+        clip_skip: params.clip_skip,
+        control_type: params.control_type,
+        image_is_control: params.image_is_control,
+        return_control_map: params.return_control_map,
+        facefixer_strength: params.facefixer_strength,
+        loras: params.loras,
+        tis: params.tis,
+        special: params.special,
+        workflow: params.workflow,
+        transparent: params.transparent,
+        prompt: Some(input.prompt),
+        ddim_steps: params.steps,
+        n_iter: Some(amount),
+        use_nsfw_censor: Some(input.censor_nsfw == Some(true)),
+    };
+    Json(GenerationPayloadStable {
+        payload: Some(payload),
+        id: ids.first().cloned(),
+        ids: Some(ids),
+        skipped: Some(skipped),
+        model: input
+            .models
+            .and_then(|models| models.first().cloned())
+            .or_else(|| pop.models.and_then(|models| models.first().cloned())),
+        source_image: input.source_image,
+        source_processing: input.source_processing,
+        source_mask: input.source_mask,
+        extra_source_images: input.extra_source_images,
+        r2_upload: r2_uploads.first().cloned(),
+        r2_uploads: Some(r2_uploads),
+        ttl: Some(state.config.request_ttl.as_secs()),
+    })
+    .into_response()
+}
+
+async fn generate_submit(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(submit): Json<SubmitInputStable>,
+) -> Response {
+    let mut state = lock(&state);
+    let api_key = api_key(&headers);
+    if let Some(response) = reject_worker(&state, &api_key) {
+        return response;
+    }
+    let Some((request_id, index)) = state.jobs.get(&submit.id).cloned() else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            RequestErrorCode::InvalidJobID,
+            "Processing Job with ID not found",
+        );
+    };
+    let base_url = state.base_url.clone();
+    let reward = state.config.kudos_per_image;
+    let Some(request) = state.requests.get_mut(&request_id) else {
+        return request_not_found(&request_id);
+    };
+    if request.submitted.contains_key(&index) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            RequestErrorCode::DuplicateGen,
+            "Processing Job with ID has already been submitted",
+        );
+    }
+    let generation_state = submit.state.unwrap_or(GenerationState::Ok);
+    let img = match submit.generation.as_deref() {
+        Some("R2") => Some(format!("{base_url}/r2/{request_id}/{index}.webp")),
+        generation => generation.map(str::to_string),
+    };
+    let model = request
+        .input
+        .models
+        .as_ref()
+        .and_then(|models| models.first().cloned());
+    request.submitted.insert(
+        index,
+        GenerationStable {
+            worker_id: Some(format!("worker-{api_key}")),
+            worker_name: Some("Mock Worker".to_string()),
+            model,
+            censored: submit
+                .censored
+                .or(Some(generation_state == GenerationState::Censored)),
+            state: generation_state,
+            img,
+            seed: submit.seed.map(|seed| seed.to_string()),
+            id: Some(submit.id.clone()),
+            gen_metadata: submit.gen_metadata,
+        },
+    );
+    if let Some(user) = state.user_mut(&api_key) {
+        user.kudos = Some(user.kudos.unwrap_or_default() + reward);
+    }
+    Json(GenerationSubmitted {
+        reward: Some(reward),
+    })
+    .into_response()
+}
+
+async fn generate_text_pop(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(pop): Json<PopInputKobold>,
+) -> Response {
+    let mut state = lock(&state);
+    if let Some(response) = reject_worker(&state, &api_key(&headers)) {
+        return response;
+    }
+    let ttl = state.config.request_ttl.as_secs();
+    let mut skipped = NoValidRequestFoundKobold::default();
+    for job in state.text_jobs.iter_mut().filter(|job| !job.popped) {
+        let exceeds = |wanted: Option<u32>, max: Option<u32>| matches!((wanted, max), (Some(wanted), Some(max)) if wanted > max);
+        if pop
+            .models
+            .as_ref()
+            .is_some_and(|models| !models.contains(&job.model))
+        {
+            *skipped.models.get_or_insert(0) += 1;
+        } else if exceeds(job.payload.max_length, pop.max_length) {
+            *skipped.max_length.get_or_insert(0) += 1;
+        } else if exceeds(job.payload.max_context_length, pop.max_context_length) {
+            *skipped.max_context_length.get_or_insert(0) += 1;
+        } else {
+            job.popped = true;
+            debug!("Mock horde handed out text job {}", job.id);
+            return Json(GenerationPayloadKobold {
+                payload: Some(job.payload.clone()),
+                id: Some(job.id.clone()),
+                ids: Some(vec![job.id.clone()]),
+                skipped: Some(skipped),
+                softprompt: None,
+                model: Some(job.model.clone()),
+                ttl: Some(ttl),
+            })
+            .into_response();
+        }
+    }
+    Json(GenerationPayloadKobold {
+        skipped: Some(skipped),
+        ..Default::default()
+    })
+    .into_response()
+}
+
+async fn generate_text_submit(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(submit): Json<SubmitInputKobold>,
+) -> Response {
+    let mut state = lock(&state);
+    let api_key = api_key(&headers);
+    if let Some(response) = reject_worker(&state, &api_key) {
+        return response;
+    }
+    let reward = state.config.kudos_per_image;
+    let Some(job) = state
+        .text_jobs
+        .iter_mut()
+        .find(|job| job.popped && job.id == submit.id)
+    else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            RequestErrorCode::InvalidJobID,
+            "Processing Job with ID not found",
+        );
+    };
+    if job.submission.is_some() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            RequestErrorCode::DuplicateGen,
+            "Processing Job with ID has already been submitted",
+        );
+    }
+    job.submission = Some(submit);
+    if let Some(user) = state.user_mut(&api_key) {
+        user.kudos = Some(user.kudos.unwrap_or_default() + reward);
+    }
+    Json(GenerationSubmitted {
+        reward: Some(reward),
+    })
+    .into_response()
+}
+
+async fn interrogate_pop(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(pop): Json<InterrogationPopInput>,
+) -> Response {
+    let mut state = lock(&state);
+    if let Some(response) = reject_worker(&state, &api_key(&headers)) {
+        return response;
+    }
+    let base_url = state.base_url.clone();
+    let amount = pop.amount.unwrap_or(1).max(1) as usize;
+    let forms: Vec<InterrogationPopFormPayload> = state
+        .forms
+        .iter_mut()
+        .filter(|form| {
+            !form.popped
+                && pop
+                    .forms
+                    .as_ref()
+                    .is_none_or(|forms| forms.contains(&form.form))
+        })
+        .take(amount)
+        .map(|form| {
+            form.popped = true;
+            debug!("Mock horde handed out interrogation form {}", form.id);
+            InterrogationPopFormPayload {
+                id: Some(form.id.clone()),
+                form: Some(form.form.clone()),
+                payload: None,
+                source_image: Some(form.source_image.clone()),
+                r2_upload: form
+                    .form
+                    .produces_image()
+                    .then(|| format!("{base_url}/r2/{}/result.webp", form.id)),
+            }
+        })
+        .collect();
+    Json(InterrogationPopPayload {
+        forms: Some(forms),
+        skipped: Some(Default::default()),
+    })
+    .into_response()
+}
+
+async fn interrogate_submit(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Json(submit): Json<InterrogationSubmitInput>,
+) -> Response {
+    let mut state = lock(&state);
+    let api_key = api_key(&headers);
+    if let Some(response) = reject_worker(&state, &api_key) {
+        return response;
+    }
+    let reward = state.config.kudos_per_image;
+    let Some(form) = state
+        .forms
+        .iter_mut()
+        .find(|form| form.popped && form.id == submit.id)
+    else {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            RequestErrorCode::InvalidJobID,
+            "Interrogation Form with ID not found",
+        );
+    };
+    if form.submission.is_some() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            RequestErrorCode::DuplicateGen,
+            "Interrogation Form with ID has already been submitted",
+        );
+    }
+    form.submission = Some(submit);
+    if let Some(user) = state.user_mut(&api_key) {
+        user.kudos = Some(user.kudos.unwrap_or_default() + reward);
+    }
+    Json(GenerationSubmitted {
+        reward: Some(reward),
+    })
+    .into_response()
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelsQuery {
+    model_type: Option<String>,
+    min_count: Option<u64>,
+    max_count: Option<u64>,
+    state: Option<String>,
+}
+
+async fn active_models(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Query(query): Query<ModelsQuery>,
+) -> Response {
+    let mut state = lock(&state);
+    // The client sends enum values JSON-encoded
+    let parse = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(|value| value.trim_matches('"').to_string())
+    };
+    let model_type = parse(&query.model_type)
+        .map(|value| serde_json::from_value::<ModelType>(value.into()))
+        .transpose();
+    let model_state = parse(&query.state)
+        .map(|value| serde_json::from_value::<ModelState>(value.into()))
+        .transpose();
+    let (Ok(model_type), Ok(_)) = (model_type, model_state) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            RequestErrorCode::BadRequest,
+            "Invalid model filter",
+        );
+    };
+    let models: Vec<ActiveModel> = state
+        .config
+        .models
+        .iter()
+        .filter(|model| {
+            let count = model.count.unwrap_or_default();
+            model_type
+                .as_ref()
+                .is_none_or(|model_type| model.model_type.as_ref() == Some(model_type))
+                && query.min_count.is_none_or(|min| count >= min)
+                && query.max_count.is_none_or(|max| count <= max)
+        })
+        .cloned()
+        .collect();
+    with_etag(&mut state, &headers, &models)
+}
+
+async fn r2_download(
+    State(state): State<SharedState>,
+    Path((id, file)): Path<(String, String)>,
+) -> Response {
+    let state = lock(&state);
+    if let Some(upload) = state.uploads.get(&format!("{id}/{file}")) {
+        return ([(header::CONTENT_TYPE, "image/webp")], upload.clone()).into_response();
+    }
+    let index = file.trim_end_matches(".webp").parse::<u64>();
+    match (state.requests.get(&id), index) {
+        (Some(request), Ok(index)) => (
+            [(header::CONTENT_TYPE, "image/webp")],
+            fake_image(request_seed(request, index)),
+        )
+            .into_response(),
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Stands in for a presigned S3 PUT link, which only accepts the signed content type.
+async fn r2_upload(
+    State(state): State<SharedState>,
+    Path((id, file)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    if content_type != Some("image/webp") {
+        return (
+            StatusCode::FORBIDDEN,
+            "SignatureDoesNotMatch: unexpected Content-Type",
+        )
+            .into_response();
+    }
+    debug!(
+        "Mock horde received an upload of {} bytes to {id}/{file}",
+        body.len()
+    );
+    lock(&state)
+        .uploads
+        .insert(format!("{id}/{file}"), body.to_vec());
+    StatusCode::OK.into_response()
+}
+
+/// An in-process mock of the AI Horde API, serving deterministic fake data.
+///
+/// Implements the endpoints used by [`AihordeClient`], so it can be used to test
+/// code using the client without network access.
+pub struct MockHorde {
+    state: SharedState,
+    base_url: Url,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl MockHorde {
+    /// ### Start a mock horde with the default configuration
+    pub async fn start() -> Result<Self, AihordeError> {
+        Self::with_config(MockHordeConfig::default()).await
+    }
+
+    /// ### Start a mock horde listening on a free local port
+    pub async fn with_config(config: MockHordeConfig) -> Result<Self, AihordeError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let local_addr: SocketAddr = listener.local_addr()?;
+        let base_url = Url::parse(&format!("http://{local_addr}/api/v2"))?;
+        let anonymous = UserDetails {
+            username: Some("Anonymous#0".to_string()),
+            id: Some(0),
+            kudos: Some(0.0),
+            concurrency: Some(10),
+            ..Default::default()
+        };
+        let state = Arc::new(Mutex::new(MockState {
+            config,
+            base_url: base_url.to_string(),
+            users: vec![(DEFAULT_API_KEY.to_string(), anonymous)],
+            requests: HashMap::new(),
+            request_counter: 0,
+            jobs: HashMap::new(),
+            job_counter: 0,
+            text_jobs: Vec::new(),
+            forms: Vec::new(),
+            uploads: HashMap::new(),
+            faults: 0,
+            errors: Vec::new(),
+            calls: VecDeque::new(),
+            call_log: Vec::new(),
+            not_modified: 0,
+        }));
+
+        let api = Router::new()
+            .route("/find_user", get(find_user))
+            .route("/users", get(get_users))
+            .route("/users/{user_id}", get(get_user))
+            .route("/generate/async", axum::routing::post(generate_async))
+            .route("/generate/check/{id}", get(generation_check))
+            .route("/generate/pop", axum::routing::post(generate_pop))
+            .route("/generate/submit", axum::routing::post(generate_submit))
+            .route("/generate/text/pop", axum::routing::post(generate_text_pop))
+            .route(
+                "/generate/text/submit",
+                axum::routing::post(generate_text_submit),
+            )
+            .route(
+                "/generate/status/{id}",
+                get(generation_status).delete(generation_status),
+            )
+            .route("/interrogate/pop", axum::routing::post(interrogate_pop))
+            .route(
+                "/interrogate/submit",
+                axum::routing::post(interrogate_submit),
+            )
+            .route("/kudos/transfer", axum::routing::post(transfer_kudos))
+            .route("/status/models", get(active_models))
+            .route("/r2/{id}/{file}", get(r2_download).put(r2_upload));
+        let router = Router::new()
+            .nest("/api/v2", api)
+            .layer(middleware::from_fn_with_state(state.clone(), intercept))
+            .with_state(state.clone());
+
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let result = axum::serve(listener, router)
+                .with_graceful_shutdown(async {
+                    let _ = signal.await;
+                })
+                .await;
+            if let Err(e) = result {
+                warn!("Mock horde stopped: {e}");
+            }
+        });
+        debug!("Mock horde listening on {base_url}");
+        Ok(Self {
+            state,
+            base_url,
+            shutdown: Some(shutdown),
+            task: Some(task),
+        })
+    }
+
+    /// The base URL to pass to [`AihordeClient::new`].
+    pub fn base_url(&self) -> Url {
+        self.base_url.clone()
+    }
+
+    /// ### Create a client talking to this mock with the anonymous API key
+    pub fn client(&self) -> AihordeClient {
+        AihordeClient::new(None, Some(self.base_url()), None)
+    }
+
+    /// ### Create a client talking to this mock with the given API key
+    pub fn client_with_key(&self, api_key: &str) -> AihordeClient {
+        AihordeClient::new(Some(api_key.to_string()), Some(self.base_url()), None)
+    }
+
+    /// ### Register a user
+    /// Users without an ID get the next free one.
+    pub fn add_user(&self, api_key: &str, mut user: UserDetails) {
+        let mut state = lock(&self.state);
+        if user.id.is_none() {
+            user.id = Some(state.users.len() as u64);
+        }
+        state.users.retain(|(key, _)| key != api_key);
+        state.users.push((api_key.to_string(), user));
+    }
+
+    /// ### The current details of the user owning `api_key`
+    pub fn user(&self, api_key: &str) -> Option<UserDetails> {
+        lock(&self.state).user(api_key).cloned()
+    }
+
+    /// ### Change the configuration of the running mock
+    pub fn configure(&self, configure: impl FnOnce(&mut MockHordeConfig)) {
+        configure(&mut lock(&self.state).config);
+    }
+
+    /// ### Make the next `count` submitted requests fault
+    pub fn fault_next_requests(&self, count: usize) {
+        lock(&self.state).faults += count;
+    }
+
+    /// ### Answer the next call whose path starts with `path` with an error
+    /// `path` is relative to the API root, e.g. `/generate/async`.
+    pub fn fail_next(&self, path: &str, status: u16, rc: RequestErrorCode, message: &str) {
+        let path = format!("{}{}", self.base_url.path(), path);
+        lock(&self.state).errors.push(InjectedError {
+            path,
+            status: StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            rc,
+            message: message.to_string(),
+        });
+    }
+
+    /// ### Queue a text job for workers to pop
+    /// Returns the UUID of the job.
+    pub fn queue_text_job(&self, model: &str, payload: ModelPayloadKobold) -> String {
+        let mut state = lock(&self.state);
+        let id = state.next_job_id();
+        state.text_jobs.push(MockTextJob {
+            id: id.clone(),
+            model: model.to_string(),
+            payload,
+            popped: false,
+            submission: None,
+        });
+        id
+    }
+
+    /// ### What a worker submitted for a text job
+    pub fn text_submission(&self, job_id: &str) -> Option<SubmitInputKobold> {
+        lock(&self.state)
+            .text_jobs
+            .iter()
+            .find(|job| job.id == job_id)
+            .and_then(|job| job.submission.clone())
+    }
+
+    /// ### Queue an interrogation form for alchemists to pop
+    /// Returns the UUID of the form.
+    pub fn queue_interrogation(&self, form: InterrogationType, source_image: &str) -> String {
+        let mut state = lock(&self.state);
+        let id = state.next_job_id();
+        state.forms.push(MockForm {
+            id: id.clone(),
+            form,
+            source_image: source_image.to_string(),
+            popped: false,
+            submission: None,
+        });
+        id
+    }
+
+    /// ### What an alchemist submitted for an interrogation form
+    pub fn interrogation_submission(&self, form_id: &str) -> Option<InterrogationSubmitInput> {
+        lock(&self.state)
+            .forms
+            .iter()
+            .find(|form| form.id == form_id)
+            .and_then(|form| form.submission.clone())
+    }
+
+    /// ### A file uploaded to an r2 upload link of this mock
+    pub fn upload(&self, upload_url: &str) -> Option<Vec<u8>> {
+        let prefix = format!("{}/r2/", self.base_url);
+        let key = upload_url.strip_prefix(&prefix)?;
+        lock(&self.state).uploads.get(key).cloned()
+    }
+
+    /// ### Every call received so far, as `METHOD /path`
+    pub fn calls(&self) -> Vec<String> {
+        lock(&self.state).call_log.clone()
+    }
+
+    /// ### How many requests were answered with `304 Not Modified`
+    pub fn not_modified_responses(&self) -> usize {
+        lock(&self.state).not_modified
+    }
+
+    /// ### Stop the mock gracefully
+    pub async fn shutdown(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for MockHorde {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
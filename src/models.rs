@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 
 use crate::enums::{
-    ControlType, GenerationState, InjectTi, MetadataType, MetadataValue, ModelType, PostProcessing, RequestErrorCode, RequestWarningCode, SamplerName, SourceProcessing, StyleType, Workflow
+    ControlType, GenerationState, InjectTi, InterrogationType, MetadataType, MetadataValue, ModelType, PostProcessing, RequestErrorCode, RequestState, RequestWarningCode, SamplerName, SourceProcessing, StyleType, Workflow
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -588,6 +588,101 @@ pub struct GenerationStable {
     pub gen_metadata: Option<Vec<GenerationMetadataStable>>,
 }
 
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct GenerationKobold {
+    /// The UUID of the worker which generated this text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_id: Option<String>,
+
+    /// The name of the worker which generated this text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_name: Option<String>,
+
+    /// The model which generated this text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// The state of this generation.
+    pub state: GenerationState,
+
+    /// The generated text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+
+    /// The seed which generated this text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    /// The ID for this generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gen_metadata: Option<Vec<GenerationMetadataStable>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct InterrogationFormStatus {
+    /// The name of this interrogation form.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form: Option<InterrogationType>,
+
+    /// The state of this interrogation form.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<RequestState>,
+
+    /// The result of this interrogation form, depending on its type.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Map<String, Value>>,
+}
+
+/// The payload the horde POSTs to the `webhook` of an image request after each delivered generation.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct ImageWebhookPayload {
+    /// The UUID of the request this generation belongs to.
+    pub request: String,
+
+    /// The kudos consumed by this generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kudos: Option<f64>,
+
+    #[serde(flatten)]
+    pub generation: GenerationStable,
+}
+
+/// The payload the horde POSTs to the `webhook` of a text request after each delivered generation.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct TextWebhookPayload {
+    /// The UUID of the request this generation belongs to.
+    pub request: String,
+
+    /// The kudos consumed by this generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kudos: Option<f64>,
+
+    #[serde(flatten)]
+    pub generation: GenerationKobold,
+}
+
+/// The payload the horde POSTs to the `webhook` of an interrogation request after each finished form.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct AlchemyWebhookPayload {
+    /// The UUID of the interrogation request this form belongs to.
+    pub request: String,
+
+    /// The ID of this form.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    #[serde(flatten)]
+    pub form: InterrogationFormStatus,
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct RequestStatusCheck {
@@ -715,4 +810,706 @@ pub struct ActiveModel {
     /// The model type (text or image).
     #[serde(skip_serializing_if = "Option::is_none")]
     pub model_type: Option<ModelType>,
-}
\ No newline at end of file
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct PopInputStable {
+    /// The Name of the Worker.
+    pub name: String,
+
+    /// The worker will pick up any request from these users as a priority.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority_usernames: Option<Vec<String>>,
+
+    /// Whether this worker can generate NSFW requests or not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nsfw: Option<bool>,
+
+    /// Which models this worker is serving.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models: Option<Vec<String>>,
+
+    /// The worker name, version and website.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_agent: Option<String>,
+
+    /// How many threads this worker is running. This is used to accurately the current power available in the horde.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+
+    /// If True, this worker will only pick up requests where the owner has the required kudos to consume already available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_upfront_kudos: Option<bool>,
+
+    /// How many jobs to pop at the same time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u32>,
+
+    /// Marks the worker as extra slow.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_slow_worker: Option<bool>,
+
+    /// If True, This worker will not pick up jobs with more steps than the average allowed for that model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_max_steps: Option<bool>,
+
+    /// The maximum amount of pixels this worker can generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pixels: Option<u64>,
+
+    /// Words which, when detected will refuse to pick up any jobs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blacklist: Option<Vec<String>>,
+
+    /// If True, this worker will pick up img2img requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_img2img: Option<bool>,
+
+    /// If True, this worker will pick up inpainting/outpainting requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_painting: Option<bool>,
+
+    /// If True, this worker will pick up img2img requests coming from clients with an unsafe IP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_unsafe_ipaddr: Option<bool>,
+
+    /// If True, this worker will pick up requests requesting post-processing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_post_processing: Option<bool>,
+
+    /// If True, this worker will pick up requests requesting ControlNet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_controlnet: Option<bool>,
+
+    /// If True, this worker will pick up requests requesting SDXL ControlNet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_sdxl_controlnet: Option<bool>,
+
+    /// If True, this worker will pick up requests requesting LoRas.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_lora: Option<bool>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct ModelPayloadStable {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampler_name: Option<SamplerName>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cfg_scale: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub denoising_strength: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hires_fix_denoising_strength: Option<f32>,
+
+    /// The seed to use to generate this request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<String>,
+
+    /// The height of the image to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u16>,
+
+    /// The width of the image to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u16>,
+
+    /// If passed with multiple n, the provided seed will be incremented every time by this value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed_variation: Option<u16>,
+
+    /// The list of post-processors to apply to the image, in the order to be applied.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_processing: Option<Vec<PostProcessing>>,
+
+    /// Set to True to enable karras noise scheduling tweaks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub karras: Option<bool>,
+
+    /// Set to True to create images that stitch together seamlessly.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tiling: Option<bool>,
+
+    /// Set to True to process the image at base resolution before upscaling and re-processing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hires_fix: Option<bool>,
+
+    /// The number of CLIP language processor layers to skip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip_skip: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub control_type: Option<ControlType>,
+
+    /// Set to True if the image submitted is a pre-generated control map for ControlNet use.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_is_control: Option<bool>,
+
+    /// Set to True if you want the ControlNet map returned instead of a generated image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub return_control_map: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facefixer_strength: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loras: Option<Vec<ModelPayloadLorasStable>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tis: Option<Vec<ModelPayloadTextualInversionsStable>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub special: Option<ModelSpecialPayloadStable>,
+
+    /// Explicitly specify the horde-engine workflow to use.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<Workflow>,
+
+    /// Set to True to generate the image using Layer Diffuse, creating an image with a transparent background.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transparent: Option<bool>,
+
+    /// The prompt which will be sent to Stable Diffusion to generate an image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ddim_steps: Option<u16>,
+
+    /// The amount of images to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n_iter: Option<u8>,
+
+    /// When true will apply NSFW censoring model on the generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_nsfw_censor: Option<bool>,
+}
+
+/// Why a worker did not receive a job, as counts of the requests skipped for each reason.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct NoValidRequestFoundStable {
+    /// How many waiting requests were skipped because they demanded a specific worker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_id: Option<u64>,
+
+    /// How many waiting requests were skipped because they required higher performance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub performance: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a nsfw generation which this worker does not provide.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nsfw: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a generation with a word that this worker does not accept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blacklist: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a trusted worker which this worker is not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub untrusted: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a different model than what this worker provides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models: Option<u64>,
+
+    /// How many waiting requests were skipped because they require a higher version of the bridge than this worker is running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_version: Option<u64>,
+
+    /// How many waiting requests were skipped because the user didn't have enough kudos when this worker requires upfront kudos.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kudos: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a higher size than this worker provides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_pixels: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a higher step count that the worker wants.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step_count: Option<u64>,
+
+    /// How many waiting requests were skipped because they came from an unsafe IP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unsafe_ip: Option<u64>,
+
+    /// How many waiting requests were skipped because they requested img2img.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub img2img: Option<u64>,
+
+    /// How many waiting requests were skipped because they requested inpainting/outpainting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub painting: Option<u64>,
+
+    /// How many waiting requests were skipped because they requested post-processing.
+    #[serde(rename = "post-processing", skip_serializing_if = "Option::is_none")]
+    pub post_processing: Option<u64>,
+
+    /// How many waiting requests were skipped because they requested loras.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lora: Option<u64>,
+
+    /// How many waiting requests were skipped because they requested a controlnet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub controlnet: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct GenerationPayloadStable {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<ModelPayloadStable>,
+
+    /// The UUID for this image generation. Empty when no job was available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// The UUIDs of all image generations in this batch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<NoValidRequestFoundStable>,
+
+    /// Which of the available models to use for this request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// The Base64-encoded webp or the download URL of the source image for img2img.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_image: Option<String>,
+
+    /// If source_image is provided, specifies how to process it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_processing: Option<SourceProcessing>,
+
+    /// If img_processing is set to 'inpainting' or 'outpainting', this parameter can be optionally provided as the mask of the areas to inpaint.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_mask: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_source_images: Option<Vec<ExtraSourceImage>>,
+
+    /// The r2 upload link to use to upload this image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r2_upload: Option<String>,
+
+    /// The r2 upload links for each image of this batch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r2_uploads: Option<Vec<String>>,
+
+    /// The amount of seconds before this job is considered stale and aborted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct SubmitInputStable {
+    /// The UUID of this generation.
+    pub id: String,
+
+    /// The Base64-encoded webp of the generated image, or "R2" when it was uploaded to the r2 upload link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<String>,
+
+    /// The state of this generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<GenerationState>,
+
+    /// The seed for this generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    /// If True, this resulting image has been censored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub censored: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gen_metadata: Option<Vec<GenerationMetadataStable>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct GenerationSubmitted {
+    /// The amount of kudos gained for submitting this request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reward: Option<f64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct PopInputKobold {
+    /// The Name of the Worker.
+    pub name: String,
+
+    /// The worker will pick up any request from these users as a priority.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority_usernames: Option<Vec<String>>,
+
+    /// Whether this worker can generate NSFW requests or not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nsfw: Option<bool>,
+
+    /// Which models this worker is serving.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models: Option<Vec<String>>,
+
+    /// The worker name, version and website.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_agent: Option<String>,
+
+    /// How many threads this worker is running. This is used to accurately the current power available in the horde.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+
+    /// If True, this worker will only pick up requests where the owner has the required kudos to consume already available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_upfront_kudos: Option<bool>,
+
+    /// How many jobs to pop at the same time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u32>,
+
+    /// Marks the worker as extra slow.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_slow_worker: Option<bool>,
+
+    /// The maximum amount of tokens this worker can generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u32>,
+
+    /// The max amount of context to submit to this AI for sampling.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_context_length: Option<u32>,
+
+    /// The available softprompt files on this worker for the currently running model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub softprompts: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct ModelPayloadKobold {
+    /// The prompt which will be sent to KoboldAI to generate text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+
+    /// The number of generations to produce.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u8>,
+
+    /// Input formatting option. When enabled, adds a leading space to your input if there is no trailing whitespace at the end of the previous action.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frmtadsnsp: Option<bool>,
+
+    /// Output formatting option. When enabled, replaces all occurrences of two or more consecutive newlines in the output with one newline.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frmtrmblln: Option<bool>,
+
+    /// Output formatting option. When enabled, removes #/@%}{+=~|^<> from the output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frmtrmspch: Option<bool>,
+
+    /// Output formatting option. When enabled, removes some characters from the end of the output such that the output doesn't end in the middle of a sentence.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frmttriminc: Option<bool>,
+
+    /// Maximum number of tokens to send to the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_context_length: Option<u32>,
+
+    /// Number of tokens to generate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u32>,
+
+    /// Base repetition penalty value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rep_pen: Option<f32>,
+
+    /// Repetition penalty range.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rep_pen_range: Option<u32>,
+
+    /// Repetition penalty slope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rep_pen_slope: Option<f32>,
+
+    /// Output formatting option. When enabled, removes everything after the first line of the output, including the newline.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub singleline: Option<bool>,
+
+    /// Temperature value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+
+    /// Tail free sampling value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tfs: Option<f32>,
+
+    /// Top-a sampling value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_a: Option<f32>,
+
+    /// Top-k sampling value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+
+    /// Top-p sampling value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+
+    /// Typical sampling value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typical: Option<f32>,
+
+    /// The sampler order to use during generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampler_order: Option<Vec<u8>>,
+
+    /// When True, uses the default KoboldAI bad word IDs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_default_badwordsids: Option<bool>,
+
+    /// An array of string sequences whereby the model will stop generating further tokens. The returned text WILL contain the stop sequence.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequence: Option<Vec<String>>,
+
+    /// Min-p sampling value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f32>,
+
+    /// Quadratic sampling value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smoothing_factor: Option<f32>,
+
+    /// Dynamic temperature range value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynatemp_range: Option<f32>,
+
+    /// Dynamic temperature exponent value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynatemp_exponent: Option<f32>,
+}
+
+/// Why a text worker did not receive a job, as counts of the requests skipped for each reason.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct NoValidRequestFoundKobold {
+    /// How many waiting requests were skipped because they demanded a specific worker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_id: Option<u64>,
+
+    /// How many waiting requests were skipped because they required higher performance.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub performance: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a nsfw generation which this worker does not provide.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nsfw: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a generation with a word that this worker does not accept.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blacklist: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a trusted worker which this worker is not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub untrusted: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a different model than what this worker provides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub models: Option<u64>,
+
+    /// How many waiting requests were skipped because they require a higher version of the bridge than this worker is running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_version: Option<u64>,
+
+    /// How many waiting requests were skipped because the user didn't have enough kudos when this worker requires upfront kudos.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kudos: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a higher max_context_length than what this worker provides.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_context_length: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded more generated tokens that what this worker can provide.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded an available soft-prompt which this worker does not have.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matching_softprompt: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct GenerationPayloadKobold {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<ModelPayloadKobold>,
+
+    /// The UUID for this text generation. Empty when no job was available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// The UUIDs of all text generations in this batch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<NoValidRequestFoundKobold>,
+
+    /// The soft prompt requested for this generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub softprompt: Option<String>,
+
+    /// Which of the available models to use for this request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    /// The amount of seconds before this job is considered stale and aborted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct SubmitInputKobold {
+    /// The UUID of this generation.
+    pub id: String,
+
+    /// The generated text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<String>,
+
+    /// The state of this generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<GenerationState>,
+
+    /// The seed for this generation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gen_metadata: Option<Vec<GenerationMetadataStable>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct InterrogationPopInput {
+    /// The Name of the Worker.
+    pub name: String,
+
+    /// The worker will pick up any request from these users as a priority.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority_usernames: Option<Vec<String>>,
+
+    /// The type of interrogation this worker can fulfil.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forms: Option<Vec<InterrogationType>>,
+
+    /// The amount of forms to pop at the same time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<u32>,
+
+    /// The worker name, version and website.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_agent: Option<String>,
+
+    /// How many threads this worker is running. This is used to accurately the current power available in the horde.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<u32>,
+
+    /// The maximum amount of 512x512 tiles this worker can post-process.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tiles: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct InterrogationPopFormPayload {
+    /// The UUID of the interrogation form. Use this to post the results in the future.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    /// The name of this interrogation form.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub form: Option<InterrogationType>,
+
+    /// Extra arguments of this form.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<Map<String, Value>>,
+
+    /// The URL From which the source image can be downloaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_image: Option<String>,
+
+    /// The URL in which the post-processed image can be uploaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r2_upload: Option<String>,
+}
+
+/// Why an alchemist did not receive a form, as counts of the forms skipped for each reason.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct NoValidInterrogationsFound {
+    /// How many waiting requests were skipped because they demanded a specific worker.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_id: Option<u64>,
+
+    /// How many waiting requests were skipped because they demanded a trusted worker which this worker is not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub untrusted: Option<u64>,
+
+    /// How many waiting requests were skipped because they require a higher version of the bridge than this worker is running.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bridge_version: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct InterrogationPopPayload {
+    /// The forms to fulfil. Empty when no form was available.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forms: Option<Vec<InterrogationPopFormPayload>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<NoValidInterrogationsFound>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct InterrogationSubmitInput {
+    /// The UUID of the interrogation form.
+    pub id: String,
+
+    /// The result of the form, e.g. `{"caption": "..."}`, or `{"<form>": "R2"}` when an image was uploaded to the r2 upload link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<Map<String, Value>>,
+
+    /// The state of this form.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<GenerationState>,
+}
+
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct KudosTransferInput {
+    /// The user to transfer kudos to, as `username#id`.
+    pub username: String,
+
+    /// The amount of kudos to transfer.
+    pub amount: f64,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct KudosTransferred {
+    /// The amount of Kudos transferred.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transferred: Option<f64>,
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+
+use log::{info, warn};
+
+use crate::enums::{GenerationState, MetadataType, MetadataValue};
+use crate::errors::AihordeError;
+use crate::models::{GenerationMetadataStable, GenerationStable};
+
+/// Something that went wrong while a worker processed a generation.
+#[derive(Debug, PartialEq, Clone)]
+pub enum GenerationWarning {
+    /// A requested LoRa could not be used. `reference` is the LoRa ID as sent by the worker.
+    LoraFailed {
+        reference: Option<String>,
+        reason: MetadataValue,
+    },
+
+    /// A requested Textual Inversion could not be used.
+    TiFailed {
+        reference: Option<String>,
+        reason: MetadataValue,
+    },
+
+    /// The image was replaced by the worker's safety filter.
+    /// The reason is `Nsfw` or `Csam` when the worker reported it.
+    Censored(Option<MetadataValue>),
+
+    /// The source image could not be downloaded or parsed.
+    SourceImageFailed(MetadataValue),
+
+    /// The source mask could not be downloaded or parsed.
+    SourceMaskFailed(MetadataValue),
+
+    /// One of the extra source images could not be downloaded or parsed.
+    ExtraSourceImageFailed {
+        reference: Option<String>,
+        reason: MetadataValue,
+    },
+}
+
+impl fmt::Display for GenerationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reference = |reference: &Option<String>| {
+            reference
+                .as_deref()
+                .map(|reference| format!(" {reference}"))
+                .unwrap_or_default()
+        };
+        match self {
+            GenerationWarning::LoraFailed {
+                reference: r,
+                reason,
+            } => {
+                write!(f, "LoRa{} failed: {reason}", reference(r))
+            }
+            GenerationWarning::TiFailed {
+                reference: r,
+                reason,
+            } => {
+                write!(f, "TI{} failed: {reason}", reference(r))
+            }
+            GenerationWarning::Censored(Some(reason)) => write!(f, "Image censored: {reason}"),
+            GenerationWarning::Censored(None) => write!(f, "Image censored"),
+            GenerationWarning::SourceImageFailed(reason) => {
+                write!(f, "Source image failed: {reason}")
+            }
+            GenerationWarning::SourceMaskFailed(reason) => {
+                write!(f, "Source mask failed: {reason}")
+            }
+            GenerationWarning::ExtraSourceImageFailed {
+                reference: r,
+                reason,
+            } => {
+                write!(f, "Extra source image{} failed: {reason}", reference(r))
+            }
+        }
+    }
+}
+
+/// Typed interpretation of the `gen_metadata` of a generation.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct GenerationOutcome {
+    /// Everything that went wrong, in the order reported by the worker.
+    pub warnings: Vec<GenerationWarning>,
+
+    /// The index of this image in its batch, when the worker batched the request.
+    pub batch_index: Option<u32>,
+
+    /// Free-form information sent along by the worker.
+    pub information: Vec<String>,
+}
+
+impl GenerationOutcome {
+    /// ### Interpret the metadata of a generation
+    pub fn from_generation(generation: &GenerationStable) -> Self {
+        let mut outcome = GenerationOutcome::default();
+        let mut censorship = None;
+        for GenerationMetadataStable {
+            metadata_type,
+            metadata_value,
+            metadata_ref,
+        } in generation.gen_metadata.iter().flatten()
+        {
+            let reference = metadata_ref.clone();
+            let reason = metadata_value.clone();
+            match metadata_type {
+                MetadataType::Lora => outcome
+                    .warnings
+                    .push(GenerationWarning::LoraFailed { reference, reason }),
+                MetadataType::Ti => outcome
+                    .warnings
+                    .push(GenerationWarning::TiFailed { reference, reason }),
+                MetadataType::Censorship => censorship = Some(reason),
+                MetadataType::SourceImage => outcome
+                    .warnings
+                    .push(GenerationWarning::SourceImageFailed(reason)),
+                MetadataType::SourceMask => outcome
+                    .warnings
+                    .push(GenerationWarning::SourceMaskFailed(reason)),
+                MetadataType::ExtraSourceImages => outcome
+                    .warnings
+                    .push(GenerationWarning::ExtraSourceImageFailed { reference, reason }),
+                MetadataType::BatchIndex => {
+                    outcome.batch_index = reference.and_then(|index| index.parse().ok())
+                }
+                MetadataType::Information => outcome.information.extend(reference),
+            }
+        }
+
+        let censored = generation.censored == Some(true)
+            || matches!(
+                generation.state,
+                GenerationState::Censored | GenerationState::Csam
+            )
+            || censorship.is_some();
+        if censored {
+            outcome
+                .warnings
+                .push(GenerationWarning::Censored(censorship));
+        }
+        outcome
+    }
+
+    /// True when nothing went wrong.
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    /// True when the image was replaced by the worker's safety filter.
+    pub fn is_censored(&self) -> bool {
+        self.warnings
+            .iter()
+            .any(|warning| matches!(warning, GenerationWarning::Censored(_)))
+    }
+
+    /// Why the image was censored, if the worker reported it.
+    pub fn censorship_reason(&self) -> Option<&MetadataValue> {
+        self.warnings.iter().find_map(|warning| match warning {
+            GenerationWarning::Censored(reason) => reason.as_ref(),
+            _ => None,
+        })
+    }
+
+    /// References of the LoRas which could not be used.
+    pub fn failed_loras(&self) -> Vec<&str> {
+        self.warnings
+            .iter()
+            .filter_map(|warning| match warning {
+                GenerationWarning::LoraFailed { reference, .. } => reference.as_deref(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// References of the Textual Inversions which could not be used.
+    pub fn failed_tis(&self) -> Vec<&str> {
+        self.warnings
+            .iter()
+            .filter_map(|warning| match warning {
+                GenerationWarning::TiFailed { reference, .. } => reference.as_deref(),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl GenerationStable {
+    /// ### Interpret the `gen_metadata` of this generation
+    pub fn outcome(&self) -> GenerationOutcome {
+        GenerationOutcome::from_generation(self)
+    }
+
+    /// ### Interpret and log the `gen_metadata` of this generation
+    /// Every warning is logged. In strict mode, any warning turns into an error.
+    /// #### Arguments
+    /// * `strict` - Whether to fail when the generation has warnings.
+    pub fn analyze(&self, strict: bool) -> Result<GenerationOutcome, AihordeError> {
+        let outcome = self.outcome();
+        let id = self.id.as_deref().unwrap_or("<unknown>");
+        for warning in &outcome.warnings {
+            warn!("Generation {id}: {warning}");
+        }
+        for information in &outcome.information {
+            info!("Generation {id}: {information}");
+        }
+        if strict && !outcome.is_clean() {
+            return Err(AihordeError::GenerationWarnings {
+                id: self.id.clone(),
+                warnings: outcome.warnings,
+            });
+        }
+        Ok(outcome)
+    }
+}
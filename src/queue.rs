@@ -0,0 +1,383 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use futures::stream::{self, BoxStream, StreamExt};
+use log::{debug, info, warn};
+#[cfg(feature = "journal")]
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+#[cfg(feature = "journal")]
+use crate::journal::{Journal, JournalState};
+
+use crate::client::AihordeClient;
+use crate::consts::CHECK_BACKOFF_MAX_SECS;
+use crate::enums::RequestErrorCode;
+use crate::errors::AihordeError;
+use crate::models::{GenerationInputStable, RequestStatusStable};
+
+/// A job of a [`JobQueue`] which is done, either successfully or not.
+#[derive(Debug)]
+pub struct FinishedJob {
+    /// The position of the input in the submitted inputs.
+    pub index: usize,
+
+    pub input: GenerationInputStable,
+
+    /// The UUID of the last request made for this job.
+    pub request_id: Option<String>,
+
+    /// How many times the job was submitted.
+    pub attempts: u32,
+
+    /// The full status of the finished request.
+    pub result: Result<RequestStatusStable, AihordeError>,
+}
+
+struct PendingJob {
+    index: usize,
+    input: GenerationInputStable,
+    attempts: u32,
+}
+
+struct RunningJob {
+    job: PendingJob,
+    request_id: String,
+    next_check: Instant,
+    /// Checks which failed in a row.
+    failures: u32,
+}
+
+/// Submits many image generations while keeping at most N of them in flight.
+///
+/// The horde rejects requests beyond the concurrency of the user with `TooManyPrompts`,
+/// so inputs are only submitted when a slot is free. By default, N is the concurrency of the user.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    client: AihordeClient,
+    concurrency: Option<usize>,
+    poll_interval: Duration,
+    max_poll_interval: Duration,
+    max_attempts: u32,
+    max_check_failures: u32,
+    #[cfg(feature = "journal")]
+    journal: Option<Arc<Journal>>,
+}
+
+impl JobQueue {
+    /// ### Create a new job queue
+    /// #### Arguments
+    /// * `client` - The client to submit the generations with.
+    pub fn new(client: AihordeClient) -> Self {
+        Self {
+            client,
+            concurrency: None,
+            poll_interval: Duration::from_secs(2),
+            max_poll_interval: Duration::from_secs(10),
+            max_attempts: 3,
+            max_check_failures: 5,
+            #[cfg(feature = "journal")]
+            journal: None,
+        }
+    }
+
+    /// ### Keep at most `concurrency` requests in flight
+    /// By default, the concurrency is detected with `find_user`.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = Some(concurrency.max(1));
+        self
+    }
+
+    /// ### Change how often running requests are checked
+    /// Requests are checked after their estimated wait time, but never more often than `poll_interval`
+    /// and never less often than `max_poll_interval`.
+    pub fn with_poll_interval(
+        mut self,
+        poll_interval: Duration,
+        max_poll_interval: Duration,
+    ) -> Self {
+        self.poll_interval = poll_interval;
+        self.max_poll_interval = max_poll_interval.max(poll_interval);
+        self
+    }
+
+    /// ### Change how many times a faulted request is submitted
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// ### Change how many checks of a request may fail in a row before its job fails
+    /// Failed checks are retried with an exponential backoff, since the request keeps running on the horde.
+    /// Requests the horde no longer knows fail their job right away.
+    pub fn with_max_check_failures(mut self, max_check_failures: u32) -> Self {
+        self.max_check_failures = max_check_failures.max(1);
+        self
+    }
+
+    /// ### Record every submitted request in a journal
+    /// Requests which are still running when the process stops can be resumed with [`Journal::resume`].
+    #[cfg(feature = "journal")]
+    pub fn with_journal(mut self, journal: Arc<Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    #[cfg(feature = "journal")]
+    async fn journal_submitted(&self, request_id: &str, job: &PendingJob, kudos: Option<f64>) {
+        if let Some(journal) = &self.journal
+            && let Err(e) = journal
+                .record_submitted(request_id, &job.input, kudos)
+                .await
+        {
+            warn!("Failed to journal request {request_id}: {e}");
+        }
+    }
+
+    #[cfg(not(feature = "journal"))]
+    async fn journal_submitted(&self, _request_id: &str, _job: &PendingJob, _kudos: Option<f64>) {}
+
+    #[cfg(feature = "journal")]
+    async fn journal_finished(
+        &self,
+        request_id: &str,
+        result: Option<&Result<RequestStatusStable, AihordeError>>,
+    ) {
+        let state = match result {
+            None | Some(Err(AihordeError::RequestFaulted { .. })) => JournalState::Faulted,
+            Some(Ok(_)) => JournalState::Done,
+            Some(Err(AihordeError::ApiError {
+                code: RequestErrorCode::RequestNotFound | RequestErrorCode::RequestExpired,
+                ..
+            })) => JournalState::Expired,
+            Some(Err(_)) => return,
+        };
+        if let Some(journal) = &self.journal
+            && let Err(e) = journal.record_state(request_id, state).await
+        {
+            warn!("Failed to journal request {request_id}: {e}");
+        }
+    }
+
+    #[cfg(not(feature = "journal"))]
+    async fn journal_finished(
+        &self,
+        _request_id: &str,
+        _result: Option<&Result<RequestStatusStable, AihordeError>>,
+    ) {
+    }
+
+    /// ### Submit all inputs and yield the finished jobs in completion order
+    /// The jobs run in a background task, which stops when the stream is dropped.
+    pub fn run(
+        self,
+        inputs: impl IntoIterator<Item = GenerationInputStable>,
+    ) -> BoxStream<'static, FinishedJob> {
+        let pending: VecDeque<PendingJob> = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(index, input)| PendingJob {
+                index,
+                input,
+                attempts: 0,
+            })
+            .collect();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(self.drive(pending, sender));
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|job| (job, receiver))
+        })
+        .boxed()
+    }
+
+    /// Reschedule a running job after a failed check, unless the error is final.
+    /// Returns whether the job keeps running.
+    fn retry_check(&self, running: &mut RunningJob, now: Instant, error: &AihordeError) -> bool {
+        if let AihordeError::ApiError {
+            code: RequestErrorCode::RequestNotFound | RequestErrorCode::RequestExpired,
+            ..
+        } = error
+        {
+            return false;
+        }
+        running.failures += 1;
+        if running.failures >= self.max_check_failures {
+            warn!(
+                "Giving up on request {} after {} failed checks: {error}",
+                running.request_id, running.failures
+            );
+            return false;
+        }
+        let backoff = self
+            .poll_interval
+            .saturating_mul(2u32.saturating_pow(running.failures))
+            .min(Duration::from_secs(CHECK_BACKOFF_MAX_SECS))
+            .max(self.poll_interval);
+        warn!(
+            "Check {} of request {} failed, retrying in {backoff:?}: {error}",
+            running.failures, running.request_id
+        );
+        running.next_check = now + backoff;
+        true
+    }
+
+    async fn concurrency(&self) -> usize {
+        if let Some(concurrency) = self.concurrency {
+            return concurrency;
+        }
+        match self.client.find_user().await {
+            Ok(user) => user.concurrency.unwrap_or(1).max(1) as usize,
+            Err(e) => {
+                warn!("Failed to detect the concurrency of the user, using 1: {e}");
+                1
+            }
+        }
+    }
+
+    async fn drive(
+        self,
+        mut pending: VecDeque<PendingJob>,
+        sender: mpsc::UnboundedSender<FinishedJob>,
+    ) {
+        let concurrency = self.concurrency().await;
+        info!(
+            "Submitting {} generations, {concurrency} at a time",
+            pending.len()
+        );
+        let finish = |job: PendingJob, request_id, result| {
+            sender
+                .send(FinishedJob {
+                    index: job.index,
+                    input: job.input,
+                    request_id,
+                    attempts: job.attempts,
+                    result,
+                })
+                .is_ok()
+        };
+        let mut running: Vec<RunningJob> = Vec::new();
+
+        while !pending.is_empty() || !running.is_empty() {
+            // Fill the free slots
+            while running.len() < concurrency {
+                let Some(mut job) = pending.pop_front() else {
+                    break;
+                };
+                job.attempts += 1;
+                match self.client.generate_async(job.input.clone()).await {
+                    Ok(request) => match request.id {
+                        Some(request_id) => {
+                            debug!("Job {} submitted as {request_id}", job.index);
+                            self.journal_submitted(&request_id, &job, request.kudos)
+                                .await;
+                            running.push(RunningJob {
+                                job,
+                                request_id,
+                                next_check: Instant::now() + self.poll_interval,
+                                failures: 0,
+                            });
+                        }
+                        None => {
+                            let error = AihordeError::UnexpectedResponse(
+                                "Request was accepted without an ID".to_string(),
+                            );
+                            if !finish(job, None, Err(error)) {
+                                return;
+                            }
+                        }
+                    },
+                    Err(AihordeError::ApiError {
+                        code: RequestErrorCode::TooManyPrompts,
+                        ..
+                    }) => {
+                        // Someone else uses the same account, wait for a slot to free up
+                        debug!(
+                            "Concurrency limit reached, retrying job {} later",
+                            job.index
+                        );
+                        job.attempts -= 1;
+                        pending.push_front(job);
+                        break;
+                    }
+                    Err(e) => {
+                        if !finish(job, None, Err(e)) {
+                            return;
+                        }
+                    }
+                }
+            }
+
+            let next_check = running.iter().map(|running| running.next_check).min();
+            let wake = next_check.unwrap_or_else(|| Instant::now() + self.poll_interval);
+            tokio::time::sleep_until(wake.into()).await;
+            if sender.is_closed() {
+                debug!("Job queue dropped, stopping");
+                return;
+            }
+
+            let now = Instant::now();
+            let mut index = 0;
+            while index < running.len() {
+                if running[index].next_check > now {
+                    index += 1;
+                    continue;
+                }
+                let request_id = running[index].request_id.clone();
+                let check = match self.client.generation_check(request_id.clone()).await {
+                    Ok(check) => check,
+                    Err(e) => {
+                        if self.retry_check(&mut running[index], now, &e) {
+                            index += 1;
+                            continue;
+                        }
+                        let RunningJob { job, .. } = running.swap_remove(index);
+                        let result = Err(e);
+                        self.journal_finished(&request_id, Some(&result)).await;
+                        if !finish(job, Some(request_id), result) {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+                if check.faulted == Some(true) {
+                    let RunningJob { job, .. } = running.swap_remove(index);
+                    self.journal_finished(&request_id, None).await;
+                    if job.attempts < self.max_attempts {
+                        warn!(
+                            "Request {request_id} faulted, rescheduling job {}",
+                            job.index
+                        );
+                        pending.push_back(job);
+                    } else {
+                        let error = AihordeError::RequestFaulted {
+                            id: request_id.clone(),
+                        };
+                        if !finish(job, Some(request_id), Err(error)) {
+                            return;
+                        }
+                    }
+                } else if check.done == Some(true) {
+                    let result = self.client.generation_status(request_id.clone()).await;
+                    if let Err(e) = &result
+                        && self.retry_check(&mut running[index], now, e)
+                    {
+                        index += 1;
+                        continue;
+                    }
+                    let RunningJob { job, .. } = running.swap_remove(index);
+                    self.journal_finished(&request_id, Some(&result)).await;
+                    if !finish(job, Some(request_id), result) {
+                        return;
+                    }
+                } else {
+                    let wait = Duration::from_secs(check.wait_time.unwrap_or_default() as u64);
+                    running[index].next_check =
+                        now + wait.clamp(self.poll_interval, self.max_poll_interval);
+                    running[index].failures = 0;
+                    index += 1;
+                }
+            }
+        }
+        debug!("Job queue finished");
+    }
+}
@@ -0,0 +1,184 @@
+use std::fmt;
+
+use crate::models::{GenerationInputStable, GenerationStable, ModelGenerationInputStable};
+
+/// A generation seed, as accepted in `ModelGenerationInputStable.seed`.
+///
+/// The horde turns every seed into a 32-bit number before handing it to workers:
+/// numeric seeds are used as they are, and other text is read as a little-endian number.
+/// Numbers of 2^32 and above are shifted right by 32 bits until they fit.
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+pub enum Seed {
+    Number(u64),
+    Text(String),
+}
+
+impl Seed {
+    /// ### Interpret a seed string the way the horde does
+    /// Strings made of ASCII digits only are numeric seeds, anything else is textual.
+    pub fn parse(seed: &str) -> Self {
+        if !seed.is_empty() && seed.bytes().all(|b| b.is_ascii_digit()) {
+            match seed.parse() {
+                Ok(number) => Seed::Number(number),
+                // Too large for a u64, but still hashed as a number
+                Err(_) => Seed::Text(seed.to_string()),
+            }
+        } else {
+            Seed::Text(seed.to_string())
+        }
+    }
+
+    /// ### The 32-bit seed workers receive
+    pub fn to_int(&self) -> u32 {
+        match self {
+            Seed::Number(number) => fit(&[*number as u32, (*number >> 32) as u32]),
+            Seed::Text(text) if !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit()) => {
+                fit(&decimal_limbs(text))
+            }
+            Seed::Text(text) => {
+                let limbs: Vec<u32> = text
+                    .as_bytes()
+                    .chunks(4)
+                    .map(|chunk| {
+                        let mut bytes = [0; 4];
+                        bytes[..chunk.len()].copy_from_slice(chunk);
+                        u32::from_le_bytes(bytes)
+                    })
+                    .collect();
+                fit(&limbs)
+            }
+        }
+    }
+
+    /// ### Predict the seed of every image of a request
+    /// The first image gets the seed itself, and every next image adds `seed_variation` to the previous seed.
+    /// Without a variation, all images share the seed.
+    /// Images are listed in the order workers pick them up, which is not always the order of the finished generations.
+    /// #### Arguments
+    /// * `n` - How many images the request generates.
+    /// * `seed_variation` - The `seed_variation` of the request.
+    pub fn image_seeds(&self, n: u8, seed_variation: Option<u16>) -> Vec<u32> {
+        let variation = seed_variation.unwrap_or(0) as u64;
+        let mut seed = self.to_int() as u64;
+        let mut seeds = Vec::with_capacity(n as usize);
+        for index in 0..n {
+            if index > 0 {
+                seed += variation;
+                while seed >= 1 << 32 {
+                    seed >>= 32;
+                }
+            }
+            seeds.push(seed as u32);
+        }
+        seeds
+    }
+}
+
+impl fmt::Display for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Seed::Number(number) => write!(f, "{number}"),
+            Seed::Text(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+impl From<u64> for Seed {
+    fn from(number: u64) -> Self {
+        Seed::Number(number)
+    }
+}
+
+impl From<u32> for Seed {
+    fn from(number: u32) -> Self {
+        Seed::Number(number as u64)
+    }
+}
+
+impl From<&str> for Seed {
+    fn from(seed: &str) -> Self {
+        Seed::parse(seed)
+    }
+}
+
+impl From<String> for Seed {
+    fn from(seed: String) -> Self {
+        Seed::parse(&seed)
+    }
+}
+
+impl From<Seed> for String {
+    fn from(seed: Seed) -> Self {
+        seed.to_string()
+    }
+}
+
+/// The most significant non-zero 32-bit limb, which is what shifting right by 32 bits until the number fits leaves.
+fn fit(limbs: &[u32]) -> u32 {
+    limbs
+        .iter()
+        .rev()
+        .find(|limb| **limb != 0)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// A decimal number of any length as little-endian 32-bit limbs.
+fn decimal_limbs(digits: &str) -> Vec<u32> {
+    let mut limbs: Vec<u32> = vec![0];
+    for digit in digits.bytes().map(|b| (b - b'0') as u64) {
+        let mut carry = digit;
+        for limb in limbs.iter_mut() {
+            let value = *limb as u64 * 10 + carry;
+            *limb = value as u32;
+            carry = value >> 32;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+    }
+    limbs
+}
+
+impl ModelGenerationInputStable {
+    /// ### The seed of the request, if it has one
+    /// An empty seed means a random one, like no seed at all.
+    pub fn parsed_seed(&self) -> Option<Seed> {
+        self.seed
+            .as_deref()
+            .filter(|seed| !seed.is_empty())
+            .map(Seed::parse)
+    }
+
+    /// ### Predict the seed of every image of the request
+    /// Returns `None` when the seed is random. See [`Seed::image_seeds`].
+    pub fn image_seeds(&self) -> Option<Vec<u32>> {
+        let seed = self.parsed_seed()?;
+        Some(seed.image_seeds(self.n.unwrap_or(1), self.seed_variation))
+    }
+}
+
+impl GenerationInputStable {
+    /// ### Rebuild the input of a single finished image
+    /// The result uses the seed, model and worker of `generation`, so submitting it generates the same image again,
+    /// as long as the worker is still online.
+    /// #### Arguments
+    /// * `generation` - A finished image of a request made with this input.
+    pub fn regenerate(&self, generation: &GenerationStable) -> GenerationInputStable {
+        let mut input = self.clone();
+        let params = input.params.get_or_insert_with(Default::default);
+        params.n = Some(1);
+        params.seed_variation = None;
+        if let Some(seed) = &generation.seed {
+            params.seed = Some(seed.clone());
+        }
+        if let Some(model) = &generation.model {
+            input.models = Some(vec![model.clone()]);
+        }
+        if let Some(worker_id) = &generation.worker_id {
+            input.workers = Some(vec![worker_id.clone()]);
+            input.worker_blacklist = None;
+        }
+        input
+    }
+}
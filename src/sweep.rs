@@ -0,0 +1,541 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::StreamExt;
+#[cfg(feature = "image")]
+use image::{Rgb, RgbImage};
+use log::info;
+
+use crate::client::AihordeClient;
+use crate::enums::SamplerName;
+use crate::errors::AihordeError;
+use crate::images::DownloadedImage;
+use crate::models::{GenerationInputStable, ModelGenerationInputStable};
+use crate::queue::JobQueue;
+
+/// A parameter varied by a [`Sweep`], with the values it takes.
+#[derive(Debug, PartialEq, Clone)]
+pub enum SweepAxis {
+    Sampler(Vec<SamplerName>),
+    CfgScale(Vec<f32>),
+    Steps(Vec<u16>),
+    Model(Vec<String>),
+    Seed(Vec<String>),
+    /// Replaces the first value in the prompt with each value in turn, the first one included.
+    PromptReplace(Vec<String>),
+}
+
+impl SweepAxis {
+    /// The name of the parameter, as used in labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            SweepAxis::Sampler(_) => "Sampler",
+            SweepAxis::CfgScale(_) => "CFG scale",
+            SweepAxis::Steps(_) => "Steps",
+            SweepAxis::Model(_) => "Model",
+            SweepAxis::Seed(_) => "Seed",
+            SweepAxis::PromptReplace(_) => "Prompt",
+        }
+    }
+
+    /// How many values the axis takes.
+    pub fn len(&self) -> usize {
+        match self {
+            SweepAxis::Sampler(values) => values.len(),
+            SweepAxis::CfgScale(values) => values.len(),
+            SweepAxis::Steps(values) => values.len(),
+            SweepAxis::Model(values) => values.len(),
+            SweepAxis::Seed(values) => values.len(),
+            SweepAxis::PromptReplace(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// ### The label of the value at `index`
+    pub fn label(&self, index: usize) -> String {
+        match self {
+            SweepAxis::Sampler(values) => serde_json::to_value(&values[index])
+                .ok()
+                .and_then(|value| value.as_str().map(str::to_string))
+                .unwrap_or_else(|| format!("{:?}", values[index])),
+            SweepAxis::CfgScale(values) => values[index].to_string(),
+            SweepAxis::Steps(values) => values[index].to_string(),
+            SweepAxis::Model(values) => values[index].clone(),
+            SweepAxis::Seed(values) => values[index].clone(),
+            SweepAxis::PromptReplace(values) => values[index].clone(),
+        }
+    }
+
+    fn validate(&self, base: &GenerationInputStable) -> Result<(), AihordeError> {
+        if self.is_empty() {
+            return Err(AihordeError::InvalidInput(format!(
+                "The {} axis has no values",
+                self.name()
+            )));
+        }
+        if let SweepAxis::PromptReplace(values) = self
+            && !base.prompt.contains(&values[0])
+        {
+            return Err(AihordeError::InvalidInput(format!(
+                "The prompt does not contain `{}`",
+                values[0]
+            )));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, index: usize, input: &mut GenerationInputStable) {
+        let params = input.params.get_or_insert_with(Default::default);
+        match self {
+            SweepAxis::Sampler(values) => params.sampler_name = Some(values[index].clone()),
+            SweepAxis::CfgScale(values) => params.cfg_scale = Some(values[index]),
+            SweepAxis::Steps(values) => params.steps = Some(values[index]),
+            SweepAxis::Model(values) => input.models = Some(vec![values[index].clone()]),
+            SweepAxis::Seed(values) => params.seed = Some(values[index].clone()),
+            SweepAxis::PromptReplace(values) => {
+                input.prompt = input.prompt.replacen(&values[0], &values[index], 1)
+            }
+        }
+    }
+}
+
+/// A combination of a [`Sweep`], as positions on its axes.
+#[derive(Debug)]
+pub struct SweepCell {
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
+    pub input: GenerationInputStable,
+
+    /// The first image of the request.
+    pub result: Result<DownloadedImage, AihordeError>,
+}
+
+/// Generates every combination of up to three axes over a base input, like an X/Y/Z plot.
+///
+/// Every combination uses the same seed, unless the seed is an axis, and generates a single image.
+/// With the `image` feature, the results can be composed into a labeled contact sheet with
+/// `SweepResult::contact_sheet`.
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    client: AihordeClient,
+    queue: JobQueue,
+    base: GenerationInputStable,
+    axes: [Option<SweepAxis>; 3],
+}
+
+impl Sweep {
+    /// ### Create a new sweep
+    /// A random seed is picked when `base` has none.
+    /// #### Arguments
+    /// * `client` - The client to submit the generations with.
+    /// * `base` - The input every combination starts from.
+    /// * `x` - The axis along the columns of the contact sheet.
+    pub fn new(client: AihordeClient, mut base: GenerationInputStable, x: SweepAxis) -> Self {
+        let params = base
+            .params
+            .get_or_insert_with(ModelGenerationInputStable::default);
+        params.n = Some(1);
+        if params.seed.is_none() {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .subsec_nanos();
+            params.seed = Some((nanos % 1_000_000_000).to_string());
+        }
+        Self {
+            queue: JobQueue::new(client.clone()),
+            client,
+            base,
+            axes: [Some(x), None, None],
+        }
+    }
+
+    /// ### Add the axis along the rows of the contact sheet
+    pub fn with_y(mut self, y: SweepAxis) -> Self {
+        self.axes[1] = Some(y);
+        self
+    }
+
+    /// ### Add the axis producing one contact sheet per value
+    pub fn with_z(mut self, z: SweepAxis) -> Self {
+        self.axes[2] = Some(z);
+        self
+    }
+
+    /// ### Keep at most `concurrency` requests in flight
+    /// By default, the concurrency is detected with `find_user`.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.queue = self.queue.with_concurrency(concurrency);
+        self
+    }
+
+    /// ### Change how often running requests are checked
+    pub fn with_poll_interval(
+        mut self,
+        poll_interval: Duration,
+        max_poll_interval: Duration,
+    ) -> Self {
+        self.queue = self
+            .queue
+            .with_poll_interval(poll_interval, max_poll_interval);
+        self
+    }
+
+    fn dimensions(&self) -> [usize; 3] {
+        self.axes
+            .each_ref()
+            .map(|axis| axis.as_ref().map_or(1, SweepAxis::len))
+    }
+
+    /// ### The input of every combination
+    /// Ordered by z, then y, then x.
+    pub fn inputs(
+        &self,
+    ) -> Result<Vec<(usize, usize, usize, GenerationInputStable)>, AihordeError> {
+        for axis in self.axes.iter().flatten() {
+            axis.validate(&self.base)?;
+        }
+        let [width, height, depth] = self.dimensions();
+        let mut inputs = Vec::with_capacity(width * height * depth);
+        for z in 0..depth {
+            for y in 0..height {
+                for x in 0..width {
+                    let mut input = self.base.clone();
+                    for (axis, index) in self.axes.iter().zip([x, y, z]) {
+                        if let Some(axis) = axis {
+                            axis.apply(index, &mut input);
+                        }
+                    }
+                    inputs.push((x, y, z, input));
+                }
+            }
+        }
+        Ok(inputs)
+    }
+
+    /// ### Generate every combination
+    /// Failed combinations are reported in their cell, and drawn as failed on the contact sheet.
+    pub async fn run(&self) -> Result<SweepResult, AihordeError> {
+        let inputs = self.inputs()?;
+        info!("Sweeping over {} combinations", inputs.len());
+        let mut cells: Vec<Option<SweepCell>> = (0..inputs.len()).map(|_| None).collect();
+        let mut jobs = self.queue.clone().run(
+            inputs
+                .iter()
+                .map(|(.., input)| input.clone())
+                .collect::<Vec<_>>(),
+        );
+        while let Some(job) = jobs.next().await {
+            let result = match job.result {
+                Ok(status) => self
+                    .client
+                    .download_generations(&status)
+                    .await
+                    .and_then(|images| {
+                        images.into_iter().next().ok_or_else(|| {
+                            AihordeError::UnexpectedResponse(
+                                "Request finished without images".to_string(),
+                            )
+                        })
+                    }),
+                Err(e) => Err(e),
+            };
+            let (x, y, z, _) = inputs[job.index];
+            cells[job.index] = Some(SweepCell {
+                x,
+                y,
+                z,
+                input: job.input,
+                result,
+            });
+        }
+        Ok(SweepResult {
+            axes: self.axes.clone(),
+            cells: cells.into_iter().flatten().collect(),
+        })
+    }
+}
+
+/// The generated combinations of a [`Sweep`].
+#[derive(Debug)]
+pub struct SweepResult {
+    axes: [Option<SweepAxis>; 3],
+
+    /// Ordered by z, then y, then x.
+    pub cells: Vec<SweepCell>,
+}
+
+#[cfg(feature = "image")]
+const SCALE: u32 = 2;
+#[cfg(feature = "image")]
+const GLYPH_WIDTH: u32 = 6 * SCALE;
+#[cfg(feature = "image")]
+const LINE_HEIGHT: u32 = 9 * SCALE;
+#[cfg(feature = "image")]
+const GAP: u32 = 4 * SCALE;
+#[cfg(feature = "image")]
+const MAX_LABEL_CHARS: u32 = 32;
+#[cfg(feature = "image")]
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+#[cfg(feature = "image")]
+const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+#[cfg(feature = "image")]
+const GRAY: Rgb<u8> = Rgb([200, 200, 200]);
+
+impl SweepResult {
+    /// The X, Y and Z axes of the sweep.
+    pub fn axes(&self) -> &[Option<SweepAxis>; 3] {
+        &self.axes
+    }
+
+    /// ### The cell at a position
+    pub fn cell(&self, x: usize, y: usize, z: usize) -> Option<&SweepCell> {
+        self.cells
+            .iter()
+            .find(|cell| (cell.x, cell.y, cell.z) == (x, y, z))
+    }
+
+    /// ### Compose the images of one Z value into a labeled grid
+    /// Columns follow the X axis and rows the Y axis. Cells are as large as the largest image,
+    /// and failed combinations are drawn as gray cells.
+    /// #### Arguments
+    /// * `z` - The index of the value on the Z axis, 0 when the sweep has no Z axis.
+    #[cfg(feature = "image")]
+    pub fn contact_sheet(&self, z: usize) -> Result<RgbImage, AihordeError> {
+        let [x_axis, y_axis, z_axis] = &self.axes;
+        let columns = x_axis.as_ref().map_or(1, SweepAxis::len) as u32;
+        let rows = y_axis.as_ref().map_or(1, SweepAxis::len) as u32;
+        if z >= z_axis.as_ref().map_or(1, SweepAxis::len) {
+            return Err(AihordeError::InvalidInput(format!(
+                "The sweep has no Z value {z}"
+            )));
+        }
+
+        let mut images = Vec::new();
+        for cell in self.cells.iter().filter(|cell| cell.z == z) {
+            if let Ok(image) = &cell.result {
+                images.push((cell.x as u32, cell.y as u32, image.decode()?.to_rgb8()));
+            }
+        }
+        let cell_width = images
+            .iter()
+            .map(|(.., image)| image.width())
+            .max()
+            .unwrap_or(64);
+        let cell_height = images
+            .iter()
+            .map(|(.., image)| image.height())
+            .max()
+            .unwrap_or(64);
+
+        let mut title: Vec<String> = Vec::new();
+        for (name, axis) in ["X", "Y"].into_iter().zip([x_axis, y_axis]) {
+            if let Some(axis) = axis {
+                title.push(format!("{name}: {}", axis.name()));
+            }
+        }
+        if let Some(axis) = z_axis {
+            title.push(format!("Z: {} = {}", axis.name(), axis.label(z)));
+        }
+        let title = title.join(", ");
+        let y_labels: Vec<String> = match y_axis {
+            Some(axis) => (0..axis.len()).map(|index| axis.label(index)).collect(),
+            None => Vec::new(),
+        };
+        let left = y_labels
+            .iter()
+            .map(|label| text_width(label).min(MAX_LABEL_CHARS * GLYPH_WIDTH))
+            .max()
+            .map_or(GAP, |width| width + 2 * GAP);
+        let top = 2 * (LINE_HEIGHT + GAP);
+        let width = (left + columns * (cell_width + GAP)).max(text_width(&title) + 2 * GAP);
+        let height = top + rows * (cell_height + GAP);
+
+        let mut sheet = RgbImage::from_pixel(width, height, WHITE);
+        draw_text(&mut sheet, GAP, GAP / 2, &title, width);
+        for column in 0..columns {
+            let label = x_axis.as_ref().map(|axis| axis.label(column as usize));
+            let x = left + column * (cell_width + GAP);
+            draw_text(
+                &mut sheet,
+                x,
+                LINE_HEIGHT + GAP,
+                label.as_deref().unwrap_or_default(),
+                cell_width,
+            );
+            for row in 0..rows {
+                let y = top + row * (cell_height + GAP);
+                fill(&mut sheet, x, y, cell_width, cell_height, GRAY);
+                if column == 0
+                    && let Some(label) = y_labels.get(row as usize)
+                {
+                    let label_y = y + cell_height.saturating_sub(LINE_HEIGHT) / 2;
+                    draw_text(&mut sheet, GAP, label_y, label, left - 2 * GAP);
+                }
+            }
+        }
+        for row in 0..rows {
+            for column in 0..columns {
+                let x = left + column * (cell_width + GAP);
+                let y = top + row * (cell_height + GAP);
+                match images.iter().find(|(c, r, _)| (*c, *r) == (column, row)) {
+                    Some((.., image)) => {
+                        image::imageops::replace(&mut sheet, image, x as i64, y as i64)
+                    }
+                    None => draw_text(
+                        &mut sheet,
+                        x + GAP,
+                        y + GAP,
+                        "failed",
+                        cell_width.saturating_sub(GAP),
+                    ),
+                }
+            }
+        }
+        Ok(sheet)
+    }
+}
+
+#[cfg(feature = "image")]
+fn text_width(text: &str) -> u32 {
+    text.chars().count() as u32 * GLYPH_WIDTH
+}
+
+#[cfg(feature = "image")]
+fn fill(sheet: &mut RgbImage, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+    for py in y..(y + height).min(sheet.height()) {
+        for px in x..(x + width).min(sheet.width()) {
+            sheet.put_pixel(px, py, color);
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+/// Draw `text` with the built-in 5x7 font, cut to fit within `max_width` pixels.
+fn draw_text(sheet: &mut RgbImage, x: u32, y: u32, text: &str, max_width: u32) {
+    let fitting = (max_width / GLYPH_WIDTH) as usize;
+    for (position, c) in text.chars().take(fitting).enumerate() {
+        let glyph = match c {
+            ' '..='~' => FONT[c as usize - ' ' as usize],
+            _ => FONT['?' as usize - ' ' as usize],
+        };
+        let glyph_x = x + position as u32 * GLYPH_WIDTH;
+        for (column, bits) in glyph.iter().enumerate() {
+            for row in 0..7 {
+                if bits >> row & 1 == 1 {
+                    fill(
+                        sheet,
+                        glyph_x + column as u32 * SCALE,
+                        y + row * SCALE,
+                        SCALE,
+                        SCALE,
+                        BLACK,
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+/// 5x7 glyphs of the printable ASCII characters, one byte per column with the top row in the lowest bit.
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5F, 0x00, 0x00], // '!'
+    [0x00, 0x07, 0x00, 0x07, 0x00], // '"'
+    [0x14, 0x7F, 0x14, 0x7F, 0x14], // '#'
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], // '$'
+    [0x23, 0x13, 0x08, 0x64, 0x62], // '%'
+    [0x36, 0x49, 0x55, 0x22, 0x50], // '&'
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '''
+    [0x00, 0x1C, 0x22, 0x41, 0x00], // '('
+    [0x00, 0x41, 0x22, 0x1C, 0x00], // ')'
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], // '*'
+    [0x08, 0x08, 0x3E, 0x08, 0x08], // '+'
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ','
+    [0x08, 0x08, 0x08, 0x08, 0x08], // '-'
+    [0x00, 0x60, 0x60, 0x00, 0x00], // '.'
+    [0x20, 0x10, 0x08, 0x04, 0x02], // '/'
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], // '0'
+    [0x00, 0x42, 0x7F, 0x40, 0x00], // '1'
+    [0x42, 0x61, 0x51, 0x49, 0x46], // '2'
+    [0x21, 0x41, 0x45, 0x4B, 0x31], // '3'
+    [0x18, 0x14, 0x12, 0x7F, 0x10], // '4'
+    [0x27, 0x45, 0x45, 0x45, 0x39], // '5'
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], // '6'
+    [0x01, 0x71, 0x09, 0x05, 0x03], // '7'
+    [0x36, 0x49, 0x49, 0x49, 0x36], // '8'
+    [0x06, 0x49, 0x49, 0x29, 0x1E], // '9'
+    [0x00, 0x36, 0x36, 0x00, 0x00], // ':'
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ';'
+    [0x08, 0x14, 0x22, 0x41, 0x00], // '<'
+    [0x14, 0x14, 0x14, 0x14, 0x14], // '='
+    [0x00, 0x41, 0x22, 0x14, 0x08], // '>'
+    [0x02, 0x01, 0x51, 0x09, 0x06], // '?'
+    [0x32, 0x49, 0x79, 0x41, 0x3E], // '@'
+    [0x7E, 0x11, 0x11, 0x11, 0x7E], // 'A'
+    [0x7F, 0x49, 0x49, 0x49, 0x36], // 'B'
+    [0x3E, 0x41, 0x41, 0x41, 0x22], // 'C'
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], // 'D'
+    [0x7F, 0x49, 0x49, 0x49, 0x41], // 'E'
+    [0x7F, 0x09, 0x09, 0x01, 0x01], // 'F'
+    [0x3E, 0x41, 0x41, 0x51, 0x32], // 'G'
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], // 'H'
+    [0x00, 0x41, 0x7F, 0x41, 0x00], // 'I'
+    [0x20, 0x40, 0x41, 0x3F, 0x01], // 'J'
+    [0x7F, 0x08, 0x14, 0x22, 0x41], // 'K'
+    [0x7F, 0x40, 0x40, 0x40, 0x40], // 'L'
+    [0x7F, 0x02, 0x04, 0x02, 0x7F], // 'M'
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], // 'N'
+    [0x3E, 0x41, 0x41, 0x41, 0x3E], // 'O'
+    [0x7F, 0x09, 0x09, 0x09, 0x06], // 'P'
+    [0x3E, 0x41, 0x51, 0x21, 0x5E], // 'Q'
+    [0x7F, 0x09, 0x19, 0x29, 0x46], // 'R'
+    [0x46, 0x49, 0x49, 0x49, 0x31], // 'S'
+    [0x01, 0x01, 0x7F, 0x01, 0x01], // 'T'
+    [0x3F, 0x40, 0x40, 0x40, 0x3F], // 'U'
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], // 'V'
+    [0x7F, 0x20, 0x18, 0x20, 0x7F], // 'W'
+    [0x63, 0x14, 0x08, 0x14, 0x63], // 'X'
+    [0x03, 0x04, 0x78, 0x04, 0x03], // 'Y'
+    [0x61, 0x51, 0x49, 0x45, 0x43], // 'Z'
+    [0x00, 0x7F, 0x41, 0x41, 0x00], // '['
+    [0x02, 0x04, 0x08, 0x10, 0x20], // '\'
+    [0x00, 0x41, 0x41, 0x7F, 0x00], // ']'
+    [0x04, 0x02, 0x01, 0x02, 0x04], // '^'
+    [0x40, 0x40, 0x40, 0x40, 0x40], // '_'
+    [0x00, 0x01, 0x02, 0x04, 0x00], // '`'
+    [0x20, 0x54, 0x54, 0x54, 0x78], // 'a'
+    [0x7F, 0x48, 0x44, 0x44, 0x38], // 'b'
+    [0x38, 0x44, 0x44, 0x44, 0x20], // 'c'
+    [0x38, 0x44, 0x44, 0x48, 0x7F], // 'd'
+    [0x38, 0x54, 0x54, 0x54, 0x18], // 'e'
+    [0x08, 0x7E, 0x09, 0x01, 0x02], // 'f'
+    [0x0C, 0x52, 0x52, 0x52, 0x3E], // 'g'
+    [0x7F, 0x08, 0x04, 0x04, 0x78], // 'h'
+    [0x00, 0x44, 0x7D, 0x40, 0x00], // 'i'
+    [0x20, 0x40, 0x44, 0x3D, 0x00], // 'j'
+    [0x7F, 0x10, 0x28, 0x44, 0x00], // 'k'
+    [0x00, 0x41, 0x7F, 0x40, 0x00], // 'l'
+    [0x7C, 0x04, 0x18, 0x04, 0x78], // 'm'
+    [0x7C, 0x08, 0x04, 0x04, 0x78], // 'n'
+    [0x38, 0x44, 0x44, 0x44, 0x38], // 'o'
+    [0x7C, 0x14, 0x14, 0x14, 0x08], // 'p'
+    [0x08, 0x14, 0x14, 0x18, 0x7C], // 'q'
+    [0x7C, 0x08, 0x04, 0x04, 0x08], // 'r'
+    [0x48, 0x54, 0x54, 0x54, 0x20], // 's'
+    [0x04, 0x3F, 0x44, 0x40, 0x20], // 't'
+    [0x3C, 0x40, 0x40, 0x20, 0x7C], // 'u'
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], // 'v'
+    [0x3C, 0x40, 0x30, 0x40, 0x3C], // 'w'
+    [0x44, 0x28, 0x10, 0x28, 0x44], // 'x'
+    [0x0C, 0x50, 0x50, 0x50, 0x3C], // 'y'
+    [0x44, 0x64, 0x54, 0x4C, 0x44], // 'z'
+    [0x00, 0x08, 0x36, 0x41, 0x00], // '{'
+    [0x00, 0x00, 0x7F, 0x00, 0x00], // '|'
+    [0x00, 0x41, 0x36, 0x08, 0x00], // '}'
+    [0x08, 0x04, 0x08, 0x10, 0x08], // '~'
+];
@@ -1,8 +1,44 @@
 use crate::enums::ModelState;
+use crate::enums::{
+    GenerationState, InterrogationType, MetadataType, MetadataValue, RequestErrorCode, SamplerName,
+    UserSort,
+};
+use crate::images::{ImageSource, OutputFormat};
+use crate::batch::{self, Batch};
+#[cfg(feature = "budget")]
+use crate::budget::{Budget, BudgetGuard, CostEstimate, estimate_kudos};
+use crate::cache::{CacheConfig, CachedEndpoint};
+use crate::seed::Seed;
+use crate::sweep::{Sweep, SweepAxis};
+use crate::errors::AihordeError;
+use crate::metadata::{
+    GenerationParameters, embed_parameters, read_generation_input, read_parameters,
+};
+#[cfg(feature = "journal")]
+use crate::journal::{Journal, JournalState};
+use crate::key_pool::{KeyPool, KeySelection, KeyState};
+use crate::middleware::{Middleware, RequestInfo, TimingMiddleware, redact_headers};
+use crate::mock::{MockHorde, MockRateLimit};
+use crate::queue::JobQueue;
+use crate::worker::{HordeWorker, HordeWorkerBackend, WorkerConfig, WorkerOutput};
 use crate::{client::AihordeClient, enums::ModelType};
-use crate::models::GenerationInputStable;
+use crate::models::{
+    GenerationInputStable, GenerationMetadataStable, GenerationPayloadStable, GenerationStable,
+    InterrogationPopInput, InterrogationSubmitInput, KudosTransferInput,
+    ModelGenerationInputStable, ModelPayloadKobold, ModelPayloadLorasStable, PopInputKobold,
+    PopInputStable, RequestStatusStable, SubmitInputKobold, SubmitInputStable, UserDetails,
+};
+use base64::Engine;
+use futures::StreamExt;
+use base64::engine::general_purpose::STANDARD;
+use image::{ImageFormat, RgbaImage};
 use log::{debug, info};
-use std::{sync::Once, thread, time::Duration};
+use std::io::Cursor;
+#[cfg(any(feature = "tracing", feature = "metrics"))]
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::{sync::Once, time::Duration};
 use tokio::test;
 
 static INIT: Once = Once::new();
@@ -14,23 +50,41 @@ fn test_client() -> AihordeClient {
     AihordeClient::new(None, None, None)
 }
 
+async fn mock_client() -> (MockHorde, AihordeClient) {
+    INIT.call_once(|| {
+        env_logger::init();
+    });
+    let horde = MockHorde::start().await.unwrap();
+    let client = horde.client();
+    (horde, client)
+}
+
+fn test_webp_base64() -> String {
+    let image = RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+    let mut buffer = Cursor::new(Vec::new());
+    image.write_to(&mut buffer, ImageFormat::WebP).unwrap();
+    STANDARD.encode(buffer.into_inner())
+}
+
 #[test]
 async fn test_find_user() {
-    let client = test_client();
+    let (_horde, client) = mock_client().await;
     let user = client.find_user().await.unwrap();
     info!("{:?}", user);
+    assert_eq!(user.username.as_deref(), Some("Anonymous#0"));
 }
 
 #[test]
 async fn test_get_user() {
-    let client = test_client();
+    let (_horde, client) = mock_client().await;
     let user = client.get_user("0000000000".to_string()).await.unwrap();
     info!("{:?}", user);
+    assert_eq!(user.id, Some(0));
 }
 
 #[test]
 async fn test_get_users() {
-    let client = test_client();
+    let (_horde, client) = mock_client().await;
     let result = client.get_users(0, None).await;
     match &result {
         Ok(users) => debug!("Success: Found {} users", users.len()),
@@ -39,11 +93,102 @@ async fn test_get_users() {
             panic!("Failed to get users: {:?}", e);
         }
     }
+    // The string form still works
+    assert_eq!(
+        client.get_users(1, Some("age".to_string())).await.unwrap(),
+        client.get_users_sorted(1, UserSort::Age).await.unwrap()
+    );
+}
+
+#[test]
+async fn test_users_stream() {
+    let (horde, client) = mock_client().await;
+    for index in 0..60 {
+        horde.add_user(
+            &format!("user-key-{index}"),
+            UserDetails {
+                username: Some(format!("user#{index}")),
+                kudos: Some(index as f64 * 10.0),
+                ..Default::default()
+            },
+        );
+    }
+    let mut expected = Vec::new();
+    for page in 1.. {
+        let users = client
+            .get_users_sorted(page, UserSort::Kudos)
+            .await
+            .unwrap();
+        expected.extend(users.iter().map(|user| user.username.clone()));
+        if users.len() < 25 {
+            break;
+        }
+    }
+    assert!(expected.len() > 60);
+
+    let users: Vec<UserDetails> = client
+        .users_stream(UserSort::Kudos)
+        .with_concurrency(3)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(
+        users
+            .iter()
+            .map(|user| user.username.clone())
+            .collect::<Vec<_>>(),
+        expected
+    );
+    assert!(users.windows(2).all(|pair| pair[0].kudos >= pair[1].kudos));
+
+    let users: Vec<UserDetails> = client
+        .users_stream(UserSort::Age)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(users.len(), expected.len());
+    assert!(users.windows(2).all(|pair| pair[0].id < pair[1].id));
+
+    let users: Vec<UserDetails> = client
+        .users_stream(UserSort::Kudos)
+        .with_max_pages(1)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(users.len(), 25);
+
+    let calls = horde.calls().len();
+    let users: Vec<UserDetails> = client
+        .users_stream(UserSort::Kudos)
+        .with_stop_when(|user| user.kudos.unwrap_or_default() < 300.0)
+        .map(Result::unwrap)
+        .collect()
+        .await;
+    assert_eq!(users.len(), 30);
+    // The second page already has users below the threshold
+    assert_eq!(horde.calls().len() - calls, 2);
+
+    horde.fail_next(
+        "/users",
+        503,
+        RequestErrorCode::MaintenanceMode,
+        "Horde is in maintenance",
+    );
+    let results: Vec<Result<UserDetails, AihordeError>> =
+        client.users_stream(UserSort::Kudos).collect().await;
+    assert_eq!(results.len(), 1);
+    assert!(matches!(
+        results[0],
+        Err(AihordeError::ApiError {
+            code: RequestErrorCode::MaintenanceMode,
+            ..
+        })
+    ));
 }
 
 #[test]
 async fn test_generate_async_dry_run() {
-    let client = test_client();
+    let (_horde, client) = mock_client().await;
     let generation_input = GenerationInputStable {
         prompt: "A photo of a cat".to_string(),
         dry_run: Some(true),
@@ -51,18 +196,21 @@ async fn test_generate_async_dry_run() {
     };
     let request = client.generate_async(generation_input).await.unwrap();
     debug!("{:?}", request);
+    assert!(request.id.is_none());
+    assert!(request.kudos.is_some());
 }
 
 #[test]
 async fn test_get_active_models() {
-    let client = test_client();
+    let (_horde, client) = mock_client().await;
     let models = client.get_active_models(Some(ModelType::Image), Some(10), None, Some(ModelState::All)).await.unwrap();
     info!("{:?}", models);
+    assert_eq!(models.len(), 2);
 }
 
 #[test]
 async fn test_generate_async() {
-    let client = test_client();
+    let (_horde, client) = mock_client().await;
     let generation_input = GenerationInputStable {
         prompt: "A photo of a cat".to_string(),
         r2: Some(true),
@@ -77,11 +225,2027 @@ async fn test_generate_async() {
         if status.done.unwrap_or(false) {
             let full_status = client.generation_status(request.id.unwrap()).await.unwrap();
             info!("{:?}", full_status);
+            let images = client.download_generations(&full_status).await.unwrap();
+            assert_eq!(images.len(), 1);
             for generation in full_status.generations.unwrap_or_default() {
                 info!("{:?}", generation.img.unwrap_or("null".to_string()));
             }
             break;
         }
-        thread::sleep(Duration::from_secs(5));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[test]
+async fn test_generation_cancel() {
+    let (horde, client) = mock_client().await;
+    horde.configure(|config| config.external_workers = true);
+    let request = client
+        .generate_async(GenerationInputStable {
+            prompt: "A photo of a cat".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let id = request.id.unwrap();
+    let status = client.generation_cancel(id.clone()).await.unwrap();
+    assert_eq!(status.done, Some(true));
+    assert!(status.generations.unwrap_or_default().is_empty());
+    match client.generation_check(id).await {
+        Err(AihordeError::ApiError {
+            code: RequestErrorCode::RequestNotFound,
+            ..
+        }) => {}
+        other => panic!("Expected the request to be gone, got {other:?}"),
+    }
+}
+
+#[test]
+async fn test_transfer_kudos() {
+    let (horde, _) = mock_client().await;
+    horde.add_user(
+        "sender-key",
+        UserDetails {
+            username: Some("sender#1".to_string()),
+            kudos: Some(100.0),
+            ..Default::default()
+        },
+    );
+    horde.add_user(
+        "receiver-key",
+        UserDetails {
+            username: Some("receiver#2".to_string()),
+            kudos: Some(0.0),
+            ..Default::default()
+        },
+    );
+    let client = horde.client_with_key("sender-key");
+    let transfer = |username: &str, amount: f64| {
+        client.transfer_kudos(KudosTransferInput {
+            username: username.to_string(),
+            amount,
+        })
+    };
+    let transferred = transfer("receiver#2", 40.0).await.unwrap();
+    assert_eq!(transferred.transferred, Some(40.0));
+    assert_eq!(horde.user("sender-key").unwrap().kudos, Some(60.0));
+    assert_eq!(horde.user("receiver-key").unwrap().kudos, Some(40.0));
+
+    for (username, amount, expected) in [
+        ("sender#1", 1.0, RequestErrorCode::KudosTransferToSelf),
+        (
+            "receiver#2",
+            1000.0,
+            RequestErrorCode::KudosTransferNotEnough,
+        ),
+        ("receiver#2", -1.0, RequestErrorCode::NegativeKudosTransfer),
+        ("nobody#9", 1.0, RequestErrorCode::UserNotFound),
+    ] {
+        match transfer(username, amount).await {
+            Err(AihordeError::ApiError { code, .. }) => assert_eq!(code, expected),
+            other => panic!("Expected {expected}, got {other:?}"),
+        }
+    }
+}
+
+#[test]
+async fn test_response_cache() {
+    let (horde, client) = mock_client().await;
+    let client =
+        client.with_cache(CacheConfig::default().with_ttl(CachedEndpoint::Users, Duration::ZERO));
+    let models_calls = || {
+        horde
+            .calls()
+            .iter()
+            .filter(|call| *call == "GET /api/v2/status/models")
+            .count()
+    };
+
+    let models = client
+        .get_active_models(None, None, None, None)
+        .await
+        .unwrap();
+    // Clones share the cache
+    let cached = client
+        .clone()
+        .get_active_models(None, None, None, None)
+        .await
+        .unwrap();
+    assert_eq!(cached.len(), models.len());
+    assert_eq!(models_calls(), 1);
+
+    // Other filters and other API keys are other cache entries
+    client
+        .get_active_models(None, Some(1), None, None)
+        .await
+        .unwrap();
+    assert_eq!(models_calls(), 2);
+    client
+        .with_api_key("other-key")
+        .get_active_models(None, None, None, None)
+        .await
+        .unwrap();
+    assert_eq!(models_calls(), 3);
+    assert_eq!(client.cache().unwrap().len(), 3);
+
+    client
+        .without_cache()
+        .get_active_models(None, None, None, None)
+        .await
+        .unwrap();
+    assert_eq!(models_calls(), 4);
+    client
+        .cache()
+        .unwrap()
+        .invalidate(CachedEndpoint::ActiveModels);
+    assert!(client.cache().unwrap().is_empty());
+    client
+        .get_active_models(None, None, None, None)
+        .await
+        .unwrap();
+    assert_eq!(models_calls(), 5);
+
+    // Expired entries are revalidated with their ETag
+    let users = client.get_users_sorted(1, UserSort::Kudos).await.unwrap();
+    let revalidated = client.get_users_sorted(1, UserSort::Kudos).await.unwrap();
+    assert_eq!(revalidated.len(), users.len());
+    assert_eq!(horde.not_modified_responses(), 1);
+    horde.add_user(
+        "new-key",
+        UserDetails {
+            username: Some("new#3".to_string()),
+            kudos: Some(1.0),
+            ..Default::default()
+        },
+    );
+    let changed = client.get_users_sorted(1, UserSort::Kudos).await.unwrap();
+    assert_eq!(changed.len(), users.len() + 1);
+    assert_eq!(horde.not_modified_responses(), 1);
+}
+
+#[derive(Default)]
+struct RecordingMiddleware {
+    events: Arc<Mutex<Vec<String>>>,
+    api_key: Option<String>,
+}
+
+impl Middleware for RecordingMiddleware {
+    fn before_send(&self, info: &RequestInfo, request: &mut reqwest::Request) {
+        if let Some(api_key) = &self.api_key {
+            request
+                .headers_mut()
+                .insert("apikey", api_key.parse().unwrap());
+        }
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("before {} {}", info.method, info.endpoint));
+    }
+
+    fn after_response(&self, info: &RequestInfo, response: &reqwest::Response) {
+        self.events.lock().unwrap().push(format!(
+            "after {} {}",
+            info.endpoint,
+            response.status().as_u16()
+        ));
+    }
+
+    fn on_error(&self, info: &RequestInfo, error: &AihordeError) {
+        let code = match error {
+            AihordeError::ApiError { code, .. } => code.to_string(),
+            other => other.to_string(),
+        };
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("error {} {code}", info.endpoint));
+    }
+}
+
+#[test]
+async fn test_middleware() {
+    let (horde, client) = mock_client().await;
+    horde.add_user(
+        "other-key",
+        UserDetails {
+            username: Some("other#5".to_string()),
+            ..Default::default()
+        },
+    );
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let timing = TimingMiddleware::new();
+    let client = client
+        .with_middleware(RecordingMiddleware {
+            events: events.clone(),
+            api_key: Some("other-key".to_string()),
+        })
+        .with_middleware(timing.clone());
+
+    // Middlewares can change requests before they are sent
+    let user = client.find_user().await.unwrap();
+    assert_eq!(user.username.as_deref(), Some("other#5"));
+    match client.generation_status("missing".to_string()).await {
+        Err(AihordeError::ApiError { .. }) => {}
+        other => panic!("Expected an API error, got {other:?}"),
+    }
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            "before GET /find_user",
+            "after /find_user 200",
+            "before GET /generate/status/{id}",
+            "after /generate/status/{id} 404",
+            "error /generate/status/{id} RequestNotFound",
+        ]
+    );
+
+    let timings = timing.timings();
+    assert_eq!(timings["GET /find_user"].responses, 1);
+    assert_eq!(timings["GET /find_user"].errors, 0);
+    assert_eq!(timings["GET /generate/status/{id}"].errors, 1);
+    assert!(timings["GET /find_user"].average().is_some());
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert("apikey", "secret".parse().unwrap());
+    headers.insert("Client-Agent", "test".parse().unwrap());
+    let redacted = redact_headers(&headers, &HashSet::from(["apikey".to_string()]));
+    assert_eq!(
+        redacted,
+        vec![
+            ("apikey".to_string(), "***".to_string()),
+            ("client-agent".to_string(), "test".to_string()),
+        ]
+    );
+}
+
+#[test]
+async fn test_mock_injected_errors() {
+    let (horde, client) = mock_client().await;
+    horde.fail_next(
+        "/generate/async",
+        503,
+        RequestErrorCode::MaintenanceMode,
+        "Horde is in maintenance",
+    );
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        ..Default::default()
+    };
+    match client.generate_async(input.clone()).await {
+        Err(AihordeError::ApiError { code, .. }) => {
+            assert_eq!(code, RequestErrorCode::MaintenanceMode)
+        }
+        other => panic!("Expected maintenance error, got {other:?}"),
+    }
+    assert!(client.generate_async(input).await.is_ok());
+
+    match client.generation_check("missing".to_string()).await {
+        Err(AihordeError::ApiError { code, .. }) => {
+            assert_eq!(code, RequestErrorCode::RequestNotFound)
+        }
+        other => panic!("Expected missing request, got {other:?}"),
+    }
+}
+
+#[test]
+async fn test_mock_faults_and_concurrency() {
+    let (horde, _) = mock_client().await;
+    horde.add_user(
+        "mock-key",
+        UserDetails {
+            username: Some("mock#1".to_string()),
+            kudos: Some(100.0),
+            concurrency: Some(1),
+            ..Default::default()
+        },
+    );
+    horde.fault_next_requests(1);
+    let client = horde.client_with_key("mock-key");
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        ..Default::default()
+    };
+    let request = client.generate_async(input.clone()).await.unwrap();
+    let status = client.generation_check(request.id.unwrap()).await.unwrap();
+    assert_eq!(status.faulted, Some(true));
+
+    let request = client.generate_async(input.clone()).await.unwrap();
+    match client.generate_async(input).await {
+        Err(AihordeError::ApiError { code, .. }) => {
+            assert_eq!(code, RequestErrorCode::TooManyPrompts)
+        }
+        other => panic!("Expected too many prompts, got {other:?}"),
+    }
+    let user = client.find_user().await.unwrap();
+    assert_eq!(user.kudos, Some(80.0));
+    assert_eq!(
+        user.active_generations.unwrap().image,
+        Some(vec![request.id.unwrap()])
+    );
+}
+
+#[test]
+async fn test_mock_r2_download_past_last_image() {
+    let (horde, client) = mock_client().await;
+    let id = client
+        .generate_async(GenerationInputStable {
+            prompt: "A photo of a cat".to_string(),
+            params: Some(ModelGenerationInputStable {
+                seed: Some("7".to_string()),
+                ..Default::default()
+            }),
+            r2: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .id
+        .unwrap();
+    for index in [0, 255, 300] {
+        let url = format!("{}/r2/{id}/{index}.webp", horde.base_url());
+        let response = reqwest::get(url).await.unwrap();
+        assert!(response.status().is_success(), "{index}");
+    }
+}
+
+#[test]
+async fn test_mock_rate_limit() {
+    let (horde, client) = mock_client().await;
+    horde.configure(|config| {
+        config.rate_limit = Some(MockRateLimit {
+            max_requests: 2,
+            window: Duration::from_secs(60),
+        })
+    });
+    assert!(client.find_user().await.is_ok());
+    assert!(client.find_user().await.is_ok());
+    match client.find_user().await {
+        Err(AihordeError::UnexpectedHTTPCode { code, .. }) => assert_eq!(code, 429),
+        other => panic!("Expected rate limit, got {other:?}"),
+    }
+}
+
+#[test]
+async fn test_image_source_parse() {
+    let source = ImageSource::parse("https://r2.example.com/image.webp").unwrap();
+    assert!(matches!(source, ImageSource::R2(_)));
+    let source = ImageSource::parse(&test_webp_base64()).unwrap();
+    assert!(matches!(source, ImageSource::Base64(_)));
+}
+
+#[test]
+async fn test_download_generations_base64() {
+    let client = test_client();
+    let status = RequestStatusStable {
+        generations: Some(vec![
+            GenerationStable {
+                img: Some(test_webp_base64()),
+                ..Default::default()
+            };
+            2
+        ]),
+        ..Default::default()
+    };
+    let images = client.download_generations(&status).await.unwrap();
+    assert_eq!(images.len(), 2);
+    assert_eq!(images[0].format, OutputFormat::WebP);
+
+    let dir = std::env::temp_dir().join(format!("aihorde-rs-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("image.png");
+    images[0].save(&path).await.unwrap();
+    let saved = std::fs::read(&path).unwrap();
+    assert_eq!(OutputFormat::guess(&saved), Some(OutputFormat::Png));
+    assert_eq!(image::guess_format(&saved).unwrap(), ImageFormat::Png);
+    let jpeg = images[1].encode(OutputFormat::Jpeg).unwrap();
+    assert_eq!(image::guess_format(&jpeg).unwrap(), ImageFormat::Jpeg);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+async fn test_generated_image_rejects_garbage() {
+    let client = test_client();
+    let image = client
+        .generated_image(GenerationStable {
+            img: Some(STANDARD.encode(b"not an image")),
+            ..Default::default()
+        })
+        .unwrap();
+    assert!(image.download().await.is_err());
+}
+
+#[test]
+async fn test_generation_parameters_roundtrip() {
+    let input = GenerationInputStable {
+        prompt: "a cat, sitting: on a mat ### blurry".to_string(),
+        params: Some(ModelGenerationInputStable {
+            sampler_name: Some(SamplerName::KDpmpp2M),
+            karras: Some(true),
+            cfg_scale: Some(7.5),
+            width: Some(512),
+            height: Some(768),
+            steps: Some(30),
+            loras: Some(vec![ModelPayloadLorasStable {
+                name: Some("247778".to_string()),
+                model: Some(0.8),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let generation = GenerationStable {
+        seed: Some("12345".to_string()),
+        model: Some("AlbedoBase XL (SDXL)".to_string()),
+        worker_name: Some("Worker, the: first".to_string()),
+        ..Default::default()
+    };
+    let parameters = GenerationParameters::new(&input, &generation);
+    let text = parameters.to_string();
+    assert!(text.starts_with(
+        "a cat, sitting: on a mat\nNegative prompt: blurry\nSteps: 30, Sampler: DPM++ 2M"
+    ));
+    let parsed: GenerationParameters = text.parse().unwrap();
+    assert_eq!(parsed, parameters);
+
+    let rebuilt = parsed.to_generation_input();
+    assert_eq!(rebuilt.prompt, "a cat, sitting: on a mat ### blurry");
+    assert_eq!(
+        rebuilt.models,
+        Some(vec!["AlbedoBase XL (SDXL)".to_string()])
+    );
+    assert_eq!(rebuilt.params.unwrap().seed, Some("12345".to_string()));
+}
+
+#[test]
+async fn test_embed_parameters_in_images() {
+    let client = test_client();
+    let input = GenerationInputStable {
+        prompt: "a cat ### a dog".to_string(),
+        ..Default::default()
+    };
+    let image = client
+        .generated_image(GenerationStable {
+            img: Some(test_webp_base64()),
+            seed: Some("42".to_string()),
+            model: Some("Deliberate".to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+        .download()
+        .await
+        .unwrap()
+        .with_parameters(&input);
+
+    for format in [OutputFormat::WebP, OutputFormat::Png, OutputFormat::Jpeg] {
+        let data = image.encode(format).unwrap();
+        image::load_from_memory(&data).unwrap();
+        let parameters = read_parameters(&data).unwrap().unwrap();
+        assert_eq!(Some(&parameters), image.parameters.as_ref(), "{format:?}");
+        let rebuilt = read_generation_input(&data).unwrap().unwrap();
+        assert_eq!(rebuilt.prompt, input.prompt);
+    }
+
+    // A lossy WebP frame of zero width is malformed
+    let mut webp = b"RIFF\x16\x00\x00\x00WEBPVP8 \x0a\x00\x00\x00".to_vec();
+    webp.extend_from_slice(&[0, 0, 0, 0x9d, 0x01, 0x2a, 0, 0, 16, 0]);
+    assert!(matches!(
+        embed_parameters(&webp, image.parameters.as_ref().unwrap()),
+        Err(AihordeError::InvalidInput(_))
+    ));
+}
+
+#[test]
+async fn test_generation_outcome() {
+    let metadata =
+        |metadata_type, metadata_value, metadata_ref: Option<&str>| GenerationMetadataStable {
+            metadata_type,
+            metadata_value,
+            metadata_ref: metadata_ref.map(str::to_string),
+        };
+    let generation = GenerationStable {
+        id: Some("generation".to_string()),
+        gen_metadata: Some(vec![
+            metadata(
+                MetadataType::Lora,
+                MetadataValue::DownloadFailed,
+                Some("247778"),
+            ),
+            metadata(MetadataType::Censorship, MetadataValue::Csam, None),
+            metadata(MetadataType::SourceMask, MetadataValue::ParseFailed, None),
+            metadata(MetadataType::BatchIndex, MetadataValue::SeeRef, Some("2")),
+        ]),
+        ..Default::default()
+    };
+    let outcome = generation.outcome();
+    assert_eq!(outcome.failed_loras(), vec!["247778"]);
+    assert!(outcome.failed_tis().is_empty());
+    assert!(outcome.is_censored());
+    assert_eq!(outcome.censorship_reason(), Some(&MetadataValue::Csam));
+    assert_eq!(outcome.batch_index, Some(2));
+    assert_eq!(outcome.warnings.len(), 3);
+
+    assert!(generation.analyze(false).is_ok());
+    match generation.analyze(true) {
+        Err(AihordeError::GenerationWarnings { warnings, .. }) => assert_eq!(warnings.len(), 3),
+        other => panic!("Expected generation warnings, got {other:?}"),
+    }
+    assert!(
+        GenerationStable::default()
+            .analyze(true)
+            .unwrap()
+            .is_clean()
+    );
+}
+
+#[cfg(feature = "webhook")]
+#[test]
+async fn test_webhook_server() {
+    use crate::models::{AlchemyWebhookPayload, ImageWebhookPayload, TextWebhookPayload};
+    use crate::webhook::{WebhookEvent, WebhookHandler, post_webhook};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let images = Arc::new(AtomicUsize::new(0));
+    let counter = images.clone();
+    let (handler, mut events) = WebhookHandler::new()
+        .on_image(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        })
+        .channel();
+    let server = handler.serve("127.0.0.1:0").await.unwrap();
+
+    let image = WebhookEvent::Image(ImageWebhookPayload {
+        request: "image-request".to_string(),
+        kudos: Some(10.0),
+        generation: GenerationStable {
+            img: Some(test_webp_base64()),
+            seed: Some("42".to_string()),
+            ..Default::default()
+        },
+    });
+    let text = WebhookEvent::Text(TextWebhookPayload {
+        request: "text-request".to_string(),
+        generation: crate::models::GenerationKobold {
+            text: Some("Once upon a time".to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    let alchemy = WebhookEvent::Alchemy(AlchemyWebhookPayload {
+        request: "alchemy-request".to_string(),
+        form: crate::models::InterrogationFormStatus {
+            form: Some(crate::enums::InterrogationType::Caption),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    for event in [&image, &text, &alchemy] {
+        post_webhook(&server.url(), event).await.unwrap();
+        assert_eq!(&events.recv().await.unwrap(), event);
+    }
+    assert_eq!(images.load(Ordering::SeqCst), 1);
+
+    let rejected = reqwest::Client::new()
+        .post(server.url())
+        .body("{}")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(rejected.status(), reqwest::StatusCode::BAD_REQUEST);
+    server.shutdown().await;
+}
+
+#[test]
+async fn test_generate_pop_and_submit() {
+    let (horde, client) = mock_client().await;
+    horde.configure(|config| config.external_workers = true);
+    horde.add_user(
+        "worker-key",
+        UserDetails {
+            username: Some("worker#1".to_string()),
+            kudos: Some(0.0),
+            ..Default::default()
+        },
+    );
+    let worker = horde.client_with_key("worker-key");
+    let pop_input = PopInputStable {
+        name: "Mock Worker".to_string(),
+        models: Some(vec!["Deliberate".to_string()]),
+        max_pixels: Some(512 * 512),
+        ..Default::default()
+    };
+
+    match client.generate_pop(pop_input.clone()).await {
+        Err(AihordeError::ApiError { code, .. }) => {
+            assert_eq!(code, RequestErrorCode::AnonForbiddenWorker)
+        }
+        other => panic!("Expected anonymous workers to be rejected, got {other:?}"),
+    }
+    let job = worker.generate_pop(pop_input.clone()).await.unwrap();
+    assert_eq!(job.id, None);
+
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        models: Some(vec!["stable_diffusion".to_string()]),
+        ..Default::default()
+    };
+    client.generate_async(input.clone()).await.unwrap();
+    let job = worker.generate_pop(pop_input.clone()).await.unwrap();
+    assert_eq!(job.id, None);
+    assert_eq!(job.skipped.unwrap().models, Some(1));
+
+    let request = client
+        .generate_async(GenerationInputStable {
+            models: Some(vec!["Deliberate".to_string()]),
+            ..input
+        })
+        .await
+        .unwrap();
+    let request_id = request.id.unwrap();
+    let job = worker.generate_pop(pop_input).await.unwrap();
+    let job_id = job.id.clone().unwrap();
+    assert_eq!(job.model.as_deref(), Some("Deliberate"));
+    assert_eq!(
+        job.payload.unwrap().prompt.as_deref(),
+        Some("A photo of a cat")
+    );
+    assert!(job.r2_upload.is_some());
+    let status = client.generation_check(request_id.clone()).await.unwrap();
+    assert_eq!(status.processing, Some(1));
+
+    let submitted = worker
+        .generate_submit(SubmitInputStable {
+            id: job_id.clone(),
+            generation: Some(test_webp_base64()),
+            state: Some(GenerationState::Ok),
+            seed: Some(42),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(submitted.reward, Some(10.0));
+    match worker
+        .generate_submit(SubmitInputStable {
+            id: job_id,
+            ..Default::default()
+        })
+        .await
+    {
+        Err(AihordeError::ApiError { code, .. }) => {
+            assert_eq!(code, RequestErrorCode::DuplicateGen)
+        }
+        other => panic!("Expected duplicate submission, got {other:?}"),
+    }
+
+    let status = client.generation_status(request_id).await.unwrap();
+    assert_eq!(status.done, Some(true));
+    let generations = status.generations.unwrap();
+    assert_eq!(generations[0].seed.as_deref(), Some("42"));
+    assert_eq!(generations[0].img, Some(test_webp_base64()));
+    assert_eq!(horde.user("worker-key").unwrap().kudos, Some(10.0));
+}
+
+#[test]
+async fn test_generate_text_pop_and_submit() {
+    let (horde, _) = mock_client().await;
+    horde.add_user(
+        "worker-key",
+        UserDetails {
+            username: Some("scribe#1".to_string()),
+            kudos: Some(0.0),
+            ..Default::default()
+        },
+    );
+    let worker = horde.client_with_key("worker-key");
+    let model = "koboldcpp/LLaMA2-13B-Psyfighter2";
+    let long_job = horde.queue_text_job(
+        model,
+        ModelPayloadKobold {
+            prompt: Some("Tell me a long story".to_string()),
+            max_length: Some(1024),
+            ..Default::default()
+        },
+    );
+    let job_id = horde.queue_text_job(
+        model,
+        ModelPayloadKobold {
+            prompt: Some("Once upon a time".to_string()),
+            max_length: Some(80),
+            max_context_length: Some(1024),
+            ..Default::default()
+        },
+    );
+    let pop_input = PopInputKobold {
+        name: "Mock Scribe".to_string(),
+        models: Some(vec![model.to_string()]),
+        max_length: Some(512),
+        max_context_length: Some(4096),
+        ..Default::default()
+    };
+
+    let job = worker.generate_text_pop(pop_input.clone()).await.unwrap();
+    assert_eq!(job.id.as_ref(), Some(&job_id));
+    assert_eq!(job.skipped.unwrap().max_length, Some(1));
+    assert_eq!(
+        job.payload.unwrap().prompt.as_deref(),
+        Some("Once upon a time")
+    );
+    let job = worker.generate_text_pop(pop_input).await.unwrap();
+    assert_eq!(job.id, None);
+
+    let submitted = worker
+        .generate_text_submit(SubmitInputKobold {
+            id: job_id.clone(),
+            generation: Some(", there was a crab.".to_string()),
+            state: Some(GenerationState::Ok),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(submitted.reward, Some(10.0));
+    let submission = horde.text_submission(&job_id).unwrap();
+    assert_eq!(
+        submission.generation.as_deref(),
+        Some(", there was a crab.")
+    );
+    match worker
+        .generate_text_submit(SubmitInputKobold {
+            id: long_job,
+            ..Default::default()
+        })
+        .await
+    {
+        Err(AihordeError::ApiError { code, .. }) => {
+            assert_eq!(code, RequestErrorCode::InvalidJobID)
+        }
+        other => panic!("Expected unknown job, got {other:?}"),
+    }
+}
+
+#[test]
+async fn test_interrogate_pop_and_submit() {
+    let (horde, _) = mock_client().await;
+    horde.add_user(
+        "worker-key",
+        UserDetails {
+            username: Some("alchemist#1".to_string()),
+            ..Default::default()
+        },
+    );
+    let worker = horde.client_with_key("worker-key");
+    let source = "https://example.com/source.webp";
+    let caption = horde.queue_interrogation(InterrogationType::Caption, source);
+    horde.queue_interrogation(InterrogationType::Nsfw, source);
+    let upscale = horde.queue_interrogation(InterrogationType::RealEsrganX4plus, source);
+
+    let pop = worker
+        .interrogate_pop(InterrogationPopInput {
+            name: "Mock Alchemist".to_string(),
+            forms: Some(vec![
+                InterrogationType::Caption,
+                InterrogationType::RealEsrganX4plus,
+            ]),
+            amount: Some(5),
+            max_tiles: Some(16),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let forms = pop.forms.unwrap();
+    assert_eq!(forms.len(), 2);
+    assert_eq!(forms[0].id.as_ref(), Some(&caption));
+    assert_eq!(forms[0].source_image.as_deref(), Some(source));
+    assert_eq!(forms[0].r2_upload, None);
+    assert_eq!(forms[1].id.as_ref(), Some(&upscale));
+    assert!(forms[1].r2_upload.is_some());
+
+    let mut result = serde_json::Map::new();
+    result.insert("caption".to_string(), "a crab on a beach".into());
+    let submitted = worker
+        .interrogate_submit(InterrogationSubmitInput {
+            id: caption.clone(),
+            result: Some(result.clone()),
+            state: Some(GenerationState::Ok),
+        })
+        .await
+        .unwrap();
+    assert_eq!(submitted.reward, Some(10.0));
+    assert_eq!(
+        horde.interrogation_submission(&caption).unwrap().result,
+        Some(result)
+    );
+}
+
+struct FakeBackend;
+
+impl HordeWorkerBackend for FakeBackend {
+    async fn generate(&self, job: &GenerationPayloadStable) -> Result<WorkerOutput, AihordeError> {
+        let payload = job.payload.clone().unwrap_or_default();
+        match payload.prompt.as_deref() {
+            Some("fail") => return Err(AihordeError::Other("Out of VRAM".to_string())),
+            Some("panic") => panic!("Backend crashed"),
+            _ => {}
+        }
+        Ok(WorkerOutput {
+            seed: payload.seed.and_then(|seed| seed.parse().ok()),
+            ..WorkerOutput::new(STANDARD.decode(test_webp_base64()).unwrap())
+        })
+    }
+}
+
+#[test]
+async fn test_worker_bridge() {
+    let (horde, client) = mock_client().await;
+    horde.configure(|config| config.external_workers = true);
+    horde.add_user(
+        "worker-key",
+        UserDetails {
+            username: Some("worker#1".to_string()),
+            kudos: Some(0.0),
+            ..Default::default()
+        },
+    );
+    horde.fail_next(
+        "/generate/pop",
+        403,
+        RequestErrorCode::WorkerMaintenance,
+        "Worker in maintenance",
+    );
+    let ok = client
+        .generate_async(GenerationInputStable {
+            prompt: "A photo of a cat".to_string(),
+            params: Some(ModelGenerationInputStable {
+                n: Some(2),
+                seed: Some("7".to_string()),
+                seed_variation: Some(1),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .id
+        .unwrap();
+    let failing = client
+        .generate_async(GenerationInputStable {
+            prompt: "fail".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .id
+        .unwrap();
+    let panicking = client
+        .generate_async(GenerationInputStable {
+            prompt: "panic".to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .id
+        .unwrap();
+
+    let worker = HordeWorker::new(
+        horde.client_with_key("worker-key"),
+        FakeBackend,
+        PopInputStable {
+            name: "Mock Worker".to_string(),
+            ..Default::default()
+        },
+    )
+    .with_config(WorkerConfig {
+        concurrency: 2,
+        pop_interval: Duration::from_millis(10),
+        maintenance_interval: Duration::from_millis(10),
+    })
+    .start();
+    for _ in 0..200 {
+        let stats = worker.stats();
+        if stats.jobs_completed + stats.jobs_faulted >= 4 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let stats = worker.shutdown().await.unwrap();
+    assert_eq!(stats.jobs_completed, 2);
+    assert_eq!(stats.jobs_faulted, 2);
+    assert_eq!(stats.maintenance_pauses, 1);
+    assert_eq!(stats.kudos_earned, 40.0);
+
+    let status = client.generation_status(ok).await.unwrap();
+    assert_eq!(status.done, Some(true));
+    let images = client.download_generations(&status).await.unwrap();
+    assert_eq!(images.len(), 2);
+    assert_eq!(
+        images[0].bytes,
+        STANDARD.decode(test_webp_base64()).unwrap()
+    );
+    let seeds: Vec<_> = images
+        .iter()
+        .map(|image| image.generation.seed.clone().unwrap())
+        .collect();
+    assert_eq!(seeds, vec!["7", "8"]);
+    for id in [failing, panicking] {
+        let status = client.generation_status(id).await.unwrap();
+        assert_eq!(
+            status.generations.unwrap()[0].state,
+            GenerationState::Faulted
+        );
     }
-}
\ No newline at end of file
+}
+
+#[test]
+async fn test_upload_r2() {
+    let (horde, client) = mock_client().await;
+    let image = STANDARD.decode(test_webp_base64()).unwrap();
+    let upload_url = format!("{}/r2/upload-test/0.webp", horde.base_url());
+
+    horde.fail_next("/r2/", 503, RequestErrorCode::Unknown, "Slow down");
+    client.upload_r2(&upload_url, image.clone()).await.unwrap();
+    assert_eq!(horde.upload(&upload_url), Some(image.clone()));
+    let puts = |horde: &MockHorde| {
+        horde
+            .calls()
+            .iter()
+            .filter(|call| call.starts_with("PUT"))
+            .count()
+    };
+    assert_eq!(puts(&horde), 2);
+
+    // Client errors are not retried
+    horde.fail_next("/r2/", 403, RequestErrorCode::Forbidden, "Expired link");
+    match client.upload_r2(&upload_url, image.clone()).await {
+        Err(AihordeError::UnexpectedHTTPCode { code, .. }) => assert_eq!(code, 403),
+        other => panic!("Expected a forbidden upload, got {other:?}"),
+    }
+    assert_eq!(puts(&horde), 3);
+
+    for _ in 0..3 {
+        horde.fail_next("/r2/", 500, RequestErrorCode::Unknown, "Internal error");
+    }
+    match client.upload_r2(&upload_url, image.clone()).await {
+        Err(AihordeError::UnexpectedHTTPCode { code, .. }) => assert_eq!(code, 500),
+        other => panic!("Expected the retries to run out, got {other:?}"),
+    }
+    assert_eq!(puts(&horde), 6);
+
+    // The stand-in only accepts the content type the link was signed for
+    let response = reqwest::Client::new()
+        .put(&upload_url)
+        .body(image)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[test]
+async fn test_key_pool() {
+    let (horde, _) = mock_client().await;
+    horde.configure(|config| config.strict_api_keys = true);
+    horde.add_user(
+        "rich-key",
+        UserDetails {
+            username: Some("rich#1".to_string()),
+            kudos: Some(500.0),
+            concurrency: Some(1),
+            ..Default::default()
+        },
+    );
+    horde.add_user(
+        "poor-key",
+        UserDetails {
+            username: Some("poor#2".to_string()),
+            kudos: Some(100.0),
+            concurrency: Some(5),
+            ..Default::default()
+        },
+    );
+    let keys = ["revoked-key", "poor-key", "rich-key"].map(String::from);
+    let pool = KeyPool::new(horde.client(), keys.clone());
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        ..Default::default()
+    };
+
+    // The richest key is picked and the revoked one is disabled
+    let client = pool.client().await.unwrap();
+    assert_eq!(client.api_key(), "rich-key");
+    assert_eq!(
+        pool.keys()[0].state,
+        KeyState::Disabled(RequestErrorCode::InvalidAPIKey)
+    );
+
+    // The rich key reaches its concurrency, so the next request goes to the poor key
+    pool.generate_async(input.clone()).await.unwrap();
+    pool.generate_async(input.clone()).await.unwrap();
+    assert_eq!(horde.user("rich-key").unwrap().kudos, Some(490.0));
+    assert_eq!(horde.user("poor-key").unwrap().kudos, Some(90.0));
+
+    // Fail over when a key lacks the kudos
+    horde.add_user(
+        "spare-key",
+        UserDetails {
+            username: Some("spare#3".to_string()),
+            kudos: Some(50.0),
+            concurrency: Some(5),
+            ..Default::default()
+        },
+    );
+    let keys = ["poor-key", "rich-key", "spare-key"].map(String::from);
+    let pool = KeyPool::new(horde.client(), keys).with_selection(KeySelection::FewestActive);
+    horde.fail_next(
+        "/generate/async",
+        403,
+        RequestErrorCode::KudosUpfront,
+        "Not enough kudos",
+    );
+    pool.generate_async(input.clone()).await.unwrap();
+    assert_eq!(horde.user("poor-key").unwrap().kudos, Some(80.0));
+    assert_eq!(
+        pool.keys()[2].state,
+        KeyState::Exhausted(RequestErrorCode::KudosUpfront)
+    );
+    horde.fail_next(
+        "/generate/async",
+        403,
+        RequestErrorCode::KudosUpfront,
+        "Not enough kudos",
+    );
+    match pool.generate_async(input).await {
+        Err(AihordeError::NoApiKeyAvailable) => {}
+        other => panic!("Expected the pool to run out of keys, got {other:?}"),
+    }
+}
+
+#[test]
+async fn test_generate_and_wait() {
+    let (horde, client) = mock_client().await;
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        ..Default::default()
+    };
+    let status = client
+        .generate_and_wait(input.clone(), Duration::from_millis(10))
+        .await
+        .unwrap();
+    assert_eq!(status.done, Some(true));
+    assert_eq!(status.generations.unwrap().len(), 1);
+
+    horde.fault_next_requests(1);
+    match client
+        .generate_and_wait(input, Duration::from_millis(10))
+        .await
+    {
+        Err(AihordeError::RequestFaulted { .. }) => {}
+        other => panic!("Expected a faulted request, got {other:?}"),
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[derive(Debug, Clone)]
+struct RecordedSpan {
+    name: &'static str,
+    parent: Option<u64>,
+    fields: HashMap<String, String>,
+}
+
+#[cfg(feature = "tracing")]
+/// Keeps every span, for a single-threaded runtime.
+#[derive(Default)]
+struct SpanRecorder {
+    spans: Arc<Mutex<Vec<RecordedSpan>>>,
+    metadata: Mutex<Vec<&'static tracing::Metadata<'static>>>,
+    stack: Mutex<Vec<u64>>,
+}
+
+#[cfg(feature = "tracing")]
+struct FieldRecorder<'a>(&'a mut HashMap<String, String>);
+
+#[cfg(feature = "tracing")]
+impl tracing::field::Visit for FieldRecorder<'_> {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl tracing::Subscriber for SpanRecorder {
+    fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attributes: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let parent = match attributes.parent() {
+            Some(parent) => Some(parent.into_u64()),
+            None if attributes.is_contextual() => self.stack.lock().unwrap().last().copied(),
+            None => None,
+        };
+        let mut fields = HashMap::new();
+        attributes.record(&mut FieldRecorder(&mut fields));
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(RecordedSpan {
+            name: attributes.metadata().name(),
+            parent,
+            fields,
+        });
+        self.metadata.lock().unwrap().push(attributes.metadata());
+        tracing::span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+        let mut spans = self.spans.lock().unwrap();
+        values.record(&mut FieldRecorder(
+            &mut spans[span.into_u64() as usize - 1].fields,
+        ));
+    }
+
+    fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+    fn event(&self, _event: &tracing::Event<'_>) {}
+
+    fn enter(&self, span: &tracing::span::Id) {
+        self.stack.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, _span: &tracing::span::Id) {
+        self.stack.lock().unwrap().pop();
+    }
+
+    fn current_span(&self) -> tracing_core::span::Current {
+        match self.stack.lock().unwrap().last() {
+            Some(id) => tracing_core::span::Current::new(
+                tracing::span::Id::from_u64(*id),
+                self.metadata.lock().unwrap()[*id as usize - 1],
+            ),
+            None => tracing_core::span::Current::none(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+async fn test_tracing_spans() {
+    let (horde, _) = mock_client().await;
+    horde.add_user(
+        "secret-key",
+        UserDetails {
+            username: Some("traced#4".to_string()),
+            kudos: Some(100.0),
+            ..Default::default()
+        },
+    );
+    let client = horde.client_with_key("secret-key");
+    let recorder = SpanRecorder::default();
+    let spans = recorder.spans.clone();
+    let _guard = tracing::subscriber::set_default(recorder);
+
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        models: Some(vec!["stable_diffusion".to_string()]),
+        ..Default::default()
+    };
+    let status = client
+        .generate_and_wait(input, Duration::from_millis(10))
+        .await
+        .unwrap();
+
+    let spans = spans.lock().unwrap().clone();
+    let (index, wait) = spans
+        .iter()
+        .enumerate()
+        .find(|(_, span)| span.name == "generate_and_wait")
+        .unwrap();
+    let wait_id = index as u64 + 1;
+    let request_id = &wait.fields["request_id"];
+    assert_eq!(
+        wait.fields["kudos"],
+        format!("{:?}", status.kudos.unwrap() as f64)
+    );
+    assert_eq!(wait.fields["model"], r#"Some(["stable_diffusion"])"#);
+
+    let children: Vec<&RecordedSpan> = spans
+        .iter()
+        .filter(|span| span.parent == Some(wait_id))
+        .collect();
+    let submit = children
+        .iter()
+        .find(|span| span.name == "generate_async")
+        .unwrap();
+    assert_eq!(submit.fields["endpoint"], "/generate/async");
+    assert_eq!(&submit.fields["request_id"], request_id);
+    let polls: Vec<&&RecordedSpan> = children
+        .iter()
+        .filter(|span| span.name == "generation_check")
+        .collect();
+    assert_eq!(wait.fields["polls"], polls.len().to_string());
+    assert!(
+        polls
+            .iter()
+            .all(|span| &span.fields["request_id"] == request_id)
+    );
+    assert_eq!(polls.last().unwrap().fields["done"], "true");
+    assert!(children.iter().any(|span| span.name == "generation_status"));
+
+    // API keys never end up in spans
+    assert!(
+        spans
+            .iter()
+            .flat_map(|span| span.fields.values())
+            .all(|value| !value.contains("secret-key"))
+    );
+}
+
+#[cfg(feature = "metrics")]
+/// Keeps every metric, by `name{label=value,...}`.
+#[derive(Default)]
+struct MetricsRecorder {
+    counters: Mutex<HashMap<String, Arc<std::sync::atomic::AtomicU64>>>,
+    histograms: Mutex<HashMap<String, Arc<RecordedHistogram>>>,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct RecordedHistogram(Mutex<Vec<f64>>);
+
+#[cfg(feature = "metrics")]
+impl metrics::HistogramFn for RecordedHistogram {
+    fn record(&self, value: f64) {
+        self.0.lock().unwrap().push(value);
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn metric_key(key: &metrics::Key) -> String {
+    let mut labels: Vec<String> = key
+        .labels()
+        .map(|label| format!("{}={}", label.key(), label.value()))
+        .collect();
+    labels.sort();
+    format!("{}{{{}}}", key.name(), labels.join(","))
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsRecorder {
+    fn counter(&self, key: &str) -> u64 {
+        self.counters.lock().unwrap().get(key).map_or(0, |counter| {
+            counter.load(std::sync::atomic::Ordering::Relaxed)
+        })
+    }
+
+    fn histogram(&self, key: &str) -> Vec<f64> {
+        self.histograms
+            .lock()
+            .unwrap()
+            .get(key)
+            .map_or(Vec::new(), |histogram| histogram.0.lock().unwrap().clone())
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl metrics::Recorder for MetricsRecorder {
+    fn describe_counter(
+        &self,
+        _key: metrics::KeyName,
+        _unit: Option<metrics::Unit>,
+        _description: metrics::SharedString,
+    ) {
+    }
+
+    fn describe_gauge(
+        &self,
+        _key: metrics::KeyName,
+        _unit: Option<metrics::Unit>,
+        _description: metrics::SharedString,
+    ) {
+    }
+
+    fn describe_histogram(
+        &self,
+        _key: metrics::KeyName,
+        _unit: Option<metrics::Unit>,
+        _description: metrics::SharedString,
+    ) {
+    }
+
+    fn register_counter(
+        &self,
+        key: &metrics::Key,
+        _metadata: &metrics::Metadata<'_>,
+    ) -> metrics::Counter {
+        let counter = self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(metric_key(key))
+            .or_default()
+            .clone();
+        metrics::Counter::from_arc(counter)
+    }
+
+    fn register_gauge(
+        &self,
+        _key: &metrics::Key,
+        _metadata: &metrics::Metadata<'_>,
+    ) -> metrics::Gauge {
+        metrics::Gauge::noop()
+    }
+
+    fn register_histogram(
+        &self,
+        key: &metrics::Key,
+        _metadata: &metrics::Metadata<'_>,
+    ) -> metrics::Histogram {
+        let histogram = self
+            .histograms
+            .lock()
+            .unwrap()
+            .entry(metric_key(key))
+            .or_default()
+            .clone();
+        metrics::Histogram::from_arc(histogram)
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+async fn test_metrics() {
+    let (horde, client) = mock_client().await;
+    horde.configure(|config| config.checks_until_done = 2);
+    let recorder = MetricsRecorder::default();
+    let _guard = metrics::set_default_local_recorder(&recorder);
+    crate::metrics::describe();
+
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        ..Default::default()
+    };
+    client
+        .generate_and_wait(input, Duration::from_millis(10))
+        .await
+        .unwrap();
+    match client.generation_status("missing".to_string()).await {
+        Err(AihordeError::ApiError { .. }) => {}
+        other => panic!("Expected an API error, got {other:?}"),
+    }
+
+    assert_eq!(
+        recorder.counter("aihorde_requests_total{endpoint=/generate/async,method=POST,status=202}"),
+        1
+    );
+    assert_eq!(
+        recorder.counter(
+            "aihorde_requests_total{endpoint=/generate/status/{id},method=GET,status=404}"
+        ),
+        1
+    );
+    assert_eq!(
+        recorder
+            .counter("aihorde_errors_total{code=RequestNotFound,endpoint=/generate/status/{id}}"),
+        1
+    );
+    assert_eq!(
+        recorder
+            .histogram("aihorde_request_duration_seconds{endpoint=/generate/async,method=POST}")
+            .len(),
+        1
+    );
+    let checks = recorder
+        .counter("aihorde_requests_total{endpoint=/generate/check/{id},method=GET,status=200}");
+    assert_eq!(checks, 2);
+    assert_eq!(
+        recorder.histogram("aihorde_queue_wait_seconds{}").len(),
+        checks as usize
+    );
+    let kudos = recorder.histogram("aihorde_kudos_spent{}");
+    assert_eq!(kudos.len(), 1);
+    assert!(kudos[0] > 0.0);
+    // Estimates are not spending
+    let estimate = client
+        .generate_async(GenerationInputStable {
+            prompt: "A photo of a cat".to_string(),
+            dry_run: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert!(estimate.kudos.unwrap() > 0.0);
+    assert_eq!(recorder.histogram("aihorde_kudos_spent{}").len(), 1);
+    assert_eq!(
+        recorder
+            .histogram("aihorde_generation_duration_seconds{outcome=done}")
+            .len(),
+        1
+    );
+}
+
+#[test]
+async fn test_job_queue() {
+    let (horde, _) = mock_client().await;
+    horde.add_user(
+        "bulk-key",
+        UserDetails {
+            username: Some("bulk#1".to_string()),
+            kudos: Some(1000.0),
+            concurrency: Some(2),
+            ..Default::default()
+        },
+    );
+    horde.fault_next_requests(1);
+    let inputs: Vec<GenerationInputStable> = (0..5)
+        .map(|i| GenerationInputStable {
+            prompt: format!("A photo of cat number {i}"),
+            ..Default::default()
+        })
+        .collect();
+    let queue = JobQueue::new(horde.client_with_key("bulk-key"))
+        .with_poll_interval(Duration::from_millis(10), Duration::from_millis(20));
+    let jobs: Vec<_> = queue.run(inputs).collect().await;
+
+    assert_eq!(jobs.len(), 5);
+    let mut indices: Vec<usize> = jobs.iter().map(|job| job.index).collect();
+    indices.sort();
+    assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+    for job in &jobs {
+        let status = job.result.as_ref().unwrap();
+        assert_eq!(status.done, Some(true));
+        assert_eq!(job.attempts, if job.index == 0 { 2 } else { 1 });
+    }
+    // The concurrency of the user is never exceeded, so nothing is rejected
+    let submissions = horde
+        .calls()
+        .iter()
+        .filter(|call| call.ends_with("/generate/async"))
+        .count();
+    assert_eq!(submissions, 6);
+    assert_eq!(horde.user("bulk-key").unwrap().kudos, Some(940.0));
+}
+
+#[test]
+async fn test_job_queue_retries_failed_checks() {
+    let (horde, _) = mock_client().await;
+    horde.add_user(
+        "retry-key",
+        UserDetails {
+            username: Some("retry#1".to_string()),
+            kudos: Some(1000.0),
+            // Requests given up on keep running on the horde
+            concurrency: Some(3),
+            ..Default::default()
+        },
+    );
+    let queue = JobQueue::new(horde.client_with_key("retry-key"))
+        .with_poll_interval(Duration::from_millis(10), Duration::from_millis(20))
+        .with_max_check_failures(2);
+    let input = GenerationInputStable {
+        prompt: "A photo of a patient cat".to_string(),
+        ..Default::default()
+    };
+
+    // Transient errors keep the request running
+    horde.fail_next(
+        "/generate/check/",
+        503,
+        RequestErrorCode::Unknown,
+        "Bad gateway",
+    );
+    horde.fail_next(
+        "/generate/status/",
+        429,
+        RequestErrorCode::Unknown,
+        "Slow down",
+    );
+    let jobs: Vec<_> = queue.clone().run([input.clone()]).collect().await;
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].result.as_ref().unwrap().done, Some(true));
+    assert_eq!(jobs[0].attempts, 1);
+    let submissions = horde
+        .calls()
+        .iter()
+        .filter(|call| call.ends_with("/generate/async"))
+        .count();
+    assert_eq!(submissions, 1);
+
+    // Unknown requests are not retried
+    horde.fail_next(
+        "/generate/check/",
+        404,
+        RequestErrorCode::RequestNotFound,
+        "Request not found",
+    );
+    let jobs: Vec<_> = queue.clone().run([input.clone()]).collect().await;
+    assert!(matches!(
+        jobs[0].result,
+        Err(AihordeError::ApiError {
+            code: RequestErrorCode::RequestNotFound,
+            ..
+        })
+    ));
+
+    // Neither are requests failing too many checks in a row
+    for _ in 0..2 {
+        horde.fail_next("/generate/check/", 500, RequestErrorCode::Unknown, "Oops");
+    }
+    let jobs: Vec<_> = queue.run([input]).collect().await;
+    assert!(matches!(
+        jobs[0].result,
+        Err(AihordeError::ApiError {
+            code: RequestErrorCode::Unknown,
+            ..
+        })
+    ));
+}
+
+#[cfg(feature = "budget")]
+#[test]
+async fn test_budget_guard() {
+    let (horde, client) = mock_client().await;
+    let path = std::env::temp_dir().join(format!("aihorde-rs-ledger-{}.jsonl", std::process::id()));
+    let csv = path.with_extension("csv");
+    let _ = std::fs::remove_file(&path);
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        models: Some(vec!["stable_diffusion".to_string()]),
+        ..Default::default()
+    };
+
+    // Every image costs 10 kudos on the mock
+    let guard = BudgetGuard::open(client.clone(), &path)
+        .await
+        .unwrap()
+        .with_budget("team-a", Budget::default().with_daily(25.0))
+        .with_default_budget(Budget::default().with_monthly(1000.0));
+    let first = guard.generate_async("team-a", input.clone()).await.unwrap();
+    guard.generate_async("team-a", input.clone()).await.unwrap();
+    match guard.generate_async("team-a", input.clone()).await {
+        Err(AihordeError::BudgetExceeded {
+            period,
+            spent,
+            limit,
+            estimate,
+            ..
+        }) => {
+            assert_eq!(
+                (period.as_str(), spent, limit, estimate),
+                ("daily", 20.0, 25.0, 10.0)
+            );
+        }
+        other => panic!("Expected the budget to be exceeded, got {other:?}"),
+    }
+    guard.generate_async("team-b", input.clone()).await.unwrap();
+    let submissions = horde
+        .calls()
+        .iter()
+        .filter(|call| call.ends_with("/generate/async"))
+        .count();
+    // One dry run for every estimate
+    assert_eq!(submissions, 7);
+
+    let id = first.id.unwrap();
+    let status = loop {
+        let status = guard.generation_status(id.clone()).await.unwrap();
+        if status.done == Some(true) {
+            break status;
+        }
+    };
+    drop(guard);
+
+    // Restart
+    let guard = BudgetGuard::open(client, &path)
+        .await
+        .unwrap()
+        .with_estimate(CostEstimate::Local);
+    let entries = guard.entries().await;
+    assert_eq!(entries.len(), 3);
+    let finished = entries.iter().find(|entry| entry.request_id == id).unwrap();
+    assert_eq!(finished.final_kudos, status.kudos.map(f64::from));
+    assert_eq!(finished.model, "stable_diffusion");
+    let (daily, monthly) = guard.spent("team-a", chrono::Utc::now()).await;
+    assert_eq!((daily, monthly), (20.0, 20.0));
+    assert_eq!(
+        guard.estimate(&input).await.unwrap(),
+        estimate_kudos(&input)
+    );
+
+    let report = guard.report().await;
+    assert_eq!(report.len(), 2);
+    assert_eq!(
+        (
+            report[0].caller.as_str(),
+            report[0].requests,
+            report[0].kudos
+        ),
+        ("team-a", 2, 20.0)
+    );
+    assert_eq!(
+        (
+            report[1].caller.as_str(),
+            report[1].requests,
+            report[1].kudos
+        ),
+        ("team-b", 1, 10.0)
+    );
+    guard.export_csv(&csv).await.unwrap();
+    let today = chrono::Utc::now().date_naive();
+    assert_eq!(
+        std::fs::read_to_string(&csv).unwrap(),
+        format!(
+            "caller,model,day,requests,kudos\nteam-a,stable_diffusion,{today},2,20\nteam-b,stable_diffusion,{today},1,10\n"
+        )
+    );
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(&csv);
+}
+
+#[cfg(feature = "budget")]
+#[test]
+async fn test_estimate_kudos() {
+    let mut input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        ..Default::default()
+    };
+    let base = estimate_kudos(&input);
+    assert!(base > 0.0);
+    input.params = Some(ModelGenerationInputStable {
+        n: Some(2),
+        ..Default::default()
+    });
+    assert_eq!(estimate_kudos(&input), base * 2.0);
+    input.params = Some(ModelGenerationInputStable {
+        width: Some(1024),
+        height: Some(1024),
+        steps: Some(30),
+        ..Default::default()
+    });
+    assert!(estimate_kudos(&input) > base * 4.0);
+}
+
+#[cfg(feature = "journal")]
+#[test]
+async fn test_journal_resume() {
+    let (_horde, client) = mock_client().await;
+    let path =
+        std::env::temp_dir().join(format!("aihorde-rs-journal-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        ..Default::default()
+    };
+
+    let journal = Journal::open(&path).await.unwrap();
+    let request = journal
+        .generate_async(&client, input.clone())
+        .await
+        .unwrap();
+    journal
+        .record_submitted("00000000-0000-4000-8000-999999999999", &input, Some(10.0))
+        .await
+        .unwrap();
+    drop(journal);
+    // A request submitted long ago by a previous run, and a line truncated by a crash
+    let old = serde_json::json!({
+        "request_id": "00000000-0000-4000-8000-000000000000",
+        "input": input,
+        "submitted_at": "2020-01-01T00:00:00Z",
+        "state": "pending",
+    });
+    let mut content = std::fs::read_to_string(&path).unwrap();
+    content.push_str(&format!("{old}\n{{\"request_id\": \"000"));
+    std::fs::write(&path, content).unwrap();
+
+    // Restart
+    let journal = Journal::open(&path).await.unwrap();
+    assert_eq!(journal.pending().await.len(), 3);
+    let resumed = journal.resume(&client, Duration::from_millis(10)).await;
+    assert_eq!(resumed.len(), 3);
+    for resumed in &resumed {
+        if Some(&resumed.entry.request_id) == request.id.as_ref() {
+            assert_eq!(resumed.result.as_ref().unwrap().done, Some(true));
+        } else {
+            match &resumed.result {
+                Err(AihordeError::ApiError { code, .. }) => {
+                    assert_eq!(*code, RequestErrorCode::RequestExpired)
+                }
+                other => panic!("Expected an expired request, got {other:?}"),
+            }
+        }
+    }
+    assert!(journal.pending().await.is_empty());
+
+    journal.compact().await.unwrap();
+    drop(journal);
+    let journal = Journal::open(&path).await.unwrap();
+    assert!(journal.entries().await.is_empty());
+
+    // The job queue journals its requests too
+    let queue = JobQueue::new(client)
+        .with_journal(std::sync::Arc::new(journal))
+        .with_poll_interval(Duration::from_millis(10), Duration::from_millis(20));
+    let jobs: Vec<_> = queue.run(vec![input]).collect().await;
+    let journal = Journal::open(&path).await.unwrap();
+    let entries = journal.entries().await;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].state, JournalState::Done);
+    assert_eq!(jobs[0].request_id.as_ref(), Some(&entries[0].request_id));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(feature = "blocking")]
+#[test]
+async fn test_blocking_client() {
+    let (horde, client) = mock_client().await;
+    let expected_user = horde.client().find_user().await.unwrap();
+    let status = tokio::task::spawn_blocking(move || {
+        let client = crate::blocking::AihordeClient::from_async(client).unwrap();
+        let user = client.find_user().unwrap();
+        assert_eq!(user.username, expected_user.username);
+
+        let input = GenerationInputStable {
+            prompt: "A photo of a cat".to_string(),
+            ..Default::default()
+        };
+        let status = client
+            .generate_and_wait(input, Duration::from_millis(10))
+            .unwrap();
+        let images = client.download_generations(&status).unwrap();
+        assert_eq!(images.len(), 1);
+
+        let users: Vec<UserDetails> = client
+            .users_stream(UserSort::Kudos)
+            .with_max_pages(1)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(users, client.get_users_sorted(1, UserSort::Kudos).unwrap());
+        status
+    })
+    .await
+    .unwrap();
+    assert_eq!(status.done, Some(true));
+}
+
+#[test]
+async fn test_batch_resume() {
+    let (horde, client) = mock_client().await;
+    let dir = std::env::temp_dir().join(format!("aihorde-rs-batch-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let inputs = batch::parse_jsonl(concat!(
+        r#"{"prompt": "A photo of a cat"}"#,
+        "\n",
+        "\n",
+        r#"{"prompt": "A photo of a dog", "params": {"n": 2}}"#,
+        "\n",
+        r#"{"prompt": "A photo of a cat"}"#,
+        "\n",
+    ))
+    .unwrap();
+    assert_eq!(inputs.len(), 3);
+    let batch = Batch::new(client, &dir)
+        .with_concurrency(2)
+        .with_max_attempts(1)
+        .with_poll_interval(Duration::from_millis(10), Duration::from_millis(20));
+
+    horde.fault_next_requests(1);
+    let report = batch.run(inputs.clone()).await.unwrap();
+    assert_eq!(report.skipped, 0);
+    assert_eq!(report.completed, 2);
+    assert_eq!(report.failed.len(), 1);
+    assert_eq!(report.failed[0].index, 0);
+
+    // Only the failed input is generated again, and identical inputs are kept apart
+    let report = batch.run(inputs.clone()).await.unwrap();
+    assert_eq!((report.skipped, report.completed), (2, 1));
+    assert!(report.failed.is_empty());
+    let manifest = batch.manifest().await.unwrap();
+    assert_eq!(manifest.len(), 4);
+    let keys: std::collections::HashSet<&str> =
+        manifest.iter().map(|entry| entry.key.as_str()).collect();
+    assert_eq!(keys.len(), 3);
+    for entry in &manifest {
+        assert!(dir.join(&entry.path).exists());
+        assert!(entry.seed.is_some());
+        assert!(entry.model.is_some());
+    }
+
+    let submissions = horde
+        .calls()
+        .iter()
+        .filter(|call| call.ends_with("/generate/async"))
+        .count();
+    let report = batch.run(inputs).await.unwrap();
+    assert_eq!((report.skipped, report.completed), (3, 0));
+    assert_eq!(
+        horde
+            .calls()
+            .iter()
+            .filter(|call| call.ends_with("/generate/async"))
+            .count(),
+        submissions
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+async fn test_batch_input_keys() {
+    let input = |names: Vec<String>| GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        params: Some(ModelGenerationInputStable {
+            special: Some(
+                names
+                    .into_iter()
+                    .map(|name| (name, serde_json::Map::new()))
+                    .collect(),
+            ),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    // Maps built separately iterate in different orders
+    let names: Vec<String> = (0..16).map(|i| format!("key{i}")).collect();
+    let inputs = vec![
+        input(names.clone()),
+        input(names.iter().rev().cloned().collect()),
+        input(names[..1].to_vec()),
+    ];
+    let keys = batch::input_keys(&inputs).unwrap();
+    assert_eq!(keys[0].len(), 32);
+    assert_eq!(keys[1], format!("{}-2", keys[0]));
+    assert_ne!(keys[2], keys[0]);
+    // Keys do not depend on where an input is in the file
+    assert_eq!(batch::input_keys(&inputs[1..]).unwrap()[0], keys[0]);
+}
+
+#[test]
+async fn test_batch_parse_csv() {
+    let inputs = batch::parse_csv(concat!(
+        "prompt,negative_prompt,models,steps,seed\r\n",
+        "\"A cat, sitting\",blurry,Deliberate|stable_diffusion,20,42\r\n",
+        "\"A \"\"quoted\"\"\ndog\",,,,\n",
+    ))
+    .unwrap();
+    assert_eq!(inputs.len(), 2);
+    assert_eq!(inputs[0].prompt, "A cat, sitting ### blurry");
+    assert_eq!(
+        inputs[0].models,
+        Some(vec![
+            "Deliberate".to_string(),
+            "stable_diffusion".to_string()
+        ])
+    );
+    let params = inputs[0].params.as_ref().unwrap();
+    assert_eq!(
+        (params.steps, params.seed.as_deref()),
+        (Some(20), Some("42"))
+    );
+    assert_eq!(inputs[1].prompt, "A \"quoted\"\ndog");
+    assert_eq!(inputs[1].params, None);
+
+    assert!(matches!(
+        batch::parse_csv("prompt,colour\ncat,red\n"),
+        Err(AihordeError::InvalidInput(_))
+    ));
+    assert!(matches!(
+        batch::parse_csv("prompt,steps\ncat,many\n"),
+        Err(AihordeError::InvalidInput(_))
+    ));
+}
+
+#[test]
+async fn test_sweep_contact_sheet() {
+    let (_horde, client) = mock_client().await;
+    let base = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        ..Default::default()
+    };
+    let sweep = Sweep::new(
+        client.clone(),
+        base.clone(),
+        SweepAxis::CfgScale(vec![5.0, 7.5, 9.0]),
+    )
+    .with_y(SweepAxis::Sampler(vec![
+        SamplerName::KEuler,
+        SamplerName::KEulerA,
+    ]))
+    .with_concurrency(4)
+    .with_poll_interval(Duration::from_millis(10), Duration::from_millis(20));
+    let result = sweep.run().await.unwrap();
+    assert_eq!(result.cells.len(), 6);
+    let seeds: Vec<_> = result
+        .cells
+        .iter()
+        .map(|cell| cell.input.params.as_ref().unwrap().seed.clone())
+        .collect();
+    assert!(seeds[0].is_some() && seeds.iter().all(|seed| *seed == seeds[0]));
+    let cell = result.cell(1, 1, 0).unwrap();
+    let params = cell.input.params.as_ref().unwrap();
+    assert_eq!(
+        (params.cfg_scale, params.sampler_name.clone(), params.n),
+        (Some(7.5), Some(SamplerName::KEulerA), Some(1))
+    );
+
+    let image = cell.result.as_ref().unwrap().decode().unwrap();
+    let sheet = result.contact_sheet(0).unwrap();
+    assert!(sheet.width() > 3 * image.width() && sheet.height() > 2 * image.height());
+    // The title and labels are drawn in black
+    assert!(sheet.pixels().any(|pixel| pixel.0 == [0, 0, 0]));
+    assert!(matches!(
+        result.contact_sheet(1),
+        Err(AihordeError::InvalidInput(_))
+    ));
+
+    let sweep = Sweep::new(
+        client,
+        base,
+        SweepAxis::PromptReplace(vec!["dog".to_string(), "fox".to_string()]),
+    );
+    assert!(matches!(sweep.inputs(), Err(AihordeError::InvalidInput(_))));
+}
+
+#[test]
+async fn test_seed_to_int() {
+    assert_eq!(Seed::parse("42"), Seed::Number(42));
+    assert_eq!(Seed::parse("cat"), Seed::Text("cat".to_string()));
+    assert_eq!(Seed::from(42u64).to_int(), 42);
+    assert_eq!(Seed::from(u32::MAX as u64 + 1).to_int(), 1);
+    assert_eq!(Seed::parse("cat").to_int(), u32::from_le_bytes(*b"cat\0"));
+    // Read as a little-endian number, then shifted right by 32 bits until it fits
+    assert_eq!(
+        Seed::parse("kittens").to_int(),
+        u32::from_le_bytes(*b"ens\0")
+    );
+    assert_eq!(Seed::parse("123456789012345678901234567890").to_int(), 1);
+    assert_eq!(
+        Seed::parse("123456789012345678901234567890").to_string(),
+        "123456789012345678901234567890"
+    );
+
+    assert_eq!(Seed::from(10u64).image_seeds(3, Some(5)), vec![10, 15, 20]);
+    assert_eq!(Seed::from(10u64).image_seeds(3, None), vec![10, 10, 10]);
+    assert_eq!(
+        Seed::from(u32::MAX).image_seeds(2, Some(1)),
+        vec![u32::MAX, 1]
+    );
+    let params = ModelGenerationInputStable {
+        seed: Some("".to_string()),
+        n: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(params.image_seeds(), None);
+}
+
+#[test]
+async fn test_regenerate() {
+    let (horde, client) = mock_client().await;
+    let input = GenerationInputStable {
+        prompt: "A photo of a cat".to_string(),
+        params: Some(ModelGenerationInputStable {
+            seed: Some("a cat seed".to_string()),
+            seed_variation: Some(3),
+            n: Some(3),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let predicted = input.params.as_ref().unwrap().image_seeds().unwrap();
+    let status = client
+        .generate_and_wait(input.clone(), Duration::from_millis(10))
+        .await
+        .unwrap();
+    let generations = status.generations.unwrap();
+    let seeds: Vec<u32> = generations
+        .iter()
+        .map(|generation| generation.seed.as_deref().unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(seeds, predicted);
+
+    let again = input.regenerate(&generations[2]);
+    let params = again.params.as_ref().unwrap();
+    assert_eq!((params.n, params.seed_variation), (Some(1), None));
+    assert_eq!(params.seed, generations[2].seed);
+    assert_eq!(
+        again.models,
+        generations[2].model.clone().map(|model| vec![model])
+    );
+    assert_eq!(
+        again.workers,
+        generations[2].worker_id.clone().map(|worker| vec![worker])
+    );
+    let status = horde
+        .client()
+        .generate_and_wait(again, Duration::from_millis(10))
+        .await
+        .unwrap();
+    let images = client.download_generations(&status).await.unwrap();
+    let original = client
+        .generated_image(generations[2].clone())
+        .unwrap()
+        .download()
+        .await
+        .unwrap();
+    assert_eq!(images[0].bytes, original.bytes);
+}
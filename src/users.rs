@@ -0,0 +1,137 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::ready;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+
+use crate::client::AihordeClient;
+use crate::consts::USERS_PER_PAGE;
+use crate::enums::UserSort;
+use crate::errors::AihordeError;
+use crate::models::UserDetails;
+
+type StopCondition = Arc<dyn Fn(&UserDetails) -> bool + Send + Sync>;
+
+/// The users of every page of `get_users_sorted`, in order, as returned by [`AihordeClient::users_stream`].
+///
+/// The stream ends after the last page, after the first error, or when a stop condition is met.
+pub struct UsersStream {
+    client: AihordeClient,
+    sort: UserSort,
+    concurrency: usize,
+    max_pages: Option<u32>,
+    stop_when: Option<StopCondition>,
+    inner: Option<BoxStream<'static, Result<UserDetails, AihordeError>>>,
+}
+
+impl std::fmt::Debug for UsersStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UsersStream")
+            .field("sort", &self.sort)
+            .field("concurrency", &self.concurrency)
+            .field("max_pages", &self.max_pages)
+            .finish()
+    }
+}
+
+impl UsersStream {
+    pub(crate) fn new(client: AihordeClient, sort: UserSort) -> Self {
+        Self {
+            client,
+            sort,
+            concurrency: 1,
+            max_pages: None,
+            stop_when: None,
+            inner: None,
+        }
+    }
+
+    /// ### Fetch several pages at once
+    /// Up to `concurrency - 1` pages past the last one may be requested.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// ### Stop after a number of pages
+    pub fn with_max_pages(mut self, max_pages: u32) -> Self {
+        self.max_pages = Some(max_pages);
+        self
+    }
+
+    /// ### Stop at the first user matching a condition
+    /// That user is not yielded. With `UserSort::Kudos`, this can stop below a kudos threshold.
+    pub fn with_stop_when(
+        mut self,
+        stop_when: impl Fn(&UserDetails) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.stop_when = Some(Arc::new(stop_when));
+        self
+    }
+
+    fn start(&self) -> BoxStream<'static, Result<UserDetails, AihordeError>> {
+        let client = self.client.clone();
+        let sort = self.sort;
+        let last_page = self.max_pages.unwrap_or(u32::MAX);
+        let users = stream::iter(1..=last_page)
+            .map(move |page| {
+                let client = client.clone();
+                async move { client.get_users_sorted(page, sort).await }
+            })
+            .buffered(self.concurrency)
+            .scan(false, |done, page| {
+                if *done {
+                    return ready(None);
+                }
+                let page = match page {
+                    // A short page is the last one
+                    Ok(users) if users.len() < USERS_PER_PAGE => {
+                        *done = true;
+                        Ok(users)
+                    }
+                    Ok(users) => Ok(users),
+                    Err(e) => {
+                        *done = true;
+                        Err(e)
+                    }
+                };
+                ready(Some(page))
+            })
+            .flat_map(|page| match page {
+                Ok(users) => stream::iter(users.into_iter().map(Ok)).left_stream(),
+                Err(e) => stream::once(ready(Err(e))).right_stream(),
+            });
+        match self.stop_when.clone() {
+            Some(stop_when) => users
+                .take_while(move |user| ready(!matches!(user, Ok(user) if stop_when(user))))
+                .boxed(),
+            None => users.boxed(),
+        }
+    }
+}
+
+impl Stream for UsersStream {
+    type Item = Result<UserDetails, AihordeError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.inner.is_none() {
+            this.inner = Some(this.start());
+        }
+        match &mut this.inner {
+            Some(inner) => inner.poll_next_unpin(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl AihordeClient {
+    /// ### Stream the users of all pages
+    /// Pages are fetched one at a time by default. See [`UsersStream`] for concurrency and stop conditions.
+    /// #### Arguments
+    /// * `sort` - How to sort the users.
+    pub fn users_stream(&self, sort: UserSort) -> UsersStream {
+        UsersStream::new(self.clone(), sort)
+    }
+}
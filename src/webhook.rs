@@ -0,0 +1,219 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::post;
+use log::{debug, warn};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::errors::AihordeError;
+use crate::models::{AlchemyWebhookPayload, ImageWebhookPayload, TextWebhookPayload};
+
+type Callback<T> = Arc<dyn Fn(&T) + Send + Sync>;
+
+/// A webhook call of the horde, for any kind of request.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+#[serde(untagged)]
+pub enum WebhookEvent {
+    Image(ImageWebhookPayload),
+    Text(TextWebhookPayload),
+    Alchemy(AlchemyWebhookPayload),
+}
+
+impl WebhookEvent {
+    /// ### Parse the body of a webhook call
+    /// The kind of request is detected from the payload: image generations carry `img`,
+    /// text generations carry `text` and interrogation forms carry `form`.
+    pub fn parse(body: &[u8]) -> Result<Self, AihordeError> {
+        let value: Value = serde_json::from_slice(body)?;
+        let has = |key: &str| value.get(key).is_some();
+        if has("form") {
+            Ok(WebhookEvent::Alchemy(serde_json::from_value(value)?))
+        } else if has("text") {
+            Ok(WebhookEvent::Text(serde_json::from_value(value)?))
+        } else if has("img") {
+            Ok(WebhookEvent::Image(serde_json::from_value(value)?))
+        } else {
+            Err(AihordeError::UnexpectedResponse(
+                "Unknown webhook payload".to_string(),
+            ))
+        }
+    }
+
+    /// The UUID of the request this event belongs to.
+    pub fn request_id(&self) -> &str {
+        match self {
+            WebhookEvent::Image(payload) => &payload.request,
+            WebhookEvent::Text(payload) => &payload.request,
+            WebhookEvent::Alchemy(payload) => &payload.request,
+        }
+    }
+}
+
+/// Dispatches webhook calls of the horde to callbacks and channels.
+///
+/// The handler is independent of the HTTP server: use [`WebhookHandler::handle`] from your own
+/// server, [`WebhookHandler::router`] to mount it into an axum application,
+/// or [`WebhookHandler::serve`] to run the embedded server.
+#[derive(Clone, Default)]
+pub struct WebhookHandler {
+    on_image: Vec<Callback<ImageWebhookPayload>>,
+    on_text: Vec<Callback<TextWebhookPayload>>,
+    on_alchemy: Vec<Callback<AlchemyWebhookPayload>>,
+    senders: Vec<mpsc::UnboundedSender<WebhookEvent>>,
+}
+
+impl WebhookHandler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ### Call `callback` for every delivered image generation
+    pub fn on_image(
+        mut self,
+        callback: impl Fn(&ImageWebhookPayload) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_image.push(Arc::new(callback));
+        self
+    }
+
+    /// ### Call `callback` for every delivered text generation
+    pub fn on_text(
+        mut self,
+        callback: impl Fn(&TextWebhookPayload) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_text.push(Arc::new(callback));
+        self
+    }
+
+    /// ### Call `callback` for every finished interrogation form
+    pub fn on_alchemy(
+        mut self,
+        callback: impl Fn(&AlchemyWebhookPayload) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_alchemy.push(Arc::new(callback));
+        self
+    }
+
+    /// ### Receive every webhook call through a channel
+    pub fn channel(mut self) -> (Self, mpsc::UnboundedReceiver<WebhookEvent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.senders.push(sender);
+        (self, receiver)
+    }
+
+    /// ### Parse and dispatch the body of a webhook call
+    pub fn handle(&self, body: &[u8]) -> Result<WebhookEvent, AihordeError> {
+        let event = WebhookEvent::parse(body)?;
+        debug!("Received webhook for request {}", event.request_id());
+        match &event {
+            WebhookEvent::Image(payload) => {
+                self.on_image.iter().for_each(|callback| callback(payload))
+            }
+            WebhookEvent::Text(payload) => {
+                self.on_text.iter().for_each(|callback| callback(payload))
+            }
+            WebhookEvent::Alchemy(payload) => self
+                .on_alchemy
+                .iter()
+                .for_each(|callback| callback(payload)),
+        }
+        for sender in &self.senders {
+            // A dropped receiver only means nobody is listening anymore
+            let _ = sender.send(event.clone());
+        }
+        Ok(event)
+    }
+
+    /// ### Build an axum router accepting webhook calls on any path
+    pub fn router(self) -> Router {
+        async fn receive(State(handler): State<Arc<WebhookHandler>>, body: Bytes) -> StatusCode {
+            match handler.handle(&body) {
+                Ok(_) => StatusCode::OK,
+                Err(e) => {
+                    warn!("Rejected webhook call: {e}");
+                    StatusCode::BAD_REQUEST
+                }
+            }
+        }
+
+        let state = Arc::new(self);
+        Router::new()
+            .route("/", post(receive))
+            .route("/{*path}", post(receive))
+            .with_state(state)
+    }
+
+    /// ### Start the embedded webhook server
+    /// #### Arguments
+    /// * `addr` - The address to listen on. Use port 0 to pick a free port.
+    pub async fn serve(self, addr: impl ToSocketAddrs) -> Result<WebhookServer, AihordeError> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let router = self.router();
+        let task = tokio::spawn(async move {
+            let result = axum::serve(listener, router)
+                .with_graceful_shutdown(async {
+                    let _ = signal.await;
+                })
+                .await;
+            if let Err(e) = result {
+                warn!("Webhook server stopped: {e}");
+            }
+        });
+        debug!("Webhook server listening on {local_addr}");
+        Ok(WebhookServer {
+            local_addr,
+            shutdown,
+            task,
+        })
+    }
+}
+
+/// A running embedded webhook server.
+pub struct WebhookServer {
+    local_addr: SocketAddr,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl WebhookServer {
+    /// The address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// ### The URL to pass as `webhook` in generation inputs
+    /// Only reachable by the horde if the address is public.
+    pub fn url(&self) -> String {
+        format!("http://{}/", self.local_addr)
+    }
+
+    /// ### Stop the server gracefully
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
+
+/// ### POST a webhook event to `url`, the way the horde does
+/// Useful to test a webhook receiver locally.
+pub async fn post_webhook(url: &str, event: &WebhookEvent) -> Result<(), AihordeError> {
+    let response = reqwest::Client::new().post(url).json(event).send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(AihordeError::UnexpectedHTTPCode {
+            code: status.as_u16(),
+            message: response.text().await.unwrap_or_default(),
+        });
+    }
+    Ok(())
+}
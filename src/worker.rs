@@ -0,0 +1,358 @@
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures::FutureExt;
+use log::{debug, info, warn};
+use tokio::sync::watch;
+use tokio::task::{JoinHandle, JoinSet};
+
+use crate::client::AihordeClient;
+use crate::enums::{GenerationState, RequestErrorCode};
+use crate::errors::AihordeError;
+use crate::models::{
+    GenerationMetadataStable, GenerationPayloadStable, PopInputStable, SubmitInputStable,
+};
+
+/// Generates images for the jobs a [`HordeWorker`] pops from the horde.
+///
+/// Implementations can use `async fn`, as long as the returned future is `Send`.
+pub trait HordeWorkerBackend: Send + Sync + 'static {
+    /// ### Generate the image of a job
+    /// Returning an error or panicking submits the job as faulted.
+    /// #### Arguments
+    /// * `job` - The job as popped from the horde. `job.payload` holds the generation parameters.
+    fn generate(
+        &self,
+        job: &GenerationPayloadStable,
+    ) -> impl Future<Output = Result<WorkerOutput, AihordeError>> + Send;
+}
+
+/// The result of a job, as produced by a [`HordeWorkerBackend`].
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct WorkerOutput {
+    /// The generated .webp file.
+    pub image: Vec<u8>,
+
+    /// The seed which was used for the generation.
+    pub seed: Option<u64>,
+
+    /// Whether the image was replaced by the safety filter.
+    pub censored: bool,
+
+    /// Anything the requester should know about the generation.
+    pub gen_metadata: Vec<GenerationMetadataStable>,
+}
+
+impl WorkerOutput {
+    pub fn new(image: Vec<u8>) -> Self {
+        Self {
+            image,
+            ..Default::default()
+        }
+    }
+}
+
+/// Behaviour of a [`HordeWorker`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct WorkerConfig {
+    /// How many jobs are processed at the same time.
+    pub concurrency: usize,
+
+    /// How long to wait before popping again when no job was available.
+    /// Popping regularly is also what keeps the worker alive on the horde.
+    pub pop_interval: Duration,
+
+    /// How long to wait before popping again while the worker is in maintenance mode.
+    pub maintenance_interval: Duration,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            pop_interval: Duration::from_secs(1),
+            maintenance_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// What a [`HordeWorker`] has done so far.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct WorkerStats {
+    /// Jobs which were generated and submitted.
+    pub jobs_completed: u64,
+
+    /// Jobs which the backend failed and were submitted as faulted.
+    pub jobs_faulted: u64,
+
+    /// Kudos rewarded for the submitted jobs.
+    pub kudos_earned: f64,
+
+    /// How many times popping was refused because the worker is in maintenance mode.
+    pub maintenance_pauses: u64,
+}
+
+/// Pops image jobs from the horde, runs them on a [`HordeWorkerBackend`] and submits the results.
+///
+/// Jobs are popped one at a time for every free slot, so `amount` of the pop input is ignored.
+/// Results are uploaded to the `r2_upload` link of the job when the horde provides one.
+pub struct HordeWorker<B: HordeWorkerBackend> {
+    client: AihordeClient,
+    backend: Arc<B>,
+    pop_input: PopInputStable,
+    config: WorkerConfig,
+}
+
+enum JobResult {
+    Completed(f64),
+    Faulted(f64),
+}
+
+impl<B: HordeWorkerBackend> HordeWorker<B> {
+    /// ### Create a new worker
+    /// #### Arguments
+    /// * `client` - A client using the API key of the user owning the worker.
+    /// * `backend` - The backend generating the images.
+    /// * `pop_input` - The name and capabilities of the worker.
+    pub fn new(client: AihordeClient, backend: B, pop_input: PopInputStable) -> Self {
+        Self {
+            client,
+            backend: Arc::new(backend),
+            pop_input: PopInputStable {
+                amount: Some(1),
+                ..pop_input
+            },
+            config: WorkerConfig::default(),
+        }
+    }
+
+    /// ### Change the behaviour of the worker
+    pub fn with_config(mut self, config: WorkerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// ### Run the worker in the background
+    /// Use the returned handle to watch and stop it.
+    pub fn start(self) -> WorkerHandle {
+        let (shutdown, signal) = watch::channel(false);
+        let stats = Arc::new(Mutex::new(WorkerStats::default()));
+        let task = tokio::spawn(self.run(signal, stats.clone()));
+        WorkerHandle {
+            shutdown,
+            stats,
+            task,
+        }
+    }
+
+    async fn run(
+        self,
+        mut signal: watch::Receiver<bool>,
+        stats: Arc<Mutex<WorkerStats>>,
+    ) -> Result<WorkerStats, AihordeError> {
+        let record = |result: Result<JobResult, tokio::task::JoinError>| {
+            let mut stats = stats
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            match result {
+                Ok(JobResult::Completed(reward)) => {
+                    stats.jobs_completed += 1;
+                    stats.kudos_earned += reward;
+                }
+                Ok(JobResult::Faulted(reward)) => {
+                    stats.jobs_faulted += 1;
+                    stats.kudos_earned += reward;
+                }
+                Err(e) => {
+                    warn!("Worker job panicked: {e}");
+                    stats.jobs_faulted += 1;
+                }
+            }
+        };
+
+        info!("Worker {} started", self.pop_input.name);
+        let mut jobs = JoinSet::new();
+        let mut outcome = Ok(());
+        while !*signal.borrow() {
+            while let Some(result) = jobs.try_join_next() {
+                record(result);
+            }
+            if jobs.len() >= self.config.concurrency.max(1) {
+                tokio::select! {
+                    changed = signal.changed() => if changed.is_err() { break },
+                    Some(result) = jobs.join_next() => record(result),
+                }
+                continue;
+            }
+
+            let delay = match self.client.generate_pop(self.pop_input.clone()).await {
+                Ok(job) if job.id.is_some() => {
+                    debug!("Popped job {}", job.id.as_deref().unwrap_or_default());
+                    jobs.spawn(process(self.client.clone(), self.backend.clone(), job));
+                    continue;
+                }
+                Ok(_) => self.config.pop_interval,
+                Err(AihordeError::ApiError {
+                    code:
+                        code @ (RequestErrorCode::WorkerMaintenance
+                        | RequestErrorCode::WorkerFlaggedMaintenance),
+                    message,
+                }) => {
+                    warn!(
+                        "Worker is in maintenance mode ({code}): {}",
+                        message.as_deref().unwrap_or("No message")
+                    );
+                    stats
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner())
+                        .maintenance_pauses += 1;
+                    self.config.maintenance_interval
+                }
+                Err(
+                    e @ AihordeError::ApiError {
+                        code:
+                            RequestErrorCode::InvalidAPIKey
+                            | RequestErrorCode::AnonForbiddenWorker
+                            | RequestErrorCode::ProfaneWorkerName
+                            | RequestErrorCode::TooLongWorkerName
+                            | RequestErrorCode::WorkerNameAlreadyExists
+                            | RequestErrorCode::WorkerInviteOnly,
+                        ..
+                    },
+                ) => {
+                    warn!("Worker cannot pop jobs: {e}");
+                    outcome = Err(e);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Failed to pop a job: {e}");
+                    self.config.pop_interval
+                }
+            };
+            tokio::select! {
+                changed = signal.changed() => if changed.is_err() { break },
+                _ = tokio::time::sleep(delay) => {}
+            }
+        }
+
+        // Let the running jobs finish so their results are not lost
+        while let Some(result) = jobs.join_next().await {
+            record(result);
+        }
+        info!("Worker {} stopped", self.pop_input.name);
+        outcome?;
+        let stats = stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        Ok(stats)
+    }
+}
+
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(name = "job", skip_all, fields(request_id = ?job.id, model = ?job.model))
+)]
+async fn process<B: HordeWorkerBackend>(
+    client: AihordeClient,
+    backend: Arc<B>,
+    job: GenerationPayloadStable,
+) -> JobResult {
+    let id = job.id.clone().unwrap_or_default();
+    // A panicking backend must not leave the job unsubmitted
+    let output = match AssertUnwindSafe(backend.generate(&job))
+        .catch_unwind()
+        .await
+    {
+        Ok(Ok(output)) => upload(&client, &job, output).await,
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(AihordeError::Other("The backend panicked".to_string())),
+    };
+    let (submit_input, faulted) = match output {
+        Ok((generation, output)) => (
+            SubmitInputStable {
+                id: id.clone(),
+                generation: Some(generation),
+                state: Some(GenerationState::Ok),
+                seed: output.seed,
+                censored: Some(output.censored),
+                gen_metadata: Some(output.gen_metadata),
+            },
+            false,
+        ),
+        Err(e) => {
+            warn!("Job {id} failed: {e}");
+            (
+                SubmitInputStable {
+                    id: id.clone(),
+                    state: Some(GenerationState::Faulted),
+                    ..Default::default()
+                },
+                true,
+            )
+        }
+    };
+    let reward = match client.generate_submit(submit_input).await {
+        Ok(submitted) => submitted.reward.unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to submit job {id}: {e}");
+            return JobResult::Faulted(0.0);
+        }
+    };
+    debug!("Submitted job {id} for {reward} kudos");
+    if faulted {
+        JobResult::Faulted(reward)
+    } else {
+        JobResult::Completed(reward)
+    }
+}
+
+/// Upload the image if the job has an r2 upload link, otherwise encode it for the submission.
+async fn upload(
+    client: &AihordeClient,
+    job: &GenerationPayloadStable,
+    output: WorkerOutput,
+) -> Result<(String, WorkerOutput), AihordeError> {
+    match job.r2_upload.as_deref() {
+        Some(upload_url) => {
+            client.upload_r2(upload_url, output.image.clone()).await?;
+            Ok(("R2".to_string(), output))
+        }
+        None => Ok((STANDARD.encode(&output.image), output)),
+    }
+}
+
+/// A running [`HordeWorker`]. Dropping the handle stops the worker as well.
+pub struct WorkerHandle {
+    shutdown: watch::Sender<bool>,
+    stats: Arc<Mutex<WorkerStats>>,
+    task: JoinHandle<Result<WorkerStats, AihordeError>>,
+}
+
+impl WorkerHandle {
+    /// What the worker has done so far.
+    pub fn stats(&self) -> WorkerStats {
+        self.stats
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// True when the worker stopped on its own, e.g. because its API key was rejected.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// ### Stop the worker gracefully
+    /// No new jobs are popped, and the running jobs are finished and submitted.
+    pub async fn shutdown(self) -> Result<WorkerStats, AihordeError> {
+        let _ = self.shutdown.send(true);
+        self.task
+            .await
+            .map_err(|e| AihordeError::Other(format!("Worker task failed: {e}")))?
+    }
+}